@@ -0,0 +1,204 @@
+//! Evens out skewed label distributions.
+
+use crate::dataset::{self, Sample};
+
+use std::collections::HashMap;
+
+use serde::{Serialize, Deserialize};
+
+/// How a skewed label distribution is evened out when selecting a dataset. In the config:
+/// `"balance": { "strategy": "cap", "max_per_label": 300 }`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "strategy", rename_all = "snake_case")]
+pub enum Balance {
+    #[default]
+    None,
+    /// Keeps at most `max_per_label` samples with each label. Samples are taken rarest label
+    /// first, so common labels are cut first and samples with rare labels survive.
+    Cap { max_per_label: usize },
+    /// Keeps at most `max_per_combination` samples with the exact same set of labels.
+    Undersample { max_per_combination: usize },
+    /// Keeps every sample, giving each the mean inverse frequency of its labels as its weight.
+    Weight,
+}
+
+/// Drops samples or sets their weights. Which samples are dropped depends on their hash, not on
+/// the order of the movies.
+pub fn apply<'a>(balance: &Balance, samples: Vec<Sample<'a>>, labels: &[String], seed: &str) -> Vec<Sample<'a>> {
+    let counts = count(&samples, labels);
+
+    // Decide in hash order, then keep the original order.
+    let mut order: Vec<_> = (0..samples.len()).collect();
+    order.sort_by(|a, b| {
+        let a = dataset::hash_unit(&samples[*a].movie.youtube_id, seed);
+        let b = dataset::hash_unit(&samples[*b].movie.youtube_id, seed);
+        a.partial_cmp(&b).unwrap()
+    });
+
+    let mut keep = vec![true; samples.len()];
+    match balance {
+        Balance::None | Balance::Weight => {},
+        Balance::Cap { max_per_label } => {
+            // The sort is stable, so samples whose rarest labels are as rare stay in hash order.
+            order.sort_by_key(|i| indices(&samples[*i], labels).map(|l| counts[l]).min());
+
+            let mut taken = vec![0; labels.len()];
+            for i in order {
+                let sample: Vec<_> = indices(&samples[i], labels).collect();
+                keep[i] = sample.iter().all(|l| taken[*l] < *max_per_label);
+                if keep[i] {
+                    for l in sample {
+                        taken[l] += 1;
+                    }
+                }
+            }
+        },
+        Balance::Undersample { max_per_combination } => {
+            let mut taken: HashMap<Vec<usize>, usize> = HashMap::new();
+            for i in order {
+                let mut combination: Vec<_> = indices(&samples[i], labels).collect();
+                combination.sort_unstable();
+                let n = taken.entry(combination).or_default();
+                *n += 1;
+                keep[i] = *n <= *max_per_combination;
+            }
+        },
+    }
+
+    let mut kept: Vec<_> = samples.into_iter()
+        .zip(keep)
+        .filter(|(_, k)| *k)
+        .map(|(s, _)| s)
+        .collect();
+
+    if *balance == Balance::Weight {
+        let total = kept.len() as f64;
+        for sample in &mut kept {
+            let weights: Vec<_> = indices(sample, labels)
+                .map(|i| total / (labels.len() as f64 * counts[i] as f64))
+                .collect();
+            sample.weight = weights.iter().sum::<f64>() / weights.len() as f64;
+        }
+
+        // Normalize to a mean of 1, so the loss keeps its scale.
+        let mean = kept.iter().map(|s| s.weight).sum::<f64>() / total;
+        for sample in &mut kept {
+            sample.weight /= mean;
+        }
+    }
+
+    kept
+}
+
+/// The `pos_weight` of each label for a binary cross-entropy loss: negatives over positives.
+pub fn pos_weights(multi_hot: &[Vec<u8>]) -> Vec<f64> {
+    let n = multi_hot.len();
+    let width = multi_hot.first().map(|r| r.len()).unwrap_or(0);
+
+    (0..width)
+        .map(|i| {
+            let positives = multi_hot.iter().filter(|r| r[i] == 1).count();
+            if positives == 0 {
+                0.0
+            } else {
+                (n - positives) as f64 / positives as f64
+            }
+        })
+        .collect()
+}
+
+fn count(samples: &[Sample], labels: &[String]) -> Vec<usize> {
+    let mut counts = vec![0; labels.len()];
+    for sample in samples {
+        for i in indices(sample, labels) {
+            counts[i] += 1;
+        }
+    }
+    counts
+}
+
+/// Positions of the sample labels in the label set.
+fn indices<'a>(sample: &'a Sample, labels: &'a [String]) -> impl Iterator<Item = usize> + 'a {
+    sample.labels.iter().filter_map(move |l| labels.iter().position(|x| x == l))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Balance;
+    use crate::dataset::{Sample, Split};
+    use crate::manifest::tests::movie;
+    use crate::manifest::Artifact;
+    use crate::movie::{Movie, TrailerSource};
+
+    fn samples<'a>(movies: &'a [Movie], artifact: &'a Artifact, labels: &[&[&str]]) -> Vec<Sample<'a>> {
+        movies.iter().zip(labels)
+            .map(|(movie, labels)| Sample {
+                movie,
+                artifact,
+                labels: labels.iter().map(|l| l.to_string()).collect(),
+                primary: None,
+                split: Split::Train,
+                weight: 1.0,
+            })
+            .collect()
+    }
+
+    fn fixture() -> (Vec<Movie>, Artifact, Vec<&'static [&'static str]>) {
+        let mut labels: Vec<&[&str]> = vec![&["Drama"]; 6];
+        labels.extend_from_slice(&[&["Drama", "Horror"], &["Horror"], &["Comedy"], &["Comedy", "Drama"]]);
+
+        let movies = (0..labels.len()).map(|i| movie(&i.to_string())).collect();
+
+        (movies, Artifact::new(&Movie::default(), "", TrailerSource::Id, String::new()), labels)
+    }
+
+    fn label_set() -> Vec<String> {
+        vec!["Drama".to_owned(), "Horror".to_owned(), "Comedy".to_owned()]
+    }
+
+    #[test]
+    fn cap() {
+        let (movies, artifact, labels) = fixture();
+        let kept = super::apply(&Balance::Cap { max_per_label: 2 }, samples(&movies, &artifact, &labels), &label_set(), "");
+
+        // The Horror and Comedy samples fill the Drama cap, so no Drama-only sample is left.
+        for label in label_set() {
+            assert_eq!(kept.iter().filter(|s| s.labels.contains(&label)).count(), 2, "{}", label);
+        }
+        assert_eq!(kept.len(), 4);
+        assert!(kept.iter().all(|s| s.labels != ["Drama"]));
+    }
+
+    #[test]
+    fn undersample() {
+        let (movies, artifact, labels) = fixture();
+        let balance = Balance::Undersample { max_per_combination: 1 };
+        let kept = super::apply(&balance, samples(&movies, &artifact, &labels), &label_set(), "");
+
+        assert_eq!(kept.len(), 5);
+        let seeded = super::apply(&balance, samples(&movies, &artifact, &labels), &label_set(), "other");
+        assert_eq!(seeded.len(), 5);
+    }
+
+    #[test]
+    fn weight() {
+        let (movies, artifact, labels) = fixture();
+        let kept = super::apply(&Balance::Weight, samples(&movies, &artifact, &labels), &label_set(), "");
+
+        assert_eq!(kept.len(), 10);
+        let mean = kept.iter().map(|s| s.weight).sum::<f64>() / 10.0;
+        assert!((mean - 1.0).abs() < 1e-9);
+
+        let weight = |l: &[&str]| kept.iter().find(|s| s.labels == l).unwrap().weight;
+        assert!(weight(&["Horror"]) > weight(&["Drama", "Horror"]));
+        assert!(weight(&["Drama", "Horror"]) > weight(&["Drama"]));
+    }
+
+    #[test]
+    fn pos_weights() {
+        let rows = vec![vec![1, 0], vec![1, 1], vec![1, 0], vec![0, 0]];
+        assert_eq!(super::pos_weights(&rows), [1.0 / 3.0, 3.0]);
+        assert_eq!(super::pos_weights(&[vec![0]]), [0.0]);
+        assert!(super::pos_weights(&[]).is_empty());
+    }
+}
@@ -0,0 +1,219 @@
+//! A baseline model to compare datasets with before training the network: one-vs-rest logistic
+//! regression on spectrograms pooled into mel bands.
+
+use crate::error::*;
+
+use std::path::Path;
+
+use image::RgbImage;
+
+/// Mel bands the frequency rows of a spectrogram are pooled into.
+pub const BANDS: usize = 32;
+
+fn mel(hz: f64) -> f64 {
+    2595.0 * (1.0 + hz / 700.0).log10()
+}
+
+/// The mel band of each row of a spectrogram `height` pixels high, from 0 Hz at the bottom to
+/// `stop_hz` at the top. Low bands narrower than a row get no rows.
+pub fn band_of_rows(height: u32, stop_hz: u32) -> Vec<usize> {
+    let top = mel(stop_hz as f64);
+
+    (0..height)
+        .map(|y| {
+            let hz = stop_hz as f64 * (height - 1 - y) as f64 / height as f64;
+            ((mel(hz) / top * BANDS as f64) as usize).min(BANDS - 1)
+        })
+        .collect()
+}
+
+/// Pools a spectrogram into the mean and standard deviation over time of the intensity of each mel
+/// band. Pixels are already on a log scale; their intensity is the mean of their channels.
+pub fn pool(image: &RgbImage, stop_hz: u32) -> Vec<f64> {
+    let bands = band_of_rows(image.height(), stop_hz);
+    let mut used: Vec<_> = bands.clone();
+    used.sort_unstable();
+    used.dedup();
+
+    let mut features = Vec::with_capacity(used.len() * 2);
+    for band in used {
+        let rows: Vec<_> = (0..image.height()).filter(|y| bands[*y as usize] == band).collect();
+        let series: Vec<f64> = (0..image.width())
+            .map(|x| {
+                let sum: f64 = rows.iter()
+                    .map(|y| image.get_pixel(x, *y).0.iter().map(|c| *c as f64).sum::<f64>() / (3.0 * 255.0))
+                    .sum();
+                sum / rows.len() as f64
+            })
+            .collect();
+
+        let mean = series.iter().sum::<f64>() / series.len().max(1) as f64;
+        let variance = series.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / series.len().max(1) as f64;
+        features.push(mean);
+        features.push(variance.sqrt());
+    }
+
+    features
+}
+
+/// Reads and pools a spectrogram file.
+pub fn pool_file<T: AsRef<Path>>(path: T, stop_hz: u32) -> Result<Vec<f64>> {
+    let path = path.as_ref();
+    let image = image::open(path).map_err(|e| format!("{}: {}", path.display(), e))?.to_rgb8();
+
+    Ok(pool(&image, stop_hz))
+}
+
+/// Settings of the gradient descent.
+#[derive(Debug, Clone, Copy)]
+pub struct TrainOptions {
+    pub epochs: usize,
+    pub learning_rate: f64,
+    /// L2 penalty on the weights.
+    pub l2: f64,
+}
+
+impl Default for TrainOptions {
+    fn default() -> Self {
+        TrainOptions {
+            epochs: 500,
+            learning_rate: 0.1,
+            l2: 1e-3,
+        }
+    }
+}
+
+fn sigmoid(x: f64) -> f64 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+/// A binary logistic regression.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogisticRegression {
+    pub weights: Vec<f64>,
+    pub bias: f64,
+}
+
+impl LogisticRegression {
+    /// Fits with full-batch gradient descent from zero weights, so the result only depends on the
+    /// data. Positive samples weigh `pos_weight` in the loss.
+    pub fn fit(x: &[Vec<f64>], y: &[bool], pos_weight: f64, options: &TrainOptions) -> LogisticRegression {
+        let dims = x.first().map(|r| r.len()).unwrap_or(0);
+        let mut model = LogisticRegression {
+            weights: vec![0.0; dims],
+            bias: 0.0,
+        };
+        let n = x.len().max(1) as f64;
+
+        for _ in 0..options.epochs {
+            let mut gradient = vec![0.0; dims];
+            let mut bias_gradient = 0.0;
+
+            for (row, target) in x.iter().zip(y) {
+                let error = model.predict(row) - if *target { 1.0 } else { 0.0 };
+                let error = if *target { error * pos_weight } else { error };
+                for (g, v) in gradient.iter_mut().zip(row) {
+                    *g += error * v;
+                }
+                bias_gradient += error;
+            }
+
+            for (w, g) in model.weights.iter_mut().zip(&gradient) {
+                *w -= options.learning_rate * (g / n + options.l2 * *w);
+            }
+            model.bias -= options.learning_rate * bias_gradient / n;
+        }
+
+        model
+    }
+
+    pub fn predict(&self, row: &[f64]) -> f64 {
+        sigmoid(self.bias + self.weights.iter().zip(row).map(|(w, v)| w * v).sum::<f64>())
+    }
+}
+
+/// One logistic regression per label on standardized features.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Baseline {
+    pub means: Vec<f64>,
+    pub stds: Vec<f64>,
+    pub models: Vec<LogisticRegression>,
+}
+
+impl Baseline {
+    /// `targets` has one column per label, each weighted by its `pos_weights` entry.
+    pub fn fit(x: &[Vec<f64>], targets: &[Vec<bool>], pos_weights: &[f64], options: &TrainOptions) -> Baseline {
+        let dims = x.first().map(|r| r.len()).unwrap_or(0);
+        let n = x.len().max(1) as f64;
+        let means: Vec<f64> = (0..dims).map(|i| x.iter().map(|r| r[i]).sum::<f64>() / n).collect();
+        let stds: Vec<f64> = (0..dims)
+            .map(|i| (x.iter().map(|r| (r[i] - means[i]).powi(2)).sum::<f64>() / n).sqrt())
+            .map(|s| if s > 1e-9 { s } else { 1.0 })
+            .collect();
+
+        let mut baseline = Baseline { means, stds, models: vec![] };
+        let x: Vec<_> = x.iter().map(|r| baseline.standardize(r)).collect();
+        baseline.models = pos_weights.iter().enumerate()
+            .map(|(i, pos_weight)| {
+                let y: Vec<_> = targets.iter().map(|t| t[i]).collect();
+                LogisticRegression::fit(&x, &y, *pos_weight, options)
+            })
+            .collect();
+
+        baseline
+    }
+
+    fn standardize(&self, row: &[f64]) -> Vec<f64> {
+        row.iter().zip(self.means.iter().zip(&self.stds)).map(|(v, (m, s))| (v - m) / s).collect()
+    }
+
+    /// The probability of each label.
+    pub fn predict(&self, row: &[f64]) -> Vec<f64> {
+        let row = self.standardize(row);
+        self.models.iter().map(|m| m.predict(&row)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{band_of_rows, pool, Baseline, TrainOptions, BANDS};
+
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn pooling() {
+        let bands = band_of_rows(224, 16000);
+        assert_eq!(bands[0], BANDS - 1);
+        assert_eq!(bands[223], 0);
+        assert!(bands.windows(2).all(|w| w[0] >= w[1]));
+
+        // Bright in the first half of the time, dark in the second.
+        let mut image = RgbImage::new(10, 224);
+        for x in 0..5 {
+            for y in 0..224 {
+                image.put_pixel(x, y, Rgb([255, 255, 255]));
+            }
+        }
+
+        let features = pool(&image, 16000);
+        assert_eq!(features.len() % 2, 0);
+        for pair in features.chunks(2) {
+            assert!((pair[0] - 0.5).abs() < 1e-9 && (pair[1] - 0.5).abs() < 1e-9, "{:?}", pair);
+        }
+    }
+
+    #[test]
+    fn baseline() {
+        // The first label follows the first feature, the second the opposite of the second one.
+        let x: Vec<Vec<f64>> = (0..40).map(|i| vec![(i % 4) as f64, (i % 5) as f64 * 10.0, 7.0]).collect();
+        let targets: Vec<Vec<bool>> = x.iter().map(|r| vec![r[0] >= 2.0, r[1] < 20.0]).collect();
+
+        let model = Baseline::fit(&x, &targets, &[1.0, 1.0], &TrainOptions::default());
+        for (row, target) in x.iter().zip(&targets) {
+            let predicted: Vec<_> = model.predict(row).iter().map(|p| *p >= 0.5).collect();
+            assert_eq!(&predicted, target, "{:?}", row);
+        }
+
+        assert_eq!(model, Baseline::fit(&x, &targets, &[1.0, 1.0], &TrainOptions::default()));
+    }
+}
@@ -0,0 +1,150 @@
+//! The dataset config loaded with `--config`.
+
+use crate::balance::Balance;
+use crate::error::*;
+use crate::labels::LabelMapping;
+
+use std::fs::File;
+use std::path::Path;
+
+use serde::{Serialize, Deserialize};
+
+/// Everything that shapes an exported dataset. Loaded with `--config`, and saved alongside
+/// exports so a dataset can be made again. Missing fields take their default.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub selection: SelectionConfig,
+    pub labels: LabelMapping,
+    pub csv: CsvConfig,
+}
+
+impl Config {
+    /// Loads the config and checks its label mapping against the CSV headers.
+    pub fn load<T: AsRef<Path>>(path: T) -> Result<Config> {
+        let config: Config = serde_json::from_reader(File::open(path)?)?;
+        config.labels.validate(&config.csv.headers)?;
+        Ok(config)
+    }
+}
+
+/// Which movies and genres make up a dataset.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SelectionConfig {
+    pub min_year: u32,
+    pub min_critics: u32,
+    /// Movies are kept when they have one of the `top_genres` most common labels.
+    pub top_genres: usize,
+    /// Use every label instead of the `top_genres` most common ones.
+    pub all_genres: bool,
+    /// Fraction of the movies put into the validation split.
+    pub validation_fraction: f64,
+    /// Mixed into the split hash; change it to draw another split.
+    pub split_seed: String,
+    pub balance: Balance,
+}
+
+impl Default for SelectionConfig {
+    fn default() -> Self {
+        SelectionConfig {
+            min_year: 2000,
+            min_critics: 100,
+            top_genres: 5,
+            all_genres: false,
+            validation_fraction: 0.2,
+            split_seed: String::new(),
+            balance: Balance::default(),
+        }
+    }
+}
+
+/// How labels are written into `movies_selected.csv`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LabelEncoding {
+    /// A 0/1 column per genre, named after the genre.
+    #[default]
+    MultiHot,
+    /// One column of genre names separated by `|`.
+    Pipe,
+    /// One column of genre indices separated by `|`, indexing into `labels.txt`.
+    ClassIds,
+}
+
+/// Layout of `movies_selected.csv`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CsvConfig {
+    pub encoding: LabelEncoding,
+    /// Adds the year, critics count, metascore, IMDb id and trailer duration columns.
+    pub metadata: bool,
+    /// Adds the sample weight column.
+    pub weights: bool,
+    pub headers: CsvHeaders,
+}
+
+/// Names of the CSV columns. Multi-hot columns are named after their genre.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CsvHeaders {
+    pub id: String,
+    pub name: String,
+    pub year: String,
+    pub critics_number: String,
+    pub metacritic_score: String,
+    pub imdb_id: String,
+    pub duration_ms: String,
+    pub labels: String,
+    /// Only written when the label mapping has a priority.
+    pub primary: String,
+    pub weight: String,
+}
+
+impl CsvHeaders {
+    /// Every header, whether or not the CSV encoding writes its column.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        vec![
+            &self.id, &self.name, &self.year, &self.critics_number, &self.metacritic_score,
+            &self.imdb_id, &self.duration_ms, &self.labels, &self.primary, &self.weight,
+        ].into_iter().map(|h| h.as_str())
+    }
+}
+
+impl Default for CsvHeaders {
+    fn default() -> Self {
+        CsvHeaders {
+            id: "Id".to_owned(),
+            name: "Name".to_owned(),
+            year: "Year".to_owned(),
+            critics_number: "Critics".to_owned(),
+            metacritic_score: "Metascore".to_owned(),
+            imdb_id: "ImdbId".to_owned(),
+            duration_ms: "DurationMs".to_owned(),
+            labels: "Labels".to_owned(),
+            primary: "Primary".to_owned(),
+            weight: "Weight".to_owned(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Config, CsvConfig, LabelEncoding, SelectionConfig};
+
+    #[test]
+    fn partial() {
+        let config: Config = serde_json::from_str(r#"{
+            "selection": { "top_genres": 3 },
+            "csv": { "encoding": "class_ids", "headers": { "id": "youtube_id" } }
+        }"#).unwrap();
+
+        assert_eq!(config.selection, SelectionConfig {
+            top_genres: 3,
+            ..Default::default()
+        });
+        assert_eq!(config.csv.encoding, LabelEncoding::ClassIds);
+        assert_eq!(config.csv.headers.id, "youtube_id");
+        assert_eq!(config.csv.headers.name, CsvConfig::default().headers.name);
+    }
+}
@@ -0,0 +1,476 @@
+//! Selects movies into a labeled dataset with a train/validation split, and writes its CSV.
+
+use crate::balance;
+use crate::config::{CsvConfig, LabelEncoding, SelectionConfig};
+use crate::error::*;
+use crate::labels::LabelMapping;
+use crate::manifest::{Artifact, Manifest};
+use crate::movie::{Genre, Movie};
+
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::io::Write;
+
+use rayon::prelude::*;
+
+/// Which part of the dataset a movie belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Split {
+    Train,
+    Validation,
+}
+
+impl Split {
+    pub fn from_text(text: &str) -> Result<Split> {
+        match text {
+            "train" => Ok(Split::Train),
+            "validation" => Ok(Split::Validation),
+            _ => Err(format!("unknown split '{}'", text))?,
+        }
+    }
+}
+
+impl std::fmt::Display for Split {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            Split::Train => write!(fmt, "train"),
+            Split::Validation => write!(fmt, "validation"),
+        }
+    }
+}
+
+/// Assigns a movie to a split from the hash of its key, so the assignment doesn't change when
+/// other movies are added or removed.
+pub fn split(key: &str, seed: &str, validation_fraction: f64) -> Split {
+    if hash_unit(key, seed) < validation_fraction {
+        Split::Validation
+    } else {
+        Split::Train
+    }
+}
+
+/// Maps a key to a number in `[0, 1]` that only depends on the key and the seed.
+pub fn hash_unit(key: &str, seed: &str) -> f64 {
+    let digest = md5::compute(format!("{}{}", seed, key));
+    u64::from_be_bytes(digest[..8].try_into().unwrap()) as f64 / u64::MAX as f64
+}
+
+/// A movie in the dataset, with its labels.
+pub struct Sample<'a> {
+    pub movie: &'a Movie,
+    pub artifact: &'a Artifact,
+    /// The mapped genres of the movie that are in the label set.
+    pub labels: Vec<String>,
+    /// The label the movie is mostly about, by the mapping priority.
+    pub primary: Option<String>,
+    pub split: Split,
+    /// Weight of the sample in the loss, 1 unless balanced by weighting.
+    pub weight: f64,
+}
+
+pub struct Dataset<'a> {
+    /// The label set: the most common labels first, or every label in genre declaration order.
+    pub labels: Vec<String>,
+    pub mapping: LabelMapping,
+    pub samples: Vec<Sample<'a>>,
+}
+
+impl<'a> Dataset<'a> {
+    /// Maps the genres of the movies with an artifact, then keeps the movies that have one of
+    /// the most common labels, or any label with `all_genres`, and balances them.
+    pub fn select(
+        movies: &'a [Movie],
+        manifest: &'a Manifest,
+        config: &SelectionConfig,
+        mapping: &LabelMapping,
+    ) -> Result<Dataset<'a>> {
+        let with_artifacts: Vec<_> = candidates(movies, config).into_iter()
+            .filter_map(|(_, m)| manifest.get(m).map(|a| (m, a, mapping.map(&m.genres))))
+            .collect();
+
+        Manifest::common_params(with_artifacts.iter().map(|(_, a, _)| *a))?;
+
+        let labels: Vec<_> = if config.all_genres {
+            mapping.all()
+        } else {
+            count_labels(mapping, with_artifacts.iter().map(|(_, _, l)| &**l)).into_iter()
+                .take(config.top_genres)
+                .map(|(l, _)| l)
+                .collect()
+        };
+
+        let samples = with_artifacts.into_iter()
+            .map(|(movie, artifact, movie_labels)| {
+                let movie_labels: Vec<_> = movie_labels.into_iter().filter(|l| labels.contains(l)).collect();
+
+                Sample {
+                    movie,
+                    artifact,
+                    primary: mapping.primary(&movie_labels).cloned(),
+                    labels: movie_labels,
+                    split: split(&movie.youtube_id, &config.split_seed, config.validation_fraction),
+                    weight: 1.0,
+                }
+            })
+            .filter(|s| !s.labels.is_empty())
+            .collect();
+        let samples = balance::apply(&config.balance, samples, &labels, &config.split_seed);
+
+        Ok(Dataset {
+            labels,
+            mapping: mapping.clone(),
+            samples,
+        })
+    }
+
+    /// Number of samples with each label, most common first.
+    pub fn label_counts(&self) -> Vec<(String, usize)> {
+        count_labels(&self.mapping, self.samples.iter().map(|s| &*s.labels))
+    }
+
+    /// The 0/1 encoding of the labels of a sample, in the order of the label set.
+    pub fn multi_hot(&self, sample: &Sample) -> Vec<u8> {
+        self.labels.iter().map(|l| sample.labels.contains(l) as u8).collect()
+    }
+
+    /// The `pos_weight` of each label, from the train split.
+    pub fn pos_weights(&self) -> Vec<f64> {
+        let rows: Vec<_> = self.samples.iter()
+            .filter(|s| s.split == Split::Train)
+            .map(|s| self.multi_hot(s))
+            .collect();
+
+        balance::pos_weights(&rows)
+    }
+
+    pub fn movies(&self) -> Vec<&'a Movie> {
+        self.samples.iter().map(|s| s.movie).collect()
+    }
+
+    /// Writes `movies_selected.csv`: the id, the name, the metadata columns if enabled, then the
+    /// labels encoded as configured.
+    pub fn write_csv<W: Write>(&self, writer: W, config: &CsvConfig) -> Result<()> {
+        let names = &config.headers;
+        let mut wtr = csv::Writer::from_writer(writer);
+        let mut headers = vec![names.id.clone(), names.name.clone()];
+        if config.metadata {
+            for header in [&names.year, &names.critics_number, &names.metacritic_score, &names.imdb_id, &names.duration_ms] {
+                headers.push(header.clone());
+            }
+        }
+        match config.encoding {
+            LabelEncoding::MultiHot => headers.extend(self.labels.iter().cloned()),
+            LabelEncoding::Pipe | LabelEncoding::ClassIds => headers.push(names.labels.clone()),
+        }
+        if !self.mapping.priority.is_empty() {
+            headers.push(names.primary.clone());
+        }
+        if config.weights {
+            headers.push(names.weight.clone());
+        }
+        wtr.write_record(headers)?;
+
+        for sample in &self.samples {
+            let movie = sample.movie;
+            wtr.write_field(&movie.youtube_id)?;
+            wtr.write_field(&movie.name)?;
+
+            if config.metadata {
+                wtr.write_field(optional(movie.year))?;
+                wtr.write_field(optional(movie.critics_number))?;
+                wtr.write_field(optional(movie.metacritic_score))?;
+                wtr.write_field(movie.imdb_id.map(|id| format!("tt{:07}", id)).unwrap_or_default())?;
+                wtr.write_field(sample.artifact.duration_ms.to_string())?;
+            }
+
+            match config.encoding {
+                LabelEncoding::MultiHot => for value in self.multi_hot(sample) {
+                    wtr.write_field(value.to_string())?;
+                },
+                LabelEncoding::Pipe => wtr.write_field(sample.labels.join("|"))?,
+                LabelEncoding::ClassIds => {
+                    let labels: Vec<_> = sample.labels.iter()
+                        .map(|l| self.labels.iter().position(|x| x == l).unwrap().to_string())
+                        .collect();
+                    wtr.write_field(labels.join("|"))?;
+                },
+            }
+
+            if !self.mapping.priority.is_empty() {
+                wtr.write_field(sample.primary.as_deref().unwrap_or(""))?;
+            }
+            if config.weights {
+                wtr.write_field(sample.weight.to_string())?;
+            }
+
+            wtr.write_record(None::<&[u8]>)?;
+        }
+
+        wtr.flush()?;
+
+        Ok(())
+    }
+
+    /// Writes `labels.txt`: the label set, one label per line, so line `i` is class id `i`.
+    pub fn write_labels<W: Write>(&self, mut writer: W) -> Result<()> {
+        for label in &self.labels {
+            writeln!(writer, "{}", label)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn optional<T: ToString>(value: Option<T>) -> String {
+    value.map(|v| v.to_string()).unwrap_or_default()
+}
+
+/// Movies that get a trailer, with their index in `movies`.
+pub fn candidates<'a>(movies: &'a [Movie], config: &SelectionConfig) -> Vec<(usize, &'a Movie)> {
+    movies.par_iter()
+        .enumerate()
+        .filter(|(_, m)| m.year >= Some(config.min_year))
+        .filter(|(_, m)| m.critics_number.is_some())
+        .filter(|(_, m)| m.critics_number.unwrap() >= config.min_critics)
+        .collect()
+}
+
+/// Number of movies in each genre, most common first.
+pub fn count_genres<'a, I>(movies: I) -> Vec<(Genre, usize)>
+where
+    I: IntoIterator<Item = &'a Movie>,
+{
+    let mut map: HashMap<Genre, usize> = HashMap::new();
+
+    for movie in movies {
+        for genre in &movie.genres {
+            *map.entry(*genre).or_default() += 1;
+        }
+    }
+
+    let mut sorted: Vec<_> = map.into_iter().collect();
+    // Break ties by genre so the selection doesn't depend on the hash order.
+    sorted.sort_by_key(|(g, n)| (std::cmp::Reverse(*n), *g));
+
+    sorted
+}
+
+/// Number of movies with each label, most common first, given the labels of each movie.
+pub fn count_labels<'a, I>(mapping: &LabelMapping, movies: I) -> Vec<(String, usize)>
+where
+    I: IntoIterator<Item = &'a [String]>,
+{
+    let mut map: HashMap<&str, usize> = HashMap::new();
+
+    for labels in movies {
+        for label in labels {
+            *map.entry(label).or_default() += 1;
+        }
+    }
+
+    // Break ties by the genre order so the selection doesn't depend on the hash order.
+    let order = mapping.all();
+    let mut sorted: Vec<_> = map.into_iter()
+        .map(|(l, n)| (l.to_owned(), n))
+        .collect();
+    sorted.sort_by_key(|(l, n)| (std::cmp::Reverse(*n), order.iter().position(|o| o == l)));
+
+    sorted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Dataset, Split};
+    use crate::balance::Balance;
+    use crate::config::{CsvConfig, LabelEncoding, SelectionConfig};
+    use crate::labels::LabelMapping;
+    use crate::manifest::tests::{self as fixture, manifest};
+    use crate::manifest::{Artifact, Manifest};
+    use crate::movie::{Genre, Movie, TrailerSource};
+
+    fn movie(id: &str, year: u32, genres: &[Genre]) -> Movie {
+        Movie {
+            name: format!("Movie {}", id),
+            year: Some(year),
+            genres: genres.to_vec(),
+            critics_number: Some(200),
+            ..fixture::movie(id)
+        }
+    }
+
+    #[test]
+    fn select() {
+        use Genre::*;
+
+        let movies = vec![
+            movie("a", 2010, &[Drama, Comedy]),
+            movie("b", 2011, &[Drama, Horror]),
+            movie("c", 2012, &[Comedy]),
+            movie("d", 2013, &[Western, Drama]),
+            movie("e", 1990, &[Drama]),
+            movie("f", 2014, &[Drama]),
+        ];
+
+        let manifest = manifest(&movies[..5]);
+
+        let config = SelectionConfig {
+            top_genres: 2,
+            ..Default::default()
+        };
+        let dataset = Dataset::select(&movies, &manifest, &config, &Default::default()).unwrap();
+
+        assert_eq!(dataset.labels, ["Drama", "Comedy"]);
+        let ids: Vec<_> = dataset.movies().iter().map(|m| &*m.youtube_id).collect();
+        assert_eq!(ids, vec!["a", "b", "c", "d"]);
+        assert_eq!(dataset.samples[1].labels, ["Drama"]);
+        assert_eq!(dataset.label_counts(), [("Drama".to_owned(), 3), ("Comedy".to_owned(), 2)]);
+
+        let mut csv = vec![];
+        dataset.write_csv(&mut csv, &CsvConfig::default()).unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap(), "\
+Id,Name,Drama,Comedy
+a,Movie a,1,1
+b,Movie b,1,0
+c,Movie c,0,1
+d,Movie d,1,0
+");
+    }
+
+    #[test]
+    fn write_csv() {
+        use Genre::*;
+
+        let movies = vec![Movie {
+            imdb_id: Some(133093),
+            metacritic_score: Some(73),
+            ..movie("a", 1999, &[Action, SciFi])
+        }];
+
+        let mut manifest = Manifest::default();
+        manifest.insert(Artifact {
+            duration_ms: 150000,
+            ..Artifact::new(&movies[0], "a", TrailerSource::Id, String::new())
+        });
+
+        let config = SelectionConfig {
+            min_year: 1990,
+            all_genres: true,
+            ..Default::default()
+        };
+        let dataset = Dataset::select(&movies, &manifest, &config, &Default::default()).unwrap();
+        assert_eq!(dataset.labels.len(), Genre::ALL.len());
+
+        let write = |csv: CsvConfig| {
+            let mut out = vec![];
+            dataset.write_csv(&mut out, &csv).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        let mut csv = CsvConfig {
+            encoding: LabelEncoding::Pipe,
+            metadata: true,
+            ..Default::default()
+        };
+        csv.headers.id = "youtube_id".to_owned();
+        assert_eq!(write(csv.clone()), "\
+youtube_id,Name,Year,Critics,Metascore,ImdbId,DurationMs,Labels
+a,Movie a,1999,200,73,tt0133093,150000,Action|SciFi
+");
+
+        csv.encoding = LabelEncoding::ClassIds;
+        csv.metadata = false;
+        assert_eq!(write(csv), "youtube_id,Name,Labels\na,Movie a,6|7\n");
+
+        let multi_hot = write(CsvConfig::default());
+        assert_eq!(multi_hot.lines().next().unwrap().split(',').count(), 2 + Genre::ALL.len());
+
+        let mut labels = vec![];
+        dataset.write_labels(&mut labels).unwrap();
+        assert_eq!(String::from_utf8(labels).unwrap().lines().nth(6), Some("Action"));
+    }
+
+    #[test]
+    fn mapped() {
+        use Genre::*;
+
+        let movies = vec![
+            movie("a", 2010, &[Drama, Crime]),
+            movie("b", 2011, &[Mystery, Thriller]),
+            movie("c", 2012, &[News]),
+            movie("d", 2013, &[Comedy]),
+        ];
+
+        let manifest = manifest(&movies);
+
+        let mapping: LabelMapping = serde_json::from_str(r#"{
+            "merge": { "Suspense": ["Thriller", "Mystery", "Crime"] },
+            "drop": ["News"],
+            "priority": ["Suspense"]
+        }"#).unwrap();
+        let config = SelectionConfig {
+            top_genres: 2,
+            ..Default::default()
+        };
+        let dataset = Dataset::select(&movies, &manifest, &config, &mapping).unwrap();
+
+        assert_eq!(dataset.labels, ["Suspense", "Comedy"]);
+        let ids: Vec<_> = dataset.movies().iter().map(|m| &*m.youtube_id).collect();
+        assert_eq!(ids, vec!["a", "b", "d"]);
+        assert_eq!(dataset.samples[0].labels, ["Suspense"]);
+        assert_eq!(dataset.samples[0].primary.as_deref(), Some("Suspense"));
+
+        let mut csv = vec![];
+        dataset.write_csv(&mut csv, &CsvConfig::default()).unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap().lines().next(), Some("Id,Name,Suspense,Comedy,Primary"));
+    }
+
+    #[test]
+    fn balanced() {
+        use Genre::*;
+
+        let movies: Vec<_> = (0..20)
+            .map(|i| movie(&i.to_string(), 2010, if i < 15 { &[Drama] } else { &[Horror] }))
+            .collect();
+
+        let manifest = manifest(&movies);
+
+        let config = SelectionConfig {
+            balance: Balance::Cap { max_per_label: 5 },
+            validation_fraction: 0.0,
+            ..Default::default()
+        };
+        let dataset = Dataset::select(&movies, &manifest, &config, &Default::default()).unwrap();
+        assert_eq!(dataset.label_counts(), [("Drama".to_owned(), 5), ("Horror".to_owned(), 5)]);
+        assert_eq!(dataset.pos_weights(), [1.0, 1.0]);
+
+        let config = SelectionConfig {
+            balance: Balance::Weight,
+            validation_fraction: 0.0,
+            ..Default::default()
+        };
+        let dataset = Dataset::select(&movies, &manifest, &config, &Default::default()).unwrap();
+        assert_eq!(dataset.samples.len(), 20);
+        assert_eq!(dataset.pos_weights(), [5.0 / 15.0, 3.0]);
+
+        let mut csv = vec![];
+        let csv_config = CsvConfig {
+            weights: true,
+            ..Default::default()
+        };
+        dataset.write_csv(&mut csv, &csv_config).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(csv.lines().next(), Some("Id,Name,Drama,Horror,Weight"));
+        assert!(csv.lines().last().unwrap().ends_with(",0,1,2"));
+    }
+
+    #[test]
+    fn split() {
+        let splits: Vec<_> = (0..1000).map(|i| super::split(&i.to_string(), "", 0.2)).collect();
+        let validation = splits.iter().filter(|s| **s == Split::Validation).count();
+        assert!((150..250).contains(&validation), "{}", validation);
+
+        assert_eq!(super::split("abc", "", 0.2), super::split("abc", "", 0.2));
+        assert_eq!(super::split("abc", "", 0.0), Split::Train);
+        assert_eq!(super::split("abc", "", 1.0), Split::Validation);
+    }
+}
@@ -0,0 +1,294 @@
+//! Pure-Rust audio decoding and spectrogram rendering, enabled by the `native-decode` feature.
+//!
+//! Decodes AAC (m4a), MP3, FLAC and WAV. Opus is not supported by the decoder, so it is decoded by
+//! ffmpeg, as is the loudness and silence analysis of `external::analyze_audio`.
+//! `external::check_ffmpeg` fails early when those need ffmpeg and it is missing.
+
+use crate::error::*;
+use crate::external::MediaInfo;
+
+use std::convert::TryFrom;
+use std::f32::consts::PI;
+use std::fs::File;
+use std::path::Path;
+
+use image::{Rgb, RgbImage};
+use rustfft::{num_complex::Complex, FftPlanner};
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{CodecParameters, DecoderOptions, CODEC_TYPE_NULL};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::{FormatOptions, FormatReader};
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+/// Decoded audio, mixed down to mono.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pcm {
+    pub sample_rate: u32,
+    pub samples: Vec<f32>,
+}
+
+impl Pcm {
+    pub fn duration_ms(&self) -> u64 {
+        self.samples.len() as u64 * 1000 / self.sample_rate as u64
+    }
+
+    /// Linearly resamples to 16-bit samples at `sample_rate`.
+    pub fn to_i16(&self, sample_rate: u32) -> Vec<i16> {
+        let step = self.sample_rate as f64 / sample_rate as f64;
+        let len = (self.samples.len() as f64 / step) as usize;
+
+        (0..len)
+            .map(|i| {
+                let pos = i as f64 * step;
+                let j = pos as usize;
+                let frac = (pos - j as f64) as f32;
+                let a = self.samples[j];
+                let b = self.samples.get(j + 1).copied().unwrap_or(a);
+                ((a + (b - a) * frac).clamp(-1.0, 1.0) * i16::MAX as f32) as i16
+            })
+            .collect()
+    }
+}
+
+fn open(path: &Path) -> Result<Box<dyn FormatReader>> {
+    let source = MediaSourceStream::new(Box::new(File::open(path)?), Default::default());
+
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+        hint.with_extension(ext);
+    }
+
+    let probed = symphonia::default::get_probe()
+        .format(&hint, source, &FormatOptions::default(), &MetadataOptions::default())
+        .map_err(to_error)?;
+
+    Ok(probed.format)
+}
+
+fn audio_track(format: &dyn FormatReader) -> Result<(u32, CodecParameters)> {
+    format.tracks().iter()
+        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+        .map(|t| (t.id, t.codec_params.clone()))
+        .ok_or_else(|| Error::Decode("no audio track".into()))
+}
+
+pub fn probe(path: &Path) -> Result<MediaInfo> {
+    let format = open(path)?;
+    let (_, params) = audio_track(&*format)?;
+
+    let duration_ms = match (params.n_frames, params.sample_rate) {
+        (Some(frames), Some(rate)) => frames * 1000 / rate as u64,
+        _ => decode(path)?.duration_ms(),
+    };
+
+    Ok(MediaInfo {
+        format: path.extension().map(|e| e.to_string_lossy().into_owned()),
+        codec: symphonia::default::get_codecs().get_codec(params.codec).map(|c| c.short_name.to_owned()),
+        sample_rate: params.sample_rate,
+        channels: params.channels.map(|c| c.count() as u32),
+        bit_rate: None,
+        duration_ms,
+    })
+}
+
+pub fn decode(path: &Path) -> Result<Pcm> {
+    let mut format = open(path)?;
+    let (track_id, params) = audio_track(&*format)?;
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&params, &DecoderOptions::default())
+        .map_err(to_error)?;
+
+    let mut pcm = Pcm {
+        sample_rate: params.sample_rate.unwrap_or(0),
+        samples: vec![],
+    };
+
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(err)) if err.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(to_error(err)),
+        };
+
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            Err(SymphoniaError::DecodeError(_)) => continue, // skip corrupted packets
+            Err(err) => return Err(to_error(err)),
+        };
+
+        let spec = *decoded.spec();
+        let channels = spec.channels.count();
+        pcm.sample_rate = spec.rate;
+
+        let mut buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+        buffer.copy_interleaved_ref(decoded);
+
+        pcm.samples.extend(buffer.samples()
+            .chunks_exact(channels)
+            .map(|frame| frame.iter().sum::<f32>() / channels as f32));
+    }
+
+    if pcm.sample_rate == 0 {
+        return Err(Error::Decode("unknown sample rate".into()));
+    }
+
+    Ok(pcm)
+}
+
+fn to_error(err: SymphoniaError) -> Error {
+    match err {
+        SymphoniaError::IoError(err) => Error::IO(err),
+        err => Error::Decode(err.to_string()),
+    }
+}
+
+/// Renders a spectrogram like ffmpeg's `showspectrumpic` with a log (dB) intensity scale: time
+/// from left to right, frequencies from 0 (bottom) to `stop_hz` (top).
+pub fn render_spectrogram<T: AsRef<Path>>(
+    samples: &[f32],
+    sample_rate: u32,
+    (width, height): (usize, usize),
+    stop_hz: f32,
+    output: T,
+) -> Result<()> {
+    if width == 0 || height == 0 || samples.is_empty() {
+        return Err(Error::Decode("empty spectrogram".into()));
+    }
+
+    // Enough resolution for one FFT bin per row.
+    let fft_size = ((2 * height) as f32 * sample_rate as f32 / stop_hz).max(2.0) as usize;
+    let fft_size = fft_size.next_power_of_two();
+    let fft = FftPlanner::new().plan_fft_forward(fft_size);
+    let window: Vec<f32> = (0..fft_size)
+        .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / (fft_size - 1) as f32).cos())
+        .collect();
+
+    let mut image = RgbImage::new(width as u32, height as u32);
+    let mut buffer = vec![Complex::new(0.0, 0.0); fft_size];
+
+    for x in 0..width {
+        let center = x * samples.len() / width;
+        let start = center as isize - fft_size as isize / 2;
+
+        for (i, value) in buffer.iter_mut().enumerate() {
+            let sample = usize::try_from(start + i as isize).ok()
+                .and_then(|j| samples.get(j))
+                .copied()
+                .unwrap_or(0.0);
+            *value = Complex::new(sample * window[i], 0.0);
+        }
+
+        fft.process(&mut buffer);
+
+        for y in 0..height {
+            let freq = stop_hz * (height - 1 - y) as f32 / height as f32;
+            let bin = ((freq * fft_size as f32 / sample_rate as f32) as usize).min(fft_size / 2);
+            let magnitude = buffer[bin].norm() / (fft_size as f32 / 4.0);
+            let db = 20.0 * magnitude.max(1e-6).log10();
+            let intensity = ((db + 120.0) / 120.0).clamp(0.0, 1.0);
+
+            image.put_pixel(x as u32, y as u32, color(intensity));
+        }
+    }
+
+    image.save(output).map_err(|e| Error::Decode(e.to_string()))
+}
+
+/// Approximates ffmpeg's `intensity` color scheme.
+fn color(intensity: f32) -> Rgb<u8> {
+    const STOPS: [(f32, [f32; 3]); 6] = [
+        (0.0, [0.0, 0.0, 0.0]),
+        (0.15, [64.0, 0.0, 128.0]),
+        (0.35, [200.0, 0.0, 100.0]),
+        (0.6, [255.0, 80.0, 0.0]),
+        (0.85, [255.0, 220.0, 0.0]),
+        (1.0, [255.0, 255.0, 255.0]),
+    ];
+
+    let i = STOPS.iter().position(|(s, _)| *s >= intensity).unwrap_or(STOPS.len() - 1).max(1);
+    let (s0, c0) = STOPS[i - 1];
+    let (s1, c1) = STOPS[i];
+    let t = ((intensity - s0) / (s1 - s0)).clamp(0.0, 1.0);
+
+    Rgb([0, 1, 2].map(|k| (c0[k] + (c1[k] - c0[k]) * t) as u8))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::f32::consts::PI;
+    use std::path::Path;
+
+    /// Writes a mono 16-bit WAV file with a sine tone.
+    pub fn write_sine_wav(path: &Path, sample_rate: u32, freq: f32, duration_ms: u32) {
+        let n = (sample_rate * duration_ms / 1000) as usize;
+        let data: Vec<u8> = (0..n)
+            .map(|i| ((2.0 * PI * freq * i as f32 / sample_rate as f32).sin() * 16000.0) as i16)
+            .flat_map(|s| s.to_le_bytes())
+            .collect();
+
+        let mut wav = vec![];
+        wav.extend(b"RIFF");
+        wav.extend(&(36 + data.len() as u32).to_le_bytes());
+        wav.extend(b"WAVEfmt ");
+        wav.extend(&16u32.to_le_bytes());
+        wav.extend(&1u16.to_le_bytes()); // PCM
+        wav.extend(&1u16.to_le_bytes()); // mono
+        wav.extend(&sample_rate.to_le_bytes());
+        wav.extend(&(sample_rate * 2).to_le_bytes());
+        wav.extend(&2u16.to_le_bytes());
+        wav.extend(&16u16.to_le_bytes());
+        wav.extend(b"data");
+        wav.extend(&(data.len() as u32).to_le_bytes());
+        wav.extend(data);
+
+        std::fs::write(path, wav).unwrap();
+    }
+
+    #[test]
+    fn decode() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("sine.wav");
+        write_sine_wav(&path, 8000, 440.0, 1500);
+
+        let info = super::probe(&path).unwrap();
+        assert_eq!(info.duration_ms, 1500);
+        assert_eq!(info.sample_rate, Some(8000));
+        assert_eq!(info.channels, Some(1));
+
+        let pcm = super::decode(&path).unwrap();
+        assert_eq!(pcm.samples.len(), 12000);
+        assert!(pcm.samples.iter().cloned().fold(0.0, f32::max) > 0.45);
+        assert_eq!(pcm.to_i16(4000).len(), 6000);
+
+        assert!(super::probe(&tempdir.path().join("missing.wav")).is_err());
+
+        tempdir.close().unwrap();
+    }
+
+    #[test]
+    fn render_spectrogram() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("sine.wav");
+        write_sine_wav(&path, 16000, 2000.0, 1000);
+
+        let pcm = super::decode(&path).unwrap();
+        let output = tempdir.path().join("sine.jpg");
+        super::render_spectrogram(&pcm.samples, pcm.sample_rate, (10, 64), 8000.0, &output).unwrap();
+
+        let image = image::open(&output).unwrap().to_rgb8();
+        assert_eq!(image.dimensions(), (10, 64));
+
+        // The 2 kHz tone is a quarter of the way up; it should be the brightest row.
+        let brightness = |y: u32| image.get_pixel(5, y).0.iter().map(|c| *c as u32).sum::<u32>();
+        let brightest = (0..64).max_by_key(|y| brightness(*y)).unwrap();
+        assert!((46..=50).contains(&brightest), "{}", brightest);
+
+        tempdir.close().unwrap();
+    }
+}
@@ -1,8 +1,11 @@
+//! The error type shared by the whole crate.
+
 use zip;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Debug)]
+#[allow(clippy::upper_case_acronyms, clippy::enum_variant_names)]
 pub enum Error {
     Unknown,
     Generic(String),
@@ -14,23 +17,37 @@ pub enum Error {
     JSON(serde_json::Error),
     YoutubeDL(String),
     FFMPEG(String),
+    Rejected(String),
+    #[cfg(feature = "native-decode")]
+    Decode(String),
+    #[cfg(feature = "parquet")]
+    Parquet(parquet::errors::ParquetError),
+    #[cfg(feature = "predict")]
+    Model(String),
 }
 
 impl std::fmt::Display for Error {
     fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
         use Error::*;
 
-        match &self {
-            &Unknown => write!(fmt, "unknown"),
-            &Generic(ref s) => s.fmt(fmt),
-            &Checksum(ref a, ref b) => write!(fmt, "mismatched checksum: '{}' != '{}'", a, b),
-            &IO(ref err) => err.fmt(fmt),
-            &Http(ref err) => err.fmt(fmt),
-            &UnknownGenre(ref text) => write!(fmt, "unknown genre: '{}'", text),
-            &ParseError(ref s) => write!(fmt, "failed to parse: '{}'", s),
-            &JSON(ref err) => err.fmt(fmt),
-            &YoutubeDL(ref err) => err.fmt(fmt),
-            &FFMPEG(ref err) => err.fmt(fmt),
+        match self {
+            Unknown => write!(fmt, "unknown"),
+            Generic(s) => s.fmt(fmt),
+            Checksum(a, b) => write!(fmt, "mismatched checksum: '{}' != '{}'", a, b),
+            IO(err) => err.fmt(fmt),
+            Http(err) => err.fmt(fmt),
+            UnknownGenre(text) => write!(fmt, "unknown genre: '{}'", text),
+            ParseError(s) => write!(fmt, "failed to parse: '{}'", s),
+            JSON(err) => err.fmt(fmt),
+            YoutubeDL(err) => err.fmt(fmt),
+            FFMPEG(err) => err.fmt(fmt),
+            Rejected(reason) => write!(fmt, "rejected: {}", reason),
+            #[cfg(feature = "native-decode")]
+            Decode(err) => write!(fmt, "failed to decode: {}", err),
+            #[cfg(feature = "parquet")]
+            Parquet(err) => err.fmt(fmt),
+            #[cfg(feature = "predict")]
+            Model(err) => write!(fmt, "model: {}", err),
         }
     }
 }
@@ -81,4 +98,18 @@ impl From<serde_json::Error> for Error {
     fn from(err: serde_json::Error) -> Self {
         Error::JSON(err)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(feature = "parquet")]
+impl From<parquet::errors::ParquetError> for Error {
+    fn from(err: parquet::errors::ParquetError) -> Self {
+        Error::Parquet(err)
+    }
+}
+
+#[cfg(feature = "predict")]
+impl From<tract_onnx::prelude::TractError> for Error {
+    fn from(err: tract_onnx::prelude::TractError) -> Self {
+        Error::Model(err.to_string())
+    }
+}
@@ -0,0 +1,558 @@
+//! Scores a model's predictions against the labels and split of the dataset.
+
+use crate::config::{Config, LabelEncoding};
+use crate::dataset::{Dataset, Split};
+use crate::error::*;
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+/// The threshold used when none is tuned.
+pub const DEFAULT_THRESHOLD: f64 = 0.5;
+
+/// The labels of each movie, as given to the model.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Truth {
+    pub labels: Vec<String>,
+    /// Movie key, whether it has each label, and its split.
+    pub samples: Vec<(String, Vec<bool>, Split)>,
+}
+
+impl Truth {
+    pub fn from_dataset(dataset: &Dataset) -> Truth {
+        let samples = dataset.samples.iter()
+            .map(|s| {
+                let targets = dataset.labels.iter().map(|l| s.labels.contains(l)).collect();
+                (s.movie.youtube_id.clone(), targets, s.split)
+            })
+            .collect();
+
+        Truth {
+            labels: dataset.labels.clone(),
+            samples,
+        }
+    }
+
+    /// Reads an extracted export: `labels.txt`, `split.csv`, and `movies_selected.csv` as
+    /// described by its `config.json`.
+    pub fn from_export<T: AsRef<Path>>(dir: T) -> Result<Truth> {
+        let dir = dir.as_ref();
+        let labels: Vec<String> = fs::read_to_string(dir.join("labels.txt"))?
+            .lines()
+            .filter(|l| !l.is_empty())
+            .map(|l| l.to_owned())
+            .collect();
+        let config = Config::load(dir.join("config.json"))?;
+        let headers = &config.csv.headers;
+
+        let mut splits = HashMap::new();
+        for record in csv::Reader::from_path(dir.join("split.csv"))?.records() {
+            let record = record?;
+            splits.insert(record[0].to_owned(), Split::from_text(&record[1])?);
+        }
+
+        let mut reader = csv::Reader::from_path(dir.join("movies_selected.csv"))?;
+        let columns = reader.headers()?.clone();
+        let column = |name: &str| columns.iter().position(|c| c == name)
+            .ok_or_else(|| Error::Generic(format!("movies_selected.csv has no column '{}'", name)));
+        let id = column(&headers.id)?;
+        let label_columns = match config.csv.encoding {
+            LabelEncoding::MultiHot => labels.iter().map(|l| column(l)).collect::<Result<Vec<_>>>()?,
+            LabelEncoding::Pipe | LabelEncoding::ClassIds => vec![column(&headers.labels)?],
+        };
+
+        let mut samples = vec![];
+        for record in reader.records() {
+            let record = record?;
+            let targets = match config.csv.encoding {
+                LabelEncoding::MultiHot => label_columns.iter().map(|i| &record[*i] == "1").collect(),
+                LabelEncoding::Pipe => {
+                    let values: Vec<_> = record[label_columns[0]].split('|').collect();
+                    labels.iter().map(|l| values.contains(&&**l)).collect()
+                },
+                LabelEncoding::ClassIds => {
+                    let values: Vec<_> = record[label_columns[0]].split('|').collect();
+                    (0..labels.len()).map(|i| values.contains(&&*i.to_string())).collect()
+                },
+            };
+
+            let key = record[id].to_owned();
+            let split = *splits.get(&key).ok_or_else(|| format!("{} is not in split.csv", key))?;
+            samples.push((key, targets, split));
+        }
+
+        Ok(Truth { labels, samples })
+    }
+}
+
+/// Reads a CSV of predictions: the movie key in the first column, then one probability column per
+/// label, named after it. Columns are returned in the order of `labels`.
+pub fn read_predictions<T: AsRef<Path>>(path: T, labels: &[String]) -> Result<BTreeMap<String, Vec<f64>>> {
+    let mut reader = csv::Reader::from_path(path)?;
+    let columns = reader.headers()?.clone();
+
+    for column in columns.iter().skip(1) {
+        if !labels.iter().any(|l| l == column) {
+            Err(format!("predictions have a column '{}' which is not a label", column))?;
+        }
+    }
+    let indices = labels.iter()
+        .map(|l| columns.iter().position(|c| c == l).ok_or_else(|| format!("predictions have no column '{}'", l)))
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+
+    let mut predictions = BTreeMap::new();
+    for record in reader.records() {
+        let record = record?;
+        let scores = indices.iter()
+            .map(|i| record[*i].parse().map_err(|_| Error::ParseError(record[*i].to_owned())))
+            .collect::<Result<Vec<f64>>>()?;
+        predictions.insert(record[0].to_owned(), scores);
+    }
+
+    Ok(predictions)
+}
+
+/// Counts and scores of one label.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ClassMetrics {
+    pub label: String,
+    pub threshold: f64,
+    pub support: usize,
+    pub true_positives: usize,
+    pub false_positives: usize,
+    pub false_negatives: usize,
+    pub precision: f64,
+    pub recall: f64,
+    pub f1: f64,
+    /// Undefined when the label is always or never present.
+    pub roc_auc: Option<f64>,
+    /// Average precision. Undefined when the label is never present.
+    pub pr_auc: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Average {
+    pub precision: f64,
+    pub recall: f64,
+    pub f1: f64,
+}
+
+/// Multi-label metrics over a set of samples, with a threshold per label.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Metrics {
+    pub samples: usize,
+    pub classes: Vec<ClassMetrics>,
+    /// Pools the counts of all labels.
+    pub micro: Average,
+    /// Averages the metrics of the labels.
+    #[serde(rename = "macro")]
+    pub macro_: Average,
+    /// Mean of the defined per-label ROC-AUCs.
+    pub roc_auc: Option<f64>,
+    /// Mean of the defined per-label PR-AUCs.
+    pub pr_auc: Option<f64>,
+    /// Fraction of wrong label decisions.
+    pub hamming_loss: f64,
+    /// Fraction of samples with every label right.
+    pub subset_accuracy: f64,
+}
+
+impl Metrics {
+    /// `targets` and `scores` have one row per sample and one column per label.
+    pub fn new(labels: &[String], targets: &[Vec<bool>], scores: &[Vec<f64>], thresholds: &[f64]) -> Metrics {
+        let predicted: Vec<Vec<bool>> = scores.iter()
+            .map(|row| row.iter().zip(thresholds).map(|(s, t)| s >= t).collect())
+            .collect();
+
+        let classes: Vec<_> = labels.iter().enumerate()
+            .map(|(i, label)| {
+                let column: Vec<_> = targets.iter().map(|t| t[i]).collect();
+                let column_scores: Vec<_> = scores.iter().map(|s| s[i]).collect();
+                let (tp, fp, fn_) = counts(&column, predicted.iter().map(|p| p[i]));
+                let (precision, recall, f1) = precision_recall_f1(tp, fp, fn_);
+
+                ClassMetrics {
+                    label: label.clone(),
+                    threshold: thresholds[i],
+                    support: tp + fn_,
+                    true_positives: tp,
+                    false_positives: fp,
+                    false_negatives: fn_,
+                    precision,
+                    recall,
+                    f1,
+                    roc_auc: roc_auc(&column, &column_scores),
+                    pr_auc: average_precision(&column, &column_scores),
+                }
+            })
+            .collect();
+
+        let sum = |f: fn(&ClassMetrics) -> usize| classes.iter().map(f).sum::<usize>();
+        let (precision, recall, f1) = precision_recall_f1(
+            sum(|c| c.true_positives),
+            sum(|c| c.false_positives),
+            sum(|c| c.false_negatives),
+        );
+        let micro = Average { precision, recall, f1 };
+
+        let count = classes.len().max(1) as f64;
+        let macro_ = Average {
+            precision: classes.iter().map(|c| c.precision).sum::<f64>() / count,
+            recall: classes.iter().map(|c| c.recall).sum::<f64>() / count,
+            f1: classes.iter().map(|c| c.f1).sum::<f64>() / count,
+        };
+
+        let decisions = (targets.len() * labels.len()).max(1) as f64;
+        let wrong = targets.iter().zip(&predicted)
+            .map(|(t, p)| t.iter().zip(p).filter(|(t, p)| t != p).count())
+            .sum::<usize>();
+        let exact = targets.iter().zip(&predicted).filter(|(t, p)| t == p).count();
+
+        Metrics {
+            samples: targets.len(),
+            micro,
+            macro_,
+            roc_auc: mean(classes.iter().filter_map(|c| c.roc_auc)),
+            pr_auc: mean(classes.iter().filter_map(|c| c.pr_auc)),
+            hamming_loss: wrong as f64 / decisions,
+            subset_accuracy: exact as f64 / targets.len().max(1) as f64,
+            classes,
+        }
+    }
+}
+
+fn counts<I: Iterator<Item = bool>>(targets: &[bool], predicted: I) -> (usize, usize, usize) {
+    let (mut tp, mut fp, mut fn_) = (0, 0, 0);
+    for (target, predicted) in targets.iter().zip(predicted) {
+        match (*target, predicted) {
+            (true, true) => tp += 1,
+            (false, true) => fp += 1,
+            (true, false) => fn_ += 1,
+            (false, false) => {},
+        }
+    }
+
+    (tp, fp, fn_)
+}
+
+/// Precision, recall and F1, each 0 when undefined.
+fn precision_recall_f1(tp: usize, fp: usize, fn_: usize) -> (f64, f64, f64) {
+    let ratio = |a: usize, b: usize| if b == 0 { 0.0 } else { a as f64 / b as f64 };
+    (ratio(tp, tp + fp), ratio(tp, tp + fn_), ratio(2 * tp, 2 * tp + fp + fn_))
+}
+
+fn mean<I: Iterator<Item = f64>>(values: I) -> Option<f64> {
+    let (sum, count) = values.fold((0.0, 0), |(s, c), v| (s + v, c + 1));
+    if count == 0 { None } else { Some(sum / count as f64) }
+}
+
+/// Area under the ROC curve: the chance that a positive sample scores above a negative one, with
+/// ties counting half.
+pub fn roc_auc(targets: &[bool], scores: &[f64]) -> Option<f64> {
+    let positives = targets.iter().filter(|t| **t).count();
+    let negatives = targets.len() - positives;
+    if positives == 0 || negatives == 0 {
+        return None;
+    }
+
+    let mut order: Vec<_> = (0..scores.len()).collect();
+    order.sort_by(|a, b| scores[*a].total_cmp(&scores[*b]));
+
+    // Tied scores share the mean of their ranks.
+    let mut rank_sum = 0.0;
+    let mut start = 0;
+    while start < order.len() {
+        let mut end = start;
+        while end < order.len() && scores[order[end]] == scores[order[start]] {
+            end += 1;
+        }
+        let rank = (start + end + 1) as f64 / 2.0;
+        rank_sum += rank * order[start..end].iter().filter(|i| targets[**i]).count() as f64;
+        start = end;
+    }
+
+    let positives = positives as f64;
+    Some((rank_sum - positives * (positives + 1.0) / 2.0) / (positives * negatives as f64))
+}
+
+/// Area under the precision-recall curve as average precision: the precision at each distinct
+/// score, weighted by the recall gained there.
+pub fn average_precision(targets: &[bool], scores: &[f64]) -> Option<f64> {
+    let positives = targets.iter().filter(|t| **t).count();
+    if positives == 0 {
+        return None;
+    }
+
+    let mut order: Vec<_> = (0..scores.len()).collect();
+    order.sort_by(|a, b| scores[*b].total_cmp(&scores[*a]));
+
+    let (mut tp, mut area) = (0, 0.0);
+    let mut start = 0;
+    while start < order.len() {
+        let mut end = start;
+        let mut gained = 0;
+        while end < order.len() && scores[order[end]] == scores[order[start]] {
+            gained += targets[order[end]] as usize;
+            end += 1;
+        }
+        tp += gained;
+        area += gained as f64 / positives as f64 * tp as f64 / end as f64;
+        start = end;
+    }
+
+    Some(area)
+}
+
+/// The threshold of each label giving the best F1 on the samples, keeping the default unless
+/// another one is strictly better.
+pub fn tune_thresholds(targets: &[Vec<bool>], scores: &[Vec<f64>], labels: usize) -> Vec<f64> {
+    (0..labels)
+        .map(|i| {
+            let column: Vec<_> = targets.iter().map(|t| t[i]).collect();
+            let f1 = |threshold: f64| {
+                let (tp, fp, fn_) = counts(&column, scores.iter().map(|s| s[i] >= threshold));
+                precision_recall_f1(tp, fp, fn_).2
+            };
+
+            let mut candidates: Vec<_> = scores.iter().map(|s| s[i]).collect();
+            candidates.sort_by(f64::total_cmp);
+            candidates.dedup();
+
+            let mut best = (DEFAULT_THRESHOLD, f1(DEFAULT_THRESHOLD));
+            for threshold in candidates {
+                let score = f1(threshold);
+                if score > best.1 {
+                    best = (threshold, score);
+                }
+            }
+
+            best.0
+        })
+        .collect()
+}
+
+/// Metrics of each split at the default threshold, and of the validation split at thresholds
+/// tuned on it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Evaluation {
+    pub labels: Vec<String>,
+    pub splits: BTreeMap<String, Metrics>,
+    /// Tuned on the validation split, so `tuned` overestimates how well they generalize.
+    pub thresholds: Vec<f64>,
+    pub tuned: Option<Metrics>,
+    /// Movies of the dataset without a prediction.
+    pub missing: usize,
+    /// Predictions of movies not in the dataset.
+    pub unknown: usize,
+}
+
+impl Evaluation {
+    pub fn new(truth: &Truth, predictions: &BTreeMap<String, Vec<f64>>) -> Evaluation {
+        let default = vec![DEFAULT_THRESHOLD; truth.labels.len()];
+        let mut splits = BTreeMap::new();
+        let mut validation = None;
+
+        for split in [Split::Train, Split::Validation] {
+            let (targets, scores): (Vec<_>, Vec<_>) = truth.samples.iter()
+                .filter(|(_, _, s)| *s == split)
+                .filter_map(|(key, targets, _)| predictions.get(key).map(|p| (targets.clone(), p.clone())))
+                .unzip();
+            if targets.is_empty() {
+                continue;
+            }
+
+            splits.insert(split.to_string(), Metrics::new(&truth.labels, &targets, &scores, &default));
+            if split == Split::Validation {
+                validation = Some((targets, scores));
+            }
+        }
+
+        let (thresholds, tuned) = match validation {
+            Some((targets, scores)) => {
+                let thresholds = tune_thresholds(&targets, &scores, truth.labels.len());
+                let tuned = Metrics::new(&truth.labels, &targets, &scores, &thresholds);
+                (thresholds, Some(tuned))
+            },
+            None => (default, None),
+        };
+
+        let missing = truth.samples.iter().filter(|(key, _, _)| !predictions.contains_key(key)).count();
+        let unknown = predictions.keys().filter(|key| !truth.samples.iter().any(|(k, _, _)| k == *key)).count();
+
+        Evaluation {
+            labels: truth.labels.clone(),
+            splits,
+            thresholds,
+            tuned,
+            missing,
+            unknown,
+        }
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let mut md = String::new();
+        writeln!(md, "# Evaluation\n").unwrap();
+
+        if self.missing > 0 || self.unknown > 0 {
+            writeln!(
+                md,
+                "{} movies of the dataset have no prediction; {} predictions are not in the dataset.\n",
+                self.missing, self.unknown,
+            ).unwrap();
+        }
+
+        for (split, metrics) in &self.splits {
+            writeln!(md, "## {} (threshold {})\n", split, DEFAULT_THRESHOLD).unwrap();
+            write_metrics(&mut md, metrics);
+        }
+
+        if let Some(tuned) = &self.tuned {
+            writeln!(md, "## validation (tuned thresholds)\n").unwrap();
+            writeln!(md, "Thresholds maximize the F1 of each label on the validation split itself, so these \
+                numbers are optimistic.\n").unwrap();
+            write_metrics(&mut md, tuned);
+        }
+
+        md
+    }
+
+    /// Writes `evaluation.json` and `evaluation.md` into `dir`, returning the path of the latter.
+    pub fn write<T: AsRef<Path>>(&self, dir: T) -> Result<PathBuf> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+
+        serde_json::to_writer_pretty(fs::File::create(dir.join("evaluation.json"))?, self)?;
+        let path = dir.join("evaluation.md");
+        fs::write(&path, self.to_markdown())?;
+
+        Ok(path)
+    }
+}
+
+fn write_metrics(md: &mut String, metrics: &Metrics) {
+    let optional = |v: Option<f64>| v.map(|v| format!("{:.3}", v)).unwrap_or_else(|| "-".to_owned());
+
+    writeln!(md, "{} movies. Hamming loss {:.4}, subset accuracy {:.3}.\n",
+        metrics.samples, metrics.hamming_loss, metrics.subset_accuracy).unwrap();
+    writeln!(md, "| Label | Threshold | Support | Precision | Recall | F1 | ROC-AUC | PR-AUC |").unwrap();
+    writeln!(md, "|---|---:|---:|---:|---:|---:|---:|---:|").unwrap();
+    for c in &metrics.classes {
+        writeln!(md, "| {} | {:.3} | {} | {:.3} | {:.3} | {:.3} | {} | {} |",
+            c.label, c.threshold, c.support, c.precision, c.recall, c.f1, optional(c.roc_auc), optional(c.pr_auc)).unwrap();
+    }
+    for (name, average, roc, pr) in [
+        ("Micro", &metrics.micro, None, None),
+        ("Macro", &metrics.macro_, metrics.roc_auc, metrics.pr_auc),
+    ] {
+        writeln!(md, "| {} | | | {:.3} | {:.3} | {:.3} | {} | {} |",
+            name, average.precision, average.recall, average.f1, optional(roc), optional(pr)).unwrap();
+    }
+    writeln!(md).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{average_precision, read_predictions, roc_auc, tune_thresholds, Evaluation, Metrics, Truth};
+    use crate::config::{Config, LabelEncoding};
+    use crate::dataset::Split;
+
+    use std::collections::BTreeMap;
+    use std::fs;
+
+    #[test]
+    fn curves() {
+        assert_eq!(roc_auc(&[false, false, true, true], &[0.1, 0.2, 0.3, 0.4]), Some(1.0));
+        assert_eq!(roc_auc(&[true, true, false, false], &[0.1, 0.2, 0.3, 0.4]), Some(0.0));
+        assert_eq!(roc_auc(&[true, false], &[0.5, 0.5]), Some(0.5));
+        assert_eq!(roc_auc(&[false, true, false, true], &[0.1, 0.35, 0.4, 0.8]), Some(0.75));
+        assert_eq!(roc_auc(&[true, true], &[0.1, 0.2]), None);
+
+        assert_eq!(average_precision(&[false, true], &[0.2, 0.9]), Some(1.0));
+        // Ranked: +, -, +. Precision 1 at recall 0.5, then 2/3 at recall 1.
+        let ap = average_precision(&[true, false, true], &[0.9, 0.8, 0.7]).unwrap();
+        assert!((ap - (0.5 + 0.5 * 2.0 / 3.0)).abs() < 1e-9);
+        assert_eq!(average_precision(&[false], &[0.3]), None);
+    }
+
+    #[test]
+    fn metrics() {
+        let labels = ["a".to_owned(), "b".to_owned()];
+        let targets = [vec![true, false], vec![true, true], vec![false, false]];
+        let scores = [vec![0.9, 0.6], vec![0.4, 0.7], vec![0.2, 0.1]];
+        let metrics = Metrics::new(&labels, &targets, &scores, &[0.5, 0.5]);
+
+        let a = &metrics.classes[0];
+        assert_eq!((a.true_positives, a.false_positives, a.false_negatives), (1, 0, 1));
+        assert_eq!((a.precision, a.recall), (1.0, 0.5));
+        let b = &metrics.classes[1];
+        assert_eq!((b.true_positives, b.false_positives, b.false_negatives), (1, 1, 0));
+
+        assert_eq!((metrics.micro.precision, metrics.micro.recall), (2.0 / 3.0, 2.0 / 3.0));
+        assert_eq!(metrics.macro_.precision, 0.75);
+        assert_eq!(metrics.hamming_loss, 2.0 / 6.0);
+        assert_eq!(metrics.subset_accuracy, 1.0 / 3.0);
+
+        assert_eq!(tune_thresholds(&targets, &scores, 2), [0.4, 0.7]);
+        let tuned = Metrics::new(&labels, &targets, &scores, &[0.4, 0.7]);
+        assert_eq!(tuned.subset_accuracy, 1.0);
+    }
+
+    #[test]
+    fn evaluation() {
+        let truth = Truth {
+            labels: vec!["a".to_owned()],
+            samples: vec![
+                ("t".to_owned(), vec![true], Split::Train),
+                ("v1".to_owned(), vec![true], Split::Validation),
+                ("v2".to_owned(), vec![false], Split::Validation),
+                ("gone".to_owned(), vec![false], Split::Validation),
+            ],
+        };
+        let predictions: BTreeMap<_, _> = [("t", 0.8), ("v1", 0.3), ("v2", 0.1), ("other", 0.5)].iter()
+            .map(|(k, p)| (k.to_string(), vec![*p]))
+            .collect();
+
+        let evaluation = Evaluation::new(&truth, &predictions);
+        assert_eq!((evaluation.missing, evaluation.unknown), (1, 1));
+        assert_eq!(evaluation.splits["train"].samples, 1);
+        assert_eq!(evaluation.splits["validation"].micro.recall, 0.0);
+        assert_eq!(evaluation.thresholds, [0.3]);
+        assert_eq!(evaluation.tuned.as_ref().unwrap().micro.f1, 1.0);
+
+        let md = evaluation.to_markdown();
+        assert!(md.contains("## validation (tuned thresholds)"));
+        assert!(md.contains("| a | 0.300 | 1 | 1.000 | 1.000 | 1.000 | 1.000 | 1.000 |"));
+    }
+
+    #[test]
+    fn export() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let dir = tempdir.path();
+
+        let mut config = Config::default();
+        config.csv.encoding = LabelEncoding::Pipe;
+        fs::write(dir.join("config.json"), serde_json::to_vec(&config).unwrap()).unwrap();
+        fs::write(dir.join("labels.txt"), "Action\nDrama\n").unwrap();
+        fs::write(dir.join("split.csv"), "Id,Split\nx,train\ny,validation\n").unwrap();
+        fs::write(dir.join("movies_selected.csv"), "Id,Name,Labels\nx,X,Action|Drama\ny,Y,Drama\n").unwrap();
+
+        let truth = Truth::from_export(dir).unwrap();
+        assert_eq!(truth.labels, ["Action", "Drama"]);
+        assert_eq!(truth.samples, [
+            ("x".to_owned(), vec![true, true], Split::Train),
+            ("y".to_owned(), vec![false, true], Split::Validation),
+        ]);
+
+        let path = dir.join("predictions.csv");
+        fs::write(&path, "Id,Drama,Action\nx,0.9,0.1\n").unwrap();
+        assert_eq!(read_predictions(&path, &truth.labels).unwrap()["x"], [0.1, 0.9]);
+        fs::write(&path, "Id,Drama,Comedy\nx,0.9,0.1\n").unwrap();
+        assert!(read_predictions(&path, &truth.labels).is_err());
+
+        tempdir.close().unwrap();
+    }
+}
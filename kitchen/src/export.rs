@@ -0,0 +1,699 @@
+//! Packages a dataset as a versioned archive, or as TFRecord, WebDataset or Parquet shards.
+
+use crate::balance::Balance;
+use crate::config::{Config, SelectionConfig};
+use crate::dataset::{Dataset, Sample, Split};
+use crate::error::*;
+use crate::layout::Layout;
+use crate::movie::TrailerSource;
+use crate::tfrecord::{self, Feature, TFRecordWriter};
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use serde_json::json;
+
+/// Compression level of exported archives.
+const ZSTD_LEVEL: i32 = 19;
+
+/// What `export` writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// A `.tar.zst` with the spectrograms as JPEG files and a dataset card.
+    Archive,
+    /// Shards of `tf.train.Example` records.
+    TFRecord,
+    /// Shards of Parquet tables, with the `parquet` feature.
+    Parquet,
+    /// Shards of WebDataset tar files.
+    WebDataset,
+}
+
+impl ExportFormat {
+    pub fn from_text(text: &str) -> Result<ExportFormat> {
+        match text {
+            "archive" => Ok(ExportFormat::Archive),
+            "tfrecord" => Ok(ExportFormat::TFRecord),
+            "parquet" => Ok(ExportFormat::Parquet),
+            "webdataset" => Ok(ExportFormat::WebDataset),
+            x => Err(Error::ParseError(format!("export format: {}", x))),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            ExportFormat::Archive => "archive",
+            ExportFormat::TFRecord => "tfrecord",
+            ExportFormat::Parquet => "parquet",
+            ExportFormat::WebDataset => "webdataset",
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Archive => "tar.zst",
+            ExportFormat::TFRecord => "tfrecord",
+            ExportFormat::Parquet => "parquet",
+            ExportFormat::WebDataset => "tar",
+        }
+    }
+}
+
+/// A sample as written into shards: its multi-hot labels, in the order of the label set, and the
+/// spectrogram JPEG.
+struct Record<'a> {
+    sample: &'a Sample<'a>,
+    multi_hot: Vec<u8>,
+    spectrogram: Vec<u8>,
+}
+
+impl Record<'_> {
+    fn metadata(&self) -> serde_json::Value {
+        let movie = self.sample.movie;
+
+        json!({
+            "id": movie.youtube_id,
+            "name": movie.name,
+            "year": movie.year,
+            "labels": self.sample.labels,
+            "multi_hot": self.multi_hot,
+            "primary": self.sample.primary,
+            "weight": self.sample.weight,
+            "split": self.sample.split.to_string(),
+            "critics_number": movie.critics_number,
+            "metacritic_score": movie.metacritic_score,
+            "duration_ms": self.sample.artifact.duration_ms,
+        })
+    }
+}
+
+/// Writes the dataset to `exports/{name}-{version}.tar.zst`, with everything under a
+/// `{name}-{version}/` directory:
+///
+/// ```text
+/// README.md            dataset card
+/// movies_selected.csv  labels
+/// labels.txt           label set, one genre per line
+/// class_weights.json   per-label counts and `pos_weight` of the train split
+/// split.csv            train/validation assignment
+/// config.json          dataset config
+/// params.json          spectrogram parameters, read by `predict`
+/// spectrograms/        trailer-{id}.jpg
+/// MD5SUMS              checksums of all the files above, for `md5sum -c`
+/// ```
+///
+/// Files are added in a fixed order with fixed metadata, so the same dataset gives the same
+/// archive. Spectrograms are streamed into the archive and hashed as they go, so the dataset
+/// doesn't have to fit in memory. Existing exports are never overwritten.
+pub fn export(layout: &Layout, dataset: &Dataset, config: &Config, name: &str, version: &str) -> Result<PathBuf> {
+    let base = format!("{}-{}", name, version);
+    let path = layout.exports_dir().join(format!("{}.tar.zst", base));
+    if path.exists() {
+        return Err(Error::Generic(format!("export {} already exists", path.display())));
+    }
+
+    let mut files: Vec<(String, Vec<u8>)> = vec![];
+    files.push(("README.md".to_owned(), card(dataset, &config.selection, name, version).into_bytes()));
+
+    let mut csv = vec![];
+    dataset.write_csv(&mut csv, &config.csv)?;
+    files.push(("movies_selected.csv".to_owned(), csv));
+
+    let mut labels = vec![];
+    dataset.write_labels(&mut labels)?;
+    files.push(("labels.txt".to_owned(), labels));
+
+    files.push(("class_weights.json".to_owned(), serde_json::to_vec_pretty(&class_weights(dataset))?));
+
+    let mut split = csv::Writer::from_writer(vec![]);
+    split.write_record(["Id", "Split"])?;
+    for sample in &dataset.samples {
+        split.write_record([&sample.movie.youtube_id, &sample.split.to_string()])?;
+    }
+    files.push(("split.csv".to_owned(), split.into_inner().map_err(|e| e.into_error())?));
+
+    files.push(("config.json".to_owned(), serde_json::to_vec_pretty(config)?));
+    let params = dataset.samples.first().map(|s| &s.artifact.params);
+    files.push(("params.json".to_owned(), serde_json::to_vec_pretty(&params)?));
+
+    // Write next to the target first so a failed export doesn't leave a truncated archive.
+    let temp = tempfile::NamedTempFile::new_in(layout.exports_dir())?;
+    let encoder = zstd::Encoder::new(temp.as_file(), ZSTD_LEVEL)?;
+    let mut archive = tar::Builder::new(encoder);
+    let mut checksums = String::new();
+
+    for (file_name, data) in &files {
+        append(&mut archive, &format!("{}/{}", base, file_name), data)?;
+        writeln!(checksums, "{:x}  {}", md5::compute(data), file_name).unwrap();
+    }
+
+    for sample in &dataset.samples {
+        let spectrogram = sample.artifact.files.first()
+            .ok_or_else(|| format!("artifact of {} has no files", sample.movie.youtube_id))?;
+        let file = File::open(layout.resolve(spectrogram))?;
+        let size = file.metadata()?.len();
+        let mut reader = Md5Reader {
+            inner: file,
+            context: md5::Context::new(),
+        };
+
+        let file_name = format!("spectrograms/trailer-{}.jpg", sample.movie.youtube_id);
+        append_reader(&mut archive, &format!("{}/{}", base, file_name), size, &mut reader)?;
+        writeln!(checksums, "{:x}  {}", reader.context.compute(), file_name).unwrap();
+    }
+
+    append(&mut archive, &format!("{}/MD5SUMS", base), checksums.as_bytes())?;
+
+    archive.into_inner()?.finish()?.flush()?;
+    temp.persist_noclobber(&path).map_err(|e| e.error)?;
+
+    Ok(path)
+}
+
+/// Writes the dataset as shards of at most `shard_size` samples into
+/// `exports/{name}-{version}-{format}/`, named like `train-00000-of-00004.tfrecord`. Each split is
+/// sharded separately. `dataset.json` lists the label set, in the order of the multi-hot labels,
+/// the label mapping and the shards.
+pub fn export_shards(
+    layout: &Layout,
+    dataset: &Dataset,
+    format: ExportFormat,
+    name: &str,
+    version: &str,
+    shard_size: usize,
+) -> Result<PathBuf> {
+    let dir = layout.exports_dir().join(format!("{}-{}-{}", name, version, format.name()));
+    if dir.exists() {
+        return Err(Error::Generic(format!("export {} already exists", dir.display())));
+    }
+
+    // Write into a temporary directory first so a failed export doesn't look finished.
+    let temp = tempfile::tempdir_in(layout.exports_dir())?;
+    let mut shards = BTreeMap::new();
+
+    for split in &[Split::Train, Split::Validation] {
+        let samples: Vec<_> = dataset.samples.iter().filter(|s| s.split == *split).collect();
+        let chunks: Vec<_> = samples.chunks(shard_size.max(1)).collect();
+        let mut file_names = vec![];
+
+        for (i, chunk) in chunks.iter().enumerate() {
+            let records = chunk.iter()
+                .map(|sample| Ok(Record {
+                    sample,
+                    multi_hot: dataset.multi_hot(sample),
+                    spectrogram: read_spectrogram(layout, sample)?,
+                }))
+                .collect::<Result<Vec<_>>>()?;
+
+            let file_name = format!("{}-{:05}-of-{:05}.{}", split, i, chunks.len(), format.extension());
+            let path = temp.path().join(&file_name);
+
+            match format {
+                ExportFormat::TFRecord => write_tfrecord(&path, &records)?,
+                ExportFormat::WebDataset => write_webdataset(&path, &records)?,
+                #[cfg(feature = "parquet")]
+                ExportFormat::Parquet => write_parquet(&path, &dataset.labels, &records)?,
+                #[cfg(not(feature = "parquet"))]
+                ExportFormat::Parquet => return Err("parquet export needs the `parquet` feature")?,
+                ExportFormat::Archive => unreachable!("archives are not sharded"),
+            }
+
+            file_names.push(file_name);
+        }
+
+        shards.insert(split.to_string(), json!({
+            "samples": samples.len(),
+            "shards": file_names,
+        }));
+    }
+
+    let info = json!({
+        "name": name,
+        "version": version,
+        "format": format.name(),
+        "labels": dataset.labels,
+        "mapping": dataset.mapping,
+        "class_weights": class_weights(dataset),
+        "params": dataset.samples.first().map(|s| &s.artifact.params),
+        "splits": shards,
+    });
+    serde_json::to_writer_pretty(File::create(temp.path().join("dataset.json"))?, &info)?;
+
+    fs::rename(temp.keep(), &dir)?;
+
+    Ok(dir)
+}
+
+/// Train split positives and `pos_weight` of each label.
+fn class_weights(dataset: &Dataset) -> serde_json::Value {
+    let train: Vec<_> = dataset.samples.iter().filter(|s| s.split == Split::Train).collect();
+
+    dataset.labels.iter().zip(dataset.pos_weights()).enumerate()
+        .map(|(i, (label, pos_weight))| {
+            let positives = train.iter().filter(|s| dataset.multi_hot(s)[i] == 1).count();
+            (label.clone(), json!({ "positives": positives, "pos_weight": pos_weight }))
+        })
+        .collect::<serde_json::Map<_, _>>()
+        .into()
+}
+
+fn read_spectrogram(layout: &Layout, sample: &Sample) -> Result<Vec<u8>> {
+    let spectrogram = sample.artifact.files.first()
+        .ok_or_else(|| format!("artifact of {} has no files", sample.movie.youtube_id))?;
+
+    Ok(fs::read(layout.resolve(spectrogram))?)
+}
+
+/// Appends a file with fixed metadata, so archives only depend on their content.
+fn append<W: Write>(archive: &mut tar::Builder<W>, path: &str, data: &[u8]) -> Result<()> {
+    append_reader(archive, path, data.len() as u64, data)
+}
+
+/// Appends `size` bytes read from `data`, like `append`.
+fn append_reader<W: Write, R: Read>(archive: &mut tar::Builder<W>, path: &str, size: u64, data: R) -> Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(size);
+    header.set_mode(0o644);
+    header.set_mtime(0);
+    archive.append_data(&mut header, path, data)?;
+
+    Ok(())
+}
+
+/// Hashes what is read through it.
+struct Md5Reader<R> {
+    inner: R,
+    context: md5::Context,
+}
+
+impl<R: Read> Read for Md5Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.context.consume(&buf[..n]);
+        Ok(n)
+    }
+}
+
+fn write_tfrecord(path: &Path, records: &[Record]) -> Result<()> {
+    let mut writer = TFRecordWriter::new(BufWriter::new(File::create(path)?));
+
+    for record in records {
+        let movie = record.sample.movie;
+        let example = tfrecord::encode_example(&[
+            ("id", Feature::Bytes(vec![movie.youtube_id.as_bytes().to_vec()])),
+            ("name", Feature::Bytes(vec![movie.name.as_bytes().to_vec()])),
+            ("year", Feature::Ints(movie.year.map(|y| y as i64).into_iter().collect())),
+            ("critics_number", Feature::Ints(movie.critics_number.map(|n| n as i64).into_iter().collect())),
+            ("metacritic_score", Feature::Ints(movie.metacritic_score.map(|n| n as i64).into_iter().collect())),
+            ("duration_ms", Feature::Ints(vec![record.sample.artifact.duration_ms as i64])),
+            ("labels", Feature::Floats(record.multi_hot.iter().map(|l| *l as f32).collect())),
+            ("primary", Feature::Bytes(record.sample.primary.iter().map(|p| p.as_bytes().to_vec()).collect())),
+            ("weight", Feature::Floats(vec![record.sample.weight as f32])),
+            ("spectrogram", Feature::Bytes(vec![record.spectrogram.clone()])),
+        ]);
+
+        writer.write(&example)?;
+    }
+
+    writer.into_inner().flush()?;
+
+    Ok(())
+}
+
+/// Each sample is a `{id}.jpg` spectrogram and a `{id}.json` with its labels and metadata.
+fn write_webdataset(path: &Path, records: &[Record]) -> Result<()> {
+    let mut archive = tar::Builder::new(BufWriter::new(File::create(path)?));
+
+    for record in records {
+        let id = &record.sample.movie.youtube_id;
+        append(&mut archive, &format!("{}.jpg", id), &record.spectrogram)?;
+        append(&mut archive, &format!("{}.json", id), &serde_json::to_vec(&record.metadata())?)?;
+    }
+
+    archive.into_inner()?.flush()?;
+
+    Ok(())
+}
+
+/// One row per sample with a 0/1 column per label, in a single row group.
+#[cfg(feature = "parquet")]
+fn write_parquet(path: &Path, labels: &[String], records: &[Record]) -> Result<()> {
+    use parquet::basic::{Repetition, Type as PhysicalType};
+    use parquet::data_type::{ByteArray, ByteArrayType, DataType, DoubleType, Int32Type, Int64Type};
+    use parquet::file::properties::WriterProperties;
+    use parquet::file::writer::{SerializedFileWriter, SerializedRowGroupWriter};
+    use parquet::schema::parser::parse_message_type;
+    use parquet::schema::types::Type;
+
+    use std::sync::Arc;
+
+    fn column<T: DataType, W: Write + Send>(
+        row_group: &mut SerializedRowGroupWriter<W>,
+        values: &[T::T],
+        def_levels: Option<&[i16]>,
+    ) -> Result<()> {
+        let mut column = row_group.next_column()?.ok_or("missing parquet column")?;
+        column.typed::<T>().write_batch(values, def_levels, None)?;
+        column.close()?;
+        Ok(())
+    }
+
+    /// Values and definition levels of an optional column.
+    fn optional<T: Copy, U>(values: impl Iterator<Item = Option<T>>, f: impl Fn(T) -> U) -> (Vec<U>, Vec<i16>) {
+        let values: Vec<_> = values.collect();
+        let levels = values.iter().map(|v| v.is_some() as i16).collect();
+        (values.into_iter().flatten().map(f).collect(), levels)
+    }
+
+    let base = parse_message_type("message trailer {
+        REQUIRED BYTE_ARRAY id (UTF8);
+        REQUIRED BYTE_ARRAY name (UTF8);
+        OPTIONAL INT32 year;
+        OPTIONAL INT32 critics_number;
+        OPTIONAL INT32 metacritic_score;
+        REQUIRED INT64 duration_ms;
+        REQUIRED BYTE_ARRAY spectrogram;
+        OPTIONAL BYTE_ARRAY primary (UTF8);
+        REQUIRED DOUBLE weight;
+    }")?;
+
+    // Labels are user-defined, so their columns are built rather than parsed.
+    let mut fields = base.get_fields().to_vec();
+    for label in labels {
+        fields.push(Arc::new(Type::primitive_type_builder(label, PhysicalType::INT32)
+            .with_repetition(Repetition::REQUIRED)
+            .build()?));
+    }
+
+    let schema = Arc::new(Type::group_type_builder("trailer").with_fields(fields).build()?);
+    let mut writer = SerializedFileWriter::new(File::create(path)?, schema, Arc::new(WriterProperties::builder().build()))?;
+    let mut row_group = writer.next_row_group()?;
+    let movies: Vec<_> = records.iter().map(|r| r.sample.movie).collect();
+
+    let ids: Vec<ByteArray> = movies.iter().map(|m| m.youtube_id.as_str().into()).collect();
+    column::<ByteArrayType, _>(&mut row_group, &ids, None)?;
+    let names: Vec<ByteArray> = movies.iter().map(|m| m.name.as_str().into()).collect();
+    column::<ByteArrayType, _>(&mut row_group, &names, None)?;
+
+    let (years, levels) = optional(movies.iter().map(|m| m.year), |y| y as i32);
+    column::<Int32Type, _>(&mut row_group, &years, Some(&levels))?;
+    let (critics, levels) = optional(movies.iter().map(|m| m.critics_number), |n| n as i32);
+    column::<Int32Type, _>(&mut row_group, &critics, Some(&levels))?;
+    let (scores, levels) = optional(movies.iter().map(|m| m.metacritic_score), |n| n as i32);
+    column::<Int32Type, _>(&mut row_group, &scores, Some(&levels))?;
+
+    let durations: Vec<_> = records.iter().map(|r| r.sample.artifact.duration_ms as i64).collect();
+    column::<Int64Type, _>(&mut row_group, &durations, None)?;
+    let spectrograms: Vec<ByteArray> = records.iter().map(|r| r.spectrogram.clone().into()).collect();
+    column::<ByteArrayType, _>(&mut row_group, &spectrograms, None)?;
+    let primary: Vec<_> = records.iter().map(|r| r.sample.primary.as_deref()).collect();
+    let levels: Vec<_> = primary.iter().map(|p| p.is_some() as i16).collect();
+    let primary: Vec<ByteArray> = primary.into_iter().flatten().map(|p| p.into()).collect();
+    column::<ByteArrayType, _>(&mut row_group, &primary, Some(&levels))?;
+    let weights: Vec<_> = records.iter().map(|r| r.sample.weight).collect();
+    column::<DoubleType, _>(&mut row_group, &weights, None)?;
+
+    for i in 0..labels.len() {
+        let values: Vec<_> = records.iter().map(|r| r.multi_hot[i] as i32).collect();
+        column::<Int32Type, _>(&mut row_group, &values, None)?;
+    }
+
+    row_group.close()?;
+    writer.close()?;
+
+    Ok(())
+}
+
+/// The dataset card: a Markdown summary of what is in the dataset and how it was made.
+pub fn card(dataset: &Dataset, config: &SelectionConfig, name: &str, version: &str) -> String {
+    let mut card = String::new();
+    let total = dataset.samples.len();
+    let count_split = |split| dataset.samples.iter().filter(|s| s.split == split).count();
+
+    writeln!(card, "# {} {}\n", name, version).unwrap();
+    writeln!(card, "Spectrograms of movie trailer audio labeled with the genres of the movie.\n").unwrap();
+    writeln!(
+        card,
+        "{} movies: {} train, {} validation. Movies from {} on with at least {} critic reviews, \
+        labeled with {}.\n",
+        total, count_split(Split::Train), count_split(Split::Validation),
+        config.min_year, config.min_critics,
+        if config.all_genres { "all genres".to_owned() } else { format!("the {} most common genres", config.top_genres) },
+    ).unwrap();
+
+    writeln!(card, "## Labels\n").unwrap();
+    writeln!(card, "| Label | Movies | % | pos_weight |").unwrap();
+    writeln!(card, "|---|---:|---:|---:|").unwrap();
+    let pos_weights = dataset.pos_weights();
+    for (label, n) in dataset.label_counts() {
+        let pos_weight = pos_weights[dataset.labels.iter().position(|l| *l == label).unwrap()];
+        writeln!(card, "| {} | {} | {:.1} | {:.2} |", label, n, percent(n, total), pos_weight).unwrap();
+    }
+
+    match &config.balance {
+        Balance::None => {},
+        Balance::Cap { max_per_label } => writeln!(card, "\nBalanced to at most {} movies per label.", max_per_label).unwrap(),
+        Balance::Undersample { max_per_combination } => {
+            writeln!(card, "\nBalanced to at most {} movies per label combination.", max_per_combination).unwrap()
+        },
+        Balance::Weight => writeln!(card, "\nBalanced by sample weights, the mean inverse frequency of the labels.").unwrap(),
+    }
+
+    let mapping = &dataset.mapping;
+    if !mapping.merge.is_empty() || !mapping.drop.is_empty() || !mapping.priority.is_empty() {
+        writeln!(card, "\nIMDb genres are mapped to labels:\n").unwrap();
+        for (label, genres) in &mapping.merge {
+            let genres: Vec<_> = genres.iter().map(|g| g.to_string()).collect();
+            writeln!(card, "- {} merged into {}", genres.join(", "), label).unwrap();
+        }
+        if !mapping.drop.is_empty() {
+            let genres: Vec<_> = mapping.drop.iter().map(|g| g.to_string()).collect();
+            writeln!(card, "- {} dropped", genres.join(", ")).unwrap();
+        }
+        if !mapping.priority.is_empty() {
+            writeln!(card, "- Primary label by priority: {}", mapping.priority.join(", ")).unwrap();
+        }
+    }
+
+    let mut years: BTreeMap<u32, usize> = BTreeMap::new();
+    for movie in dataset.movies() {
+        if let Some(year) = movie.year {
+            *years.entry(year).or_default() += 1;
+        }
+    }
+
+    let max = years.values().copied().max().unwrap_or(1);
+    writeln!(card, "\n## Years\n").unwrap();
+    writeln!(card, "| Year | Movies | |").unwrap();
+    writeln!(card, "|---|---:|---|").unwrap();
+    for (year, n) in years {
+        writeln!(card, "| {} | {} | {} |", year, n, "#".repeat((n * 40).div_ceil(max))).unwrap();
+    }
+
+    let mut sources: BTreeMap<String, usize> = BTreeMap::new();
+    for sample in &dataset.samples {
+        let source = match &sample.artifact.source {
+            TrailerSource::Id => "MovieLens video".to_owned(),
+            TrailerSource::Search { rank, .. } => format!("Search result #{}", rank),
+        };
+        *sources.entry(source).or_default() += 1;
+    }
+
+    writeln!(card, "\n## Trailer sources\n").unwrap();
+    writeln!(card, "| Source | Movies | % |").unwrap();
+    writeln!(card, "|---|---:|---:|").unwrap();
+    for (source, n) in sources {
+        writeln!(card, "| {} | {} | {:.1} |", source, n, percent(n, total)).unwrap();
+    }
+
+    if let Some(params) = dataset.samples.first().map(|s| &s.artifact.params) {
+        writeln!(card, "\n## Spectrograms\n").unwrap();
+        writeln!(card, "- {} ms of audio per column, {} px high, up to {} Hz", params.ms_per_column, params.height, params.stop_hz).unwrap();
+        writeln!(card, "- Rendered by {}", params.renderer).unwrap();
+        if let Some(loudness) = params.preprocess.loudness {
+            writeln!(card, "- Loudness normalized to {} LUFS", loudness).unwrap();
+        }
+        if let Some(threshold) = params.preprocess.silence_threshold {
+            writeln!(card, "- Silence below {} dB trimmed", threshold).unwrap();
+        }
+        if params.preprocess.cut_start > 0.0 {
+            writeln!(card, "- First {} seconds cut", params.preprocess.cut_start).unwrap();
+        }
+    }
+
+    card
+}
+
+fn percent(n: usize, total: usize) -> f64 {
+    n as f64 * 100.0 / total.max(1) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ExportFormat;
+    use crate::config::Config;
+    use crate::dataset::Dataset;
+    use crate::layout::Layout;
+    use crate::manifest::tests::{manifest, movie};
+    use crate::manifest::Manifest;
+    use crate::movie::Movie;
+
+    use std::convert::TryInto;
+    use std::fs;
+    use std::io::Read;
+    use std::path::Path;
+
+    /// Drama movies with the given ids, and a manifest with a fake spectrogram for each.
+    fn fixture(layout: &Layout, ids: &[&str]) -> (Vec<Movie>, Manifest) {
+        layout.create_dirs().unwrap();
+
+        let movies: Vec<_> = ids.iter().map(|id| movie(id)).collect();
+
+        let mut manifest = manifest(&movies);
+        for artifact in &mut manifest.artifacts {
+            let file = layout.spectrogram(&artifact.movie);
+            fs::create_dir_all(layout.resolve(&file).parent().unwrap()).unwrap();
+            fs::write(layout.resolve(&file), &artifact.movie).unwrap();
+            artifact.files = vec![file];
+        }
+
+        (movies, manifest)
+    }
+
+    fn tar_entries<R: Read>(reader: R) -> Vec<(String, String)> {
+        let mut archive = tar::Archive::new(reader);
+        let mut entries = vec![];
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let mut data = String::new();
+            entry.read_to_string(&mut data).unwrap();
+            entries.push((entry.path().unwrap().to_string_lossy().into_owned(), data));
+        }
+        entries
+    }
+
+    #[test]
+    fn export() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let layout = Layout::new(tempdir.path());
+        let (movies, manifest) = fixture(&layout, &["a", "b"]);
+
+        let config = Config::default();
+        let dataset = Dataset::select(&movies, &manifest, &config.selection, &config.labels).unwrap();
+        let path = super::export(&layout, &dataset, &config, "test", "1").unwrap();
+        assert_eq!(path, tempdir.path().join("exports/test-1.tar.zst"));
+        assert!(super::export(&layout, &dataset, &config, "test", "1").is_err());
+
+        let entries = tar_entries(zstd::Decoder::new(fs::File::open(&path).unwrap()).unwrap());
+        let names: Vec<_> = entries.iter().map(|(n, _)| &**n).collect();
+        assert_eq!(names, vec![
+            "test-1/README.md",
+            "test-1/movies_selected.csv",
+            "test-1/labels.txt",
+            "test-1/class_weights.json",
+            "test-1/split.csv",
+            "test-1/config.json",
+            "test-1/params.json",
+            "test-1/spectrograms/trailer-a.jpg",
+            "test-1/spectrograms/trailer-b.jpg",
+            "test-1/MD5SUMS",
+        ]);
+        assert!(entries[0].1.contains("| Drama | 2 | 100.0 | 0.00 |"));
+        assert!(entries[0].1.contains("| MovieLens video | 2 | 100.0 |"));
+        assert!(entries[6].1.contains(r#""ms_per_column": 100"#));
+        assert!(entries[9].1.contains(&format!("{:x}  spectrograms/trailer-a.jpg", md5::compute("a"))));
+
+        tempdir.close().unwrap();
+    }
+
+    /// Exports 10 movies in shards of 4 and returns the shards of the train split with the
+    /// number of train samples.
+    fn export_shards(layout: &Layout, format: ExportFormat) -> (Vec<String>, usize) {
+        let ids: Vec<_> = (0..10).map(|i| format!("m{}", i)).collect();
+        let (movies, manifest) = fixture(layout, &ids.iter().map(|i| &**i).collect::<Vec<_>>());
+
+        let dataset = Dataset::select(&movies, &manifest, &Default::default(), &Default::default()).unwrap();
+        let dir = super::export_shards(layout, &dataset, format, "test", "1", 4).unwrap();
+
+        let info: serde_json::Value = serde_json::from_reader(fs::File::open(dir.join("dataset.json")).unwrap()).unwrap();
+        assert_eq!(info["labels"], serde_json::json!(["Drama"]));
+        assert_eq!(
+            info["splits"]["train"]["samples"].as_u64().unwrap() + info["splits"]["validation"]["samples"].as_u64().unwrap(),
+            10,
+        );
+
+        let train = info["splits"]["train"]["samples"].as_u64().unwrap() as usize;
+        let shards: Vec<_> = info["splits"]["train"]["shards"].as_array().unwrap().iter()
+            .map(|s| dir.join(s.as_str().unwrap()).to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(shards.len(), train.div_ceil(4));
+        assert!(shards[0].ends_with(&format!("train-00000-of-{:05}.{}", shards.len(), format.extension())));
+
+        (shards, train)
+    }
+
+    #[test]
+    fn tfrecord() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let (shards, train) = export_shards(&Layout::new(tempdir.path()), ExportFormat::TFRecord);
+
+        let mut records = 0;
+        for shard in shards {
+            let data = fs::read(shard).unwrap();
+            let mut pos = 0;
+            while pos < data.len() {
+                let len = u64::from_le_bytes(data[pos..pos + 8].try_into().unwrap()) as usize;
+                pos += 8 + 4 + len + 4;
+                records += 1;
+            }
+            assert_eq!(pos, data.len());
+        }
+        assert_eq!(records, train);
+
+        tempdir.close().unwrap();
+    }
+
+    #[test]
+    fn webdataset() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let (shards, train) = export_shards(&Layout::new(tempdir.path()), ExportFormat::WebDataset);
+
+        let entries: Vec<_> = shards.iter().flat_map(|s| tar_entries(fs::File::open(s).unwrap())).collect();
+        assert_eq!(entries.len(), train * 2);
+
+        let (name, data) = &entries[0];
+        let id = Path::new(name).file_stem().unwrap().to_str().unwrap();
+        assert_eq!(name, &format!("{}.jpg", id));
+        assert_eq!(data, id);
+
+        let metadata: serde_json::Value = serde_json::from_str(&entries[1].1).unwrap();
+        assert_eq!(metadata["id"], id);
+        assert_eq!(metadata["labels"], serde_json::json!(["Drama"]));
+        assert_eq!(metadata["multi_hot"], serde_json::json!([1]));
+
+        tempdir.close().unwrap();
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn parquet() {
+        use parquet::file::reader::{FileReader, SerializedFileReader};
+
+        let tempdir = tempfile::tempdir().unwrap();
+        let (shards, train) = export_shards(&Layout::new(tempdir.path()), ExportFormat::Parquet);
+
+        let mut rows = 0;
+        for shard in shards {
+            let reader = SerializedFileReader::new(fs::File::open(shard).unwrap()).unwrap();
+            let metadata = reader.metadata().file_metadata();
+            assert_eq!(metadata.schema_descr().num_columns(), 10);
+            rows += metadata.num_rows() as usize;
+        }
+        assert_eq!(rows, train);
+
+        tempdir.close().unwrap();
+    }
+}
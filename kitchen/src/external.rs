@@ -1,4 +1,8 @@
+//! Wrappers around youtube-dl, ffprobe and ffmpeg: searching and downloading trailers, probing
+//! and preprocessing audio, and rendering spectrograms.
+
 use crate::error::*;
+use crate::throttle;
 
 use std::process::Command;
 use std::path::Path;
@@ -6,23 +10,143 @@ use std::str;
 
 use lazy_static::lazy_static;
 use regex::Regex;
+use serde::{Serialize, Deserialize};
+
+const YOUTUBE_HOST: &str = "www.youtube.com";
+pub const SPECTROGRAM_STOP_HZ: u32 = 16000;
 
 lazy_static! {
-    static ref FFPROBE_DURATION_REGEX: Regex = Regex::new("duration=(.*)").unwrap();
+    static ref SILENCE_START_REGEX: Regex = Regex::new(r"silence_start: (-?[\d.]+)").unwrap();
+    static ref SILENCE_END_REGEX: Regex = Regex::new(r"silence_end: (-?[\d.]+)").unwrap();
+}
+
+/// Options for the optional audio preprocessing done before making spectrograms.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PreprocessOptions {
+    /// Target integrated loudness in LUFS (EBU R128), measured after trimming. `None` keeps the
+    /// original gain.
+    pub loudness: Option<f64>,
+    /// Leading and trailing audio quieter than this (in dB) is trimmed. `None` disables trimming.
+    pub silence_threshold: Option<f64>,
+    /// Seconds cut from the start of the audio, e.g. to drop studio bumpers.
+    pub cut_start: f64,
+}
+
+impl PreprocessOptions {
+    pub fn is_enabled(&self) -> bool {
+        self.loudness.is_some() || self.silence_threshold.is_some() || self.cut_start > 0.0
+    }
+}
+
+/// What the preprocessing step decided for one audio file.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Preprocessing {
+    pub cut_ms: u64,
+    pub trim_start_ms: u64,
+    pub trim_end_ms: u64,
+    pub measured_loudness: Option<f64>,
+    pub gain_db: Option<f64>,
+}
+
+impl Preprocessing {
+    /// Offset in the original audio where the kept part begins.
+    pub fn start_ms(&self) -> u64 {
+        self.cut_ms + self.trim_start_ms
+    }
+
+    /// Length of the kept part given the length of the original audio.
+    pub fn length_ms(&self, audio_len: u64) -> u64 {
+        audio_len.saturating_sub(self.start_ms() + self.trim_end_ms)
+    }
+}
+
+/// Metadata of a downloaded video, as printed by youtube-dl.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct VideoInfo {
+    pub id: String,
+    pub title: String,
+    #[serde(default, alias = "uploader")]
+    pub channel: Option<String>,
+    #[serde(default)]
+    pub duration: Option<f64>,
+    /// Upload date in the `YYYYMMDD` format.
+    #[serde(default)]
+    pub upload_date: Option<String>,
 }
 
-pub fn download_youtube_m4a_by_id<T: AsRef<Path>>(id: &str, path: T) -> Result<()> {
-    download_youtube_m4a_impl(&format!("https://www.youtube.com/watch?v={}", id), path)
+/// Stream and format information of an audio file, from ffprobe.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MediaInfo {
+    pub format: Option<String>,
+    pub codec: Option<String>,
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u32>,
+    pub bit_rate: Option<u64>,
+    pub duration_ms: u64,
 }
 
-pub fn download_youtube_m4a_by_search<T: AsRef<Path>>(search: &str, path: T) -> Result<()> {
-    download_youtube_m4a_impl(&format!("ytsearch1:{}", search), path)
+/// Mirrors the parts of `ffprobe -print_format json` output that are used. ffprobe prints most
+/// numbers as strings, and `N/A` when a value is unknown.
+#[derive(Deserialize)]
+struct FfprobeOutput {
+    #[serde(default)]
+    streams: Vec<FfprobeStream>,
+    format: Option<FfprobeFormat>,
 }
 
-fn download_youtube_m4a_impl<T: AsRef<Path>>(query: &str, path: T) -> Result<()> {
+#[derive(Deserialize)]
+struct FfprobeStream {
+    codec_type: Option<String>,
+    codec_name: Option<String>,
+    sample_rate: Option<String>,
+    channels: Option<u32>,
+    bit_rate: Option<String>,
+    duration: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct FfprobeFormat {
+    format_name: Option<String>,
+    bit_rate: Option<String>,
+    duration: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct LoudnormStats {
+    input_i: String,
+}
+
+/// Downloads the audio to `path`. youtube-dl output goes to `logs` with the extensions `out.log`
+/// and `err.log`.
+pub fn download_youtube_m4a_by_id<T: AsRef<Path>, U: AsRef<Path>>(id: &str, path: T, logs: U) -> Result<VideoInfo> {
+    download_youtube_m4a_impl(&format!("https://www.youtube.com/watch?v={}", id), path, logs)
+}
+
+/// Lists the metadata of the top `n` search results without downloading them.
+pub fn search_youtube(search: &str, n: usize) -> Result<Vec<VideoInfo>> {
+    let _permit = throttle::acquire(YOUTUBE_HOST);
     let output = Command::new("youtube-dl")
-        .args(&[
+        .arg("--dump-json")
+        .arg(format!("ytsearch{}:{}", n, search))
+        .output()?;
+
+    if !output.status.success() {
+        return Err(Error::YoutubeDL(str::from_utf8(&output.stderr).unwrap().to_owned()));
+    }
+
+    str::from_utf8(&output.stdout).unwrap()
+        .lines()
+        .filter(|l| l.starts_with('{'))
+        .map(|l| Ok(serde_json::from_str(l)?))
+        .collect()
+}
+
+fn download_youtube_m4a_impl<T: AsRef<Path>, U: AsRef<Path>>(query: &str, path: T, logs: U) -> Result<VideoInfo> {
+    let _permit = throttle::acquire(YOUTUBE_HOST);
+    let output = Command::new("youtube-dl")
+        .args([
             "-x",
+            "--print-json",
             "--audio-format",
             "m4a",
             "--audio-quality",
@@ -33,46 +157,303 @@ fn download_youtube_m4a_impl<T: AsRef<Path>>(query: &str, path: T) -> Result<()>
         .arg(query)
         .output()?;
     
-    std::fs::write(path.as_ref().with_extension("out.log"), &output.stdout)?;
-    std::fs::write(path.as_ref().with_extension("err.log"), &output.stderr)?;
+    if let Some(dir) = logs.as_ref().parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(logs.as_ref().with_extension("out.log"), &output.stdout)?;
+    std::fs::write(logs.as_ref().with_extension("err.log"), &output.stderr)?;
 
     if !output.status.success() {
         return Err(Error::YoutubeDL(str::from_utf8(&output.stderr).unwrap().to_owned()));
     }
 
+    let stdout = str::from_utf8(&output.stdout).unwrap();
+    let json = stdout.lines()
+        .rev()
+        .find(|l| l.starts_with('{'))
+        .ok_or_else(|| Error::YoutubeDL("missing video metadata".into()))?;
+
+    Ok(serde_json::from_str(json)?)
+}
+
+/// Decodes the audio into mono 16-bit samples at the given sample rate.
+pub fn decode_pcm<T: AsRef<Path>>(path: T, sample_rate: u32) -> Result<Vec<i16>> {
+    #[cfg(feature = "native-decode")]
+    {
+        if let Ok(pcm) = crate::decode::decode(path.as_ref()) {
+            return Ok(pcm.to_i16(sample_rate));
+        }
+    }
+
+    ffmpeg_pcm(path, sample_rate)
+}
+
+fn ffmpeg_pcm<T: AsRef<Path>>(path: T, sample_rate: u32) -> Result<Vec<i16>> {
+    let output = Command::new("ffmpeg")
+        .args(["-hide_banner", "-i"])
+        .arg(path.as_ref().as_os_str())
+        .args(["-f", "s16le", "-ac", "1", "-ar"])
+        .arg(sample_rate.to_string())
+        .arg("-")
+        .output()?;
+
+    if !output.status.success() {
+        return Err(Error::FFMPEG(str::from_utf8(&output.stderr).unwrap().to_owned()));
+    }
+
+    Ok(output.stdout.chunks_exact(2)
+        .map(|b| i16::from_le_bytes([b[0], b[1]]))
+        .collect())
+}
+
+/// Fails early with a clear error when ffmpeg and ffprobe are needed but can't be run. Without the
+/// `native-decode` feature they make every spectrogram. With it, they are still needed for the
+/// loudness and silence analysis and for `opus` audio, which the native decoder can't read.
+pub fn check_ffmpeg(preprocess: &PreprocessOptions, opus: bool) -> Result<()> {
+    let needed_for = if !cfg!(feature = "native-decode") {
+        "making spectrograms"
+    } else if preprocess.loudness.is_some() || preprocess.silence_threshold.is_some() {
+        "--loudness and --trim-silence"
+    } else if opus {
+        "decoding Opus audio"
+    } else {
+        return Ok(());
+    };
+
+    for program in &["ffmpeg", "ffprobe"] {
+        match Command::new(program).arg("-version").output() {
+            Ok(output) if output.status.success() => {},
+            _ => Err(Error::FFMPEG(format!("{} is needed for {} but can't be run", program, needed_for)))?,
+        }
+    }
+
     Ok(())
 }
 
 pub fn get_audio_length<T: AsRef<Path>>(path: T) -> Result<u64> {
+    Ok(probe(path)?.duration_ms)
+}
+
+/// Reads the media information. With the `native-decode` feature, files that the native decoder
+/// can't open are still probed with ffprobe.
+pub fn probe<T: AsRef<Path>>(path: T) -> Result<MediaInfo> {
+    #[cfg(feature = "native-decode")]
+    {
+        if let Ok(info) = crate::decode::probe(path.as_ref()) {
+            return Ok(info);
+        }
+    }
+
     let output = Command::new("ffprobe")
-        .arg("-show_format")
+        .args(["-v", "error", "-print_format", "json", "-show_streams", "-show_format"])
         .arg(path.as_ref().as_os_str())
         .output()?;
     
     if !output.status.success() {
-        return Err(Error::YoutubeDL(str::from_utf8(&output.stderr).unwrap().to_owned()));
+        return Err(Error::FFMPEG(str::from_utf8(&output.stderr).unwrap().to_owned()));
     }
 
-    let stdout = str::from_utf8(&output.stdout).unwrap();
-    let captures = FFPROBE_DURATION_REGEX.captures(stdout)
+    parse_probe(str::from_utf8(&output.stdout).unwrap())
+}
+
+fn parse_probe(json: &str) -> Result<MediaInfo> {
+    let output: FfprobeOutput = serde_json::from_str(json)
+        .map_err(|e| Error::FFMPEG(format!("invalid ffprobe output: {}", e)))?;
+
+    let stream = output.streams.iter()
+        .find(|s| s.codec_type.as_deref() == Some("audio"))
+        .ok_or_else(|| Error::FFMPEG("no audio stream".into()))?;
+    let format = output.format.as_ref();
+
+    let duration_s: f64 = format.and_then(|f| parse_number(&f.duration))
+        .or_else(|| parse_number(&stream.duration))
         .ok_or_else(|| Error::FFMPEG("missing duration from ffprobe".into()))?;
 
-    let duration_s: f64 = captures.get(1).unwrap().as_str().parse().unwrap();
-    let duration_ms: u64 = (duration_s * 1000.0) as u64;
+    Ok(MediaInfo {
+        format: format.and_then(|f| f.format_name.clone()),
+        codec: stream.codec_name.clone(),
+        sample_rate: parse_number(&stream.sample_rate),
+        channels: stream.channels,
+        bit_rate: parse_number(&stream.bit_rate).or_else(|| format.and_then(|f| parse_number(&f.bit_rate))),
+        duration_ms: (duration_s * 1000.0) as u64,
+    })
+}
 
-    Ok(duration_ms)
+fn parse_number<T: str::FromStr>(value: &Option<String>) -> Option<T> {
+    value.as_ref().and_then(|v| v.parse().ok())
 }
 
-pub fn make_spectrogram<T: AsRef<Path>>(path: T, (width, height): (usize, usize)) -> Result<()> {
+/// Measures the silence of the audio, then the loudness of the part kept after trimming it, each
+/// in an ffmpeg pass. `audio_len` is the length of the audio in milliseconds. The audio itself is
+/// left untouched; the result is applied when making the spectrogram. This needs ffmpeg even with
+/// the `native-decode` feature.
+pub fn analyze_audio<T: AsRef<Path>>(
+    path: T,
+    audio_len: u64,
+    options: &PreprocessOptions,
+) -> Result<Preprocessing> {
+    let cut_ms = ((options.cut_start * 1000.0) as u64).min(audio_len);
+    let mut result = Preprocessing {
+        cut_ms,
+        ..Default::default()
+    };
+
+    if let Some(threshold) = options.silence_threshold {
+        let filter = format!("silencedetect=noise={}dB:d=0.5", threshold);
+        let log = run_analysis(path.as_ref(), cut_ms, audio_len - cut_ms, &filter)?;
+        let (start, end) = silence_trims(&log, audio_len - cut_ms);
+        result.trim_start_ms = start;
+        result.trim_end_ms = end;
+    }
+
+    if let Some(loudness) = options.loudness {
+        let filter = format!("loudnorm=I={}:print_format=json", loudness);
+        let log = run_analysis(path.as_ref(), result.start_ms(), result.length_ms(audio_len), &filter)?;
+        let measured = parse_loudnorm(&log)?;
+        if measured.is_finite() {
+            result.measured_loudness = Some(measured);
+            result.gain_db = Some(loudness - measured);
+        }
+    }
+
+    Ok(result)
+}
+
+/// Runs an analysis filter over `length_ms` of the audio from `start_ms`, returning the ffmpeg log.
+fn run_analysis(path: &Path, start_ms: u64, length_ms: u64, filter: &str) -> Result<String> {
     let output = Command::new("ffmpeg")
+        .args(["-hide_banner", "-nostats", "-ss"])
+        .arg(format!("{:.3}", start_ms as f64 / 1000.0))
+        .arg("-t")
+        .arg(format!("{:.3}", length_ms as f64 / 1000.0))
+        .arg("-i")
+        .arg(path.as_os_str())
+        .arg("-af")
+        .arg(filter)
+        .args(["-f", "null", "-"])
+        .output()?;
+
+    let stderr = str::from_utf8(&output.stderr).unwrap().to_owned();
+    if !output.status.success() {
+        return Err(Error::FFMPEG(stderr));
+    }
+
+    Ok(stderr)
+}
+
+/// Finds how much leading and trailing silence `silencedetect` reported, in milliseconds.
+fn silence_trims(log: &str, audio_len: u64) -> (u64, u64) {
+    let to_ms = |s: &str| (s.parse::<f64>().unwrap_or(0.0).max(0.0) * 1000.0) as u64;
+    let starts: Vec<_> = SILENCE_START_REGEX.captures_iter(log)
+        .map(|c| to_ms(c.get(1).unwrap().as_str()))
+        .collect();
+    let ends: Vec<_> = SILENCE_END_REGEX.captures_iter(log)
+        .map(|c| to_ms(c.get(1).unwrap().as_str()))
+        .collect();
+
+    let mut trim_start = 0;
+    if starts.first().map(|s| *s <= 50) == Some(true) {
+        // Entirely silent audio has no end; keep it as is and let the caller decide.
+        trim_start = ends.first().copied().unwrap_or(0);
+    }
+
+    let mut trim_end = 0;
+    if let Some(last_start) = starts.last().copied() {
+        // Newer ffmpeg reports a silence_end at EOF, older ones don't report it at all.
+        let ends_at_eof = ends.len() < starts.len()
+            || ends.last().map(|e| *e + 50 >= audio_len) == Some(true);
+
+        if ends_at_eof && last_start > trim_start {
+            trim_end = audio_len.saturating_sub(last_start);
+        }
+    }
+
+    (trim_start, trim_end)
+}
+
+fn parse_loudnorm(log: &str) -> Result<f64> {
+    let json = log.rfind('{')
+        .and_then(|start| log[start..].find('}').map(|end| &log[start..=start + end]))
+        .ok_or_else(|| Error::FFMPEG("missing loudnorm statistics".into()))?;
+    let stats: LoudnormStats = serde_json::from_str(json)?;
+
+    stats.input_i.parse().map_err(|_| Error::ParseError(stats.input_i))
+}
+
+/// Decodes in Rust, or with ffmpeg at the original sample rate when the codec isn't supported,
+/// such as Opus from an `AudioFormat::Opus` store.
+#[cfg(feature = "native-decode")]
+fn decode_any(path: &Path) -> Result<crate::decode::Pcm> {
+    match crate::decode::decode(path) {
+        Err(Error::Decode(_)) => {
+            let sample_rate = probe(path)?.sample_rate
+                .ok_or_else(|| Error::FFMPEG("unknown sample rate".into()))?;
+            let samples = ffmpeg_pcm(path, sample_rate)?.iter()
+                .map(|s| *s as f32 / i16::MAX as f32)
+                .collect();
+            Ok(crate::decode::Pcm { sample_rate, samples })
+        },
+        result => result,
+    }
+}
+
+/// Writes the spectrogram of the audio at `path`, `audio_len` milliseconds long, as an image at
+/// `output`. With the `native-decode` feature, the spectrogram is rendered in Rust instead of by
+/// ffmpeg.
+#[cfg(feature = "native-decode")]
+pub fn make_spectrogram<T: AsRef<Path>, U: AsRef<Path>>(
+    path: T,
+    output: U,
+    size: (usize, usize),
+    audio_len: u64,
+    preprocessing: Option<&Preprocessing>,
+) -> Result<()> {
+    let pcm = decode_any(path.as_ref())?;
+    let mut samples = &pcm.samples[..];
+    let mut gain = 1.0;
+
+    if let Some(pre) = preprocessing {
+        let to_index = |ms: u64| ((ms * pcm.sample_rate as u64 / 1000) as usize).min(samples.len());
+        let start = to_index(pre.start_ms());
+        let end = to_index(pre.start_ms() + pre.length_ms(audio_len)).max(start);
+        samples = &samples[start..end];
+        gain = 10f32.powf(pre.gain_db.unwrap_or(0.0) as f32 / 20.0);
+    }
+
+    let samples: Vec<f32> = samples.iter().map(|s| s * gain).collect();
+    crate::decode::render_spectrogram(&samples, pcm.sample_rate, size, SPECTROGRAM_STOP_HZ as f32, output)
+}
+
+/// Writes the spectrogram of the audio at `path`, `audio_len` milliseconds long, as an image at
+/// `output`.
+#[cfg(not(feature = "native-decode"))]
+pub fn make_spectrogram<T: AsRef<Path>, U: AsRef<Path>>(
+    path: T,
+    output: U,
+    (width, height): (usize, usize),
+    audio_len: u64,
+    preprocessing: Option<&Preprocessing>,
+) -> Result<()> {
+    let mut command = Command::new("ffmpeg");
+    let mut filter = format!("showspectrumpic=legend=disabled:stop={}:s={}x{}", SPECTROGRAM_STOP_HZ, width, height);
+
+    if let Some(pre) = preprocessing {
+        command
+            .arg("-ss")
+            .arg(format!("{:.3}", pre.start_ms() as f64 / 1000.0))
+            .arg("-t")
+            .arg(format!("{:.3}", pre.length_ms(audio_len) as f64 / 1000.0));
+
+        if let Some(gain) = pre.gain_db {
+            filter = format!("volume={:.2}dB,{}", gain, filter);
+        }
+    }
+
+    let output = command
         .arg("-i")
         .arg(path.as_ref().as_os_str())
-        .args(&[
-            "-filter_complex",
-            &format!("showspectrumpic=legend=disabled:stop=16000:s={}x{}", width, height),
-            "-y",
-        ])
-        .arg(path.as_ref().with_extension("jpg"))
+        .args(["-filter_complex", &filter, "-y"])
+        .arg(output.as_ref())
         .output()?;
     
     if !output.status.success() {
@@ -84,12 +465,84 @@ pub fn make_spectrogram<T: AsRef<Path>>(path: T, (width, height): (usize, usize)
 
 #[cfg(test)]
 mod tests {
+    #[cfg(feature = "native-decode")]
+    #[test]
+    fn check_ffmpeg() {
+        assert!(super::check_ffmpeg(&Default::default(), false).is_ok());
+    }
+
+    #[test]
+    fn silence_trims() {
+        let log = "\
+[silencedetect @ 0x5581] silence_start: 0
+[silencedetect @ 0x5581] silence_end: 1.5 | silence_duration: 1.5
+[silencedetect @ 0x5581] silence_start: 40.2
+[silencedetect @ 0x5581] silence_end: 41 | silence_duration: 0.8
+[silencedetect @ 0x5581] silence_start: 118
+";
+        assert_eq!(super::silence_trims(log, 120000), (1500, 2000));
+        assert_eq!(super::silence_trims("", 120000), (0, 0));
+    }
+
+    #[test]
+    fn parse_probe() {
+        let json = r#"{
+            "streams": [
+                { "index": 0, "codec_name": "h264", "codec_type": "video" },
+                {
+                    "index": 1,
+                    "codec_name": "aac",
+                    "codec_type": "audio",
+                    "sample_rate": "44100",
+                    "channels": 2,
+                    "bit_rate": "127999",
+                    "duration": "18.937000"
+                }
+            ],
+            "format": {
+                "format_name": "mov,mp4,m4a,3gp,3g2,mj2",
+                "duration": "18.960000",
+                "bit_rate": "130412"
+            }
+        }"#;
+
+        let info = super::parse_probe(json).unwrap();
+        assert_eq!(info.codec.as_deref(), Some("aac"));
+        assert_eq!(info.sample_rate, Some(44100));
+        assert_eq!(info.channels, Some(2));
+        assert_eq!(info.bit_rate, Some(127999));
+        assert_eq!(info.duration_ms, 18960);
+
+        let missing = r#"{
+            "streams": [{ "codec_name": "opus", "codec_type": "audio", "duration": "N/A" }],
+            "format": { "duration": "N/A" }
+        }"#;
+        assert!(matches!(super::parse_probe(missing), Err(crate::error::Error::FFMPEG(_))));
+        assert!(matches!(super::parse_probe("{}"), Err(crate::error::Error::FFMPEG(_))));
+    }
+
+    #[test]
+    fn parse_loudnorm() {
+        let log = r#"
+[Parsed_loudnorm_1 @ 0x55d6]
+{
+    "input_i" : "-27.61",
+    "input_tp" : "-4.47",
+    "input_lra" : "18.06",
+    "input_thresh" : "-39.20",
+    "target_offset" : "0.58"
+}
+"#;
+        assert_eq!(super::parse_loudnorm(log).unwrap(), -27.61);
+        assert!(super::parse_loudnorm("").is_err());
+    }
+
     #[test]
     fn youtube_duration() {
         let tempdir = tempfile::tempdir().unwrap();
         let path = tempdir.path().join("test.m4a");
 
-        super::download_youtube_m4a_by_id("jNQXAC9IVRw", &path).unwrap();
+        super::download_youtube_m4a_by_id("jNQXAC9IVRw", &path, &path).unwrap();
         assert_eq!(18960, super::get_audio_length(&path).unwrap());
 
         tempdir.close().unwrap();
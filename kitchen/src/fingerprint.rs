@@ -0,0 +1,165 @@
+//! Audio fingerprints, used to notice the same trailer audio showing up for different movies.
+
+use crate::error::*;
+
+use std::f64::consts::PI;
+
+use lazy_static::lazy_static;
+
+/// Sample rate the audio should be decoded at before fingerprinting.
+pub const SAMPLE_RATE: u32 = 5512;
+
+const FRAME_SIZE: usize = 2048;
+const HOP_SIZE: usize = 512;
+const BANDS: usize = 17;
+const MIN_FREQ: f64 = 300.0;
+const MAX_FREQ: f64 = 2000.0;
+const MAX_OFFSET: isize = 20;
+const MIN_OVERLAP: usize = 50;
+
+lazy_static! {
+    static ref WINDOW: Vec<f64> = (0..FRAME_SIZE)
+        .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f64 / (FRAME_SIZE - 1) as f64).cos())
+        .collect();
+    static ref GOERTZEL_COEFFS: Vec<f64> = (0..BANDS)
+        .map(|b| {
+            let freq = MIN_FREQ * (MAX_FREQ / MIN_FREQ).powf((b as f64 + 0.5) / BANDS as f64);
+            2.0 * (2.0 * PI * freq / SAMPLE_RATE as f64).cos()
+        })
+        .collect();
+}
+
+/// Two fingerprints more similar than this are considered to be the same audio.
+pub const DUPLICATE_SIMILARITY: f64 = 0.8;
+
+/// A coarse audio fingerprint in the style of Haitsma & Kalker: one 16-bit word per frame, each
+/// bit telling whether the energy difference between two adjacent bands grew since the last frame.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fingerprint(Vec<u16>);
+
+impl Fingerprint {
+    pub fn from_samples(samples: &[i16]) -> Fingerprint {
+        let mut words = vec![];
+        let mut prev: Option<Vec<f64>> = None;
+
+        let mut start = 0;
+        while start + FRAME_SIZE <= samples.len() {
+            let energies = band_energies(&samples[start..start + FRAME_SIZE]);
+
+            if let Some(prev) = prev {
+                let mut word = 0u16;
+                for b in 0..16 {
+                    let diff = (energies[b] - energies[b + 1]) - (prev[b] - prev[b + 1]);
+                    if diff > 0.0 {
+                        word |= 1 << b;
+                    }
+                }
+                words.push(word);
+            }
+
+            prev = Some(energies);
+            start += HOP_SIZE;
+        }
+
+        Fingerprint(words)
+    }
+
+    /// Similarity between 0 and 1, allowing the audio to be shifted by up to a couple of seconds.
+    /// Unrelated audio sits around 0.5.
+    pub fn similarity(&self, other: &Fingerprint) -> f64 {
+        let mut best = 0.0;
+
+        for offset in -MAX_OFFSET..=MAX_OFFSET {
+            let (a, b) = if offset < 0 {
+                (&self.0[..], other.0.get(-offset as usize..).unwrap_or(&[]))
+            } else {
+                (self.0.get(offset as usize..).unwrap_or(&[]), &other.0[..])
+            };
+
+            let overlap = a.len().min(b.len());
+            if overlap < MIN_OVERLAP {
+                continue;
+            }
+
+            let errors: u32 = a.iter().zip(b).map(|(x, y)| (x ^ y).count_ones()).sum();
+            let similarity = 1.0 - errors as f64 / (overlap * 16) as f64;
+            if similarity > best {
+                best = similarity;
+            }
+        }
+
+        best
+    }
+
+    pub fn to_hex(&self) -> String {
+        self.0.iter().map(|w| format!("{:04x}", w)).collect()
+    }
+
+    pub fn from_hex(hex: &str) -> Result<Fingerprint> {
+        if !hex.len().is_multiple_of(4) {
+            return Err(Error::ParseError(format!("fingerprint: {}", hex)));
+        }
+
+        (0..hex.len()).step_by(4)
+            .map(|i| u16::from_str_radix(&hex[i..i + 4], 16)
+                .map_err(|_| Error::ParseError(format!("fingerprint: {}", hex))))
+            .collect::<Result<_>>()
+            .map(Fingerprint)
+    }
+}
+
+/// Log energy of each band, using Goertzel's algorithm at the logarithmically spaced band centers.
+fn band_energies(frame: &[i16]) -> Vec<f64> {
+    GOERTZEL_COEFFS.iter()
+        .map(|&coeff| {
+            let (mut s1, mut s2) = (0.0, 0.0);
+            for (x, window) in frame.iter().zip(WINDOW.iter()) {
+                let s = *x as f64 * window + coeff * s1 - s2;
+                s2 = s1;
+                s1 = s;
+            }
+
+            (s1 * s1 + s2 * s2 - coeff * s1 * s2 + 1.0).ln()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Fingerprint, SAMPLE_RATE, DUPLICATE_SIMILARITY};
+
+    /// Deterministic noise with slowly changing pitched content.
+    fn signal(seed: u32, len: usize) -> Vec<i16> {
+        let mut state = seed;
+        (0..len)
+            .map(|i| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                let noise = ((state >> 16) as i16 as f64) * 0.3;
+                let freq = 400.0 + 300.0 * ((i / 2000) % 5) as f64;
+                let tone = 8000.0 * (2.0 * std::f64::consts::PI * freq * i as f64 / SAMPLE_RATE as f64).sin();
+                (noise + tone) as i16
+            })
+            .collect()
+    }
+
+    #[test]
+    fn similarity() {
+        let a = signal(1, SAMPLE_RATE as usize * 30);
+        let fa = Fingerprint::from_samples(&a);
+
+        assert_eq!(fa.similarity(&fa), 1.0);
+
+        let shifted = Fingerprint::from_samples(&a[512 * 7..]);
+        assert!(fa.similarity(&shifted) > DUPLICATE_SIMILARITY);
+
+        let other: Vec<_> = signal(2, a.len()).into_iter().rev().collect();
+        assert!(fa.similarity(&Fingerprint::from_samples(&other)) < DUPLICATE_SIMILARITY);
+    }
+
+    #[test]
+    fn hex() {
+        let fingerprint = Fingerprint::from_samples(&signal(3, 10000));
+        assert_eq!(Fingerprint::from_hex(&fingerprint.to_hex()).unwrap(), fingerprint);
+        assert!(Fingerprint::from_hex("abc").is_err());
+    }
+}
@@ -1,5 +1,9 @@
+//! Finds movies on IMDb and scrapes their genres, number of critic reviews and metascore.
+
 use crate::error::*;
-use crate::movie::Genre;
+use crate::movie::{Genre, Movie};
+use crate::throttle;
+use crate::title;
 
 use lazy_static::lazy_static;
 use regex::Regex;
@@ -15,27 +19,74 @@ lazy_static! {
 }
 
 fn parse_page<T: IntoUrl>(url: T) -> Result<NodeRef> {
+    let url = url.into_url()?;
+    let _permit = throttle::acquire(url.host_str().unwrap_or_default());
     let html = http_get(url)?.text()?;
     Ok(kuchiki::parse_html().one(html))
 }
 
+/// Looks the movie up on IMDb and replaces its genres, critics number and metascore.
+pub fn fetch_movie_info(movie: &mut Movie) -> Result<()> {
+    let names: Vec<_> = std::iter::once(&movie.name).chain(&movie.alternate_titles).collect();
+    let imdb_id = search_any(&names, movie.year)?;
+    let page = get_page(imdb_id)?;
+
+    movie.imdb_id = Some(imdb_id);
+    movie.genres = get_genres(&page)?;
+    movie.critics_number = get_critics_number(&page).ok(); // ignore error
+    movie.metacritic_score = get_metascore(&page).ok();
+
+    Ok(())
+}
+
 pub fn search(name: &str, year: Option<u32>) -> Result<u32> {
     search_impl(name, year, false)
 }
 
+/// Searches the names in turn, such as a title and its alternate titles, until one is found.
+/// Fails with the error of the first name.
+pub fn search_any<S: AsRef<str>>(names: &[S], year: Option<u32>) -> Result<u32> {
+    let mut first_err = None;
+
+    for name in names {
+        match search(name.as_ref(), year) {
+            Ok(id) => return Ok(id),
+            Err(err) => {
+                first_err.get_or_insert(err);
+            },
+        }
+    }
+
+    Err(first_err.unwrap_or_else(|| Error::Generic("no name to search".to_owned())))
+}
+
 #[cfg(test)]
 pub fn search_exact(name: &str, year: Option<u32>) -> Result<u32> {
     search_impl(name, year, true)
 }
 
 fn search_impl(name: &str, year: Option<u32>, exact: bool) -> Result<u32> {
+    let ranked = search_candidates(name, year, exact)?;
+    match ranked.first() {
+        Some(best) if best.score.is_accepted() => Ok(best.item),
+        Some(best) => Err(format!(
+            "movie search found no match, best was '{}' ({:?}) with score {:.2}",
+            best.title, best.year, best.score.total,
+        ))?,
+        None => Err("movie search found no result")?,
+    }
+}
+
+/// Movie results of an IMDb search by their ids, ranked by how well they match the name and year,
+/// with their scores. `search` takes the first if it is accepted.
+pub fn search_candidates(name: &str, year: Option<u32>, exact: bool) -> Result<Vec<title::Ranked<u32>>> {
     let url = Url::parse_with_params(&format!("{}/find", IMDB_BASE_URL_STR), &[
         ("q", name),
         ("s", "tt"),
         ("exact", &exact.to_string()),
     ]).map_err(|e| Error::ParseError(format!("url: {:?}", e)))?;
 
-    let mut candidate: Option<(u32, u32)> = None;
+    let mut results = vec![];
 
     let document = parse_page(url)?;
     for css_match in document.select(".result_text > a")? {
@@ -68,12 +119,12 @@ fn search_impl(name: &str, year: Option<u32>, exact: bool) -> Result<u32> {
 
         let captures = TITLE_YEAR_TYPE_REGEX.captures(&s);
 
-        let mut searched_year = 0;
+        let mut searched_year = None;
         let mut title_type = None;
 
         if let Some(captures) = captures {
             let year_str = captures.get(1).unwrap().as_str();
-            searched_year = year_str.parse().map_err(|_| Error::ParseError(year_str.to_owned()))?;
+            searched_year = Some(year_str.parse().map_err(|_| Error::ParseError(year_str.to_owned()))?);
             title_type = captures.get(2).map(|t| t.as_str());
         };
 
@@ -85,31 +136,11 @@ fn search_impl(name: &str, year: Option<u32>, exact: bool) -> Result<u32> {
             continue;
         }
 
-        let mut loss = 1;
-        if !exact && name.trim().to_lowercase() != searched_name_ref.borrow().trim().to_lowercase() {
-            loss = 2;
-        }
-
-        if let Some(year) = year {
-            loss *= (year as i64 - searched_year as i64).abs() as u32;
-    
-            if loss == 0 {
-                return Ok(id)
-            }
-
-            if candidate.map(|c| c.0 > loss) != Some(false) {
-                candidate = Some((loss, id));
-            }
-        }
+        let searched_name = searched_name_ref.borrow().trim().to_owned();
+        results.push((id, searched_name, searched_year));
     }
 
-    if candidate.map(|c| c.0) >= Some(4) {
-        Err(format!("movie search found no result with loss = {}", candidate.unwrap().0))?
-    } else if let Some(c) = candidate {
-        Ok(c.1)
-    } else {
-        Err("movie search found no result")?
-    }
+    Ok(title::rank(name, year, results))
 }
 
 pub fn get_page(id: u32) -> Result<NodeRef> {
@@ -128,7 +159,7 @@ pub fn get_genres(document: &NodeRef) -> Result<Vec<Genre>> {
             }
         }
 
-        if let Some(parent) = as_node.ancestors().nth(0) {
+        if let Some(parent) = as_node.ancestors().next() {
             let mut result = vec![];
 
             for genre_link in parent.select("a")? {
@@ -0,0 +1,178 @@
+//! Turns IMDb genres into dataset labels.
+
+use crate::config::CsvHeaders;
+use crate::error::*;
+use crate::movie::Genre;
+
+use std::collections::BTreeMap;
+
+use serde::{Serialize, Deserialize};
+
+/// Columns every exported table has besides the labels, which no label may be named after, in any
+/// case. The CSV columns are named by `CsvHeaders` instead.
+pub const FIXED_COLUMNS: &[&str] = &[
+    "id", "name", "year", "critics_number", "metacritic_score", "duration_ms", "spectrogram",
+    "primary", "weight",
+];
+
+/// Turns IMDb genres into dataset labels. For example, in the config:
+///
+/// ```json
+/// "labels": {
+///     "merge": { "Suspense": ["Thriller", "Mystery", "Crime"] },
+///     "drop": ["TalkShow", "GameShow", "RealityTV", "News"],
+///     "priority": ["Horror", "Animation", "Suspense"]
+/// }
+/// ```
+///
+/// Genres that are neither merged nor dropped keep their name. The default maps every genre to
+/// itself.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LabelMapping {
+    /// Label names and the genres merged into them.
+    pub merge: BTreeMap<String, Vec<Genre>>,
+    pub drop: Vec<Genre>,
+    /// Labels in the order they win as the primary label of a movie. Labels not listed come
+    /// after, in the order of the movie's genres.
+    pub priority: Vec<String>,
+}
+
+impl LabelMapping {
+    /// Checks that no genre is merged twice or both merged and dropped, that no label is named
+    /// after a fixed column or one of the CSV `headers`, and that `priority` only lists labels.
+    pub fn validate(&self, headers: &CsvHeaders) -> Result<()> {
+        let mut seen = self.drop.clone();
+
+        for genres in self.merge.values() {
+            for genre in genres {
+                if seen.contains(genre) {
+                    return Err(Error::Generic(format!("genre {} is mapped more than once", genre)));
+                }
+                seen.push(*genre);
+            }
+        }
+
+        let labels = self.all();
+        let columns = FIXED_COLUMNS.iter().copied().chain(headers.names());
+        for column in columns {
+            if let Some(label) = labels.iter().find(|l| l.eq_ignore_ascii_case(column)) {
+                return Err(Error::Generic(format!("label {} is named after the column {}", label, column)));
+            }
+        }
+
+        if let Some(label) = self.priority.iter().find(|p| !labels.contains(p)) {
+            return Err(Error::Generic(format!("priority label {} is not a label", label)));
+        }
+
+        Ok(())
+    }
+
+    /// The label of a genre, or `None` if it is dropped.
+    pub fn label(&self, genre: Genre) -> Option<String> {
+        if self.drop.contains(&genre) {
+            return None;
+        }
+
+        let merged = self.merge.iter().find(|(_, genres)| genres.contains(&genre));
+        Some(merged.map(|(label, _)| label.clone()).unwrap_or_else(|| genre.to_string()))
+    }
+
+    /// The labels of a movie, in the order of its genres, without duplicates.
+    pub fn map(&self, genres: &[Genre]) -> Vec<String> {
+        let mut labels: Vec<String> = vec![];
+
+        for label in genres.iter().filter_map(|g| self.label(*g)) {
+            if !labels.contains(&label) {
+                labels.push(label);
+            }
+        }
+
+        labels
+    }
+
+    /// Every label, in the order of the first genre mapped to it.
+    pub fn all(&self) -> Vec<String> {
+        self.map(&Genre::ALL)
+    }
+
+    /// The label that comes first in `priority`, or else the first of `labels`.
+    pub fn primary<'a>(&self, labels: &'a [String]) -> Option<&'a String> {
+        self.priority.iter()
+            .find_map(|p| labels.iter().find(|l| *l == p))
+            .or_else(|| labels.first())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LabelMapping;
+    use crate::config::CsvHeaders;
+    use crate::movie::Genre::*;
+
+    fn mapping() -> LabelMapping {
+        serde_json::from_str(r#"{
+            "merge": { "Suspense": ["Thriller", "Mystery", "Crime"] },
+            "drop": ["TalkShow", "GameShow", "RealityTV", "News"],
+            "priority": ["Horror", "Suspense"]
+        }"#).unwrap()
+    }
+
+    #[test]
+    fn map() {
+        let mapping = mapping();
+        mapping.validate(&CsvHeaders::default()).unwrap();
+
+        assert_eq!(mapping.map(&[Drama, Crime, Thriller, News]), ["Drama", "Suspense"]);
+        assert_eq!(mapping.map(&[TalkShow]), Vec::<String>::new());
+        assert_eq!(LabelMapping::default().map(&[SciFi, Crime]), ["SciFi", "Crime"]);
+
+        let all = mapping.all();
+        assert_eq!(all.len(), 28 - 4 - 3 + 1);
+        assert!(all.contains(&"Suspense".to_owned()));
+        assert!(!all.contains(&"Crime".to_owned()));
+    }
+
+    #[test]
+    fn primary() {
+        let mapping = mapping();
+
+        let labels = mapping.map(&[Drama, Crime, Horror]);
+        assert_eq!(mapping.primary(&labels).unwrap(), "Horror");
+        let labels = mapping.map(&[Drama, Crime]);
+        assert_eq!(mapping.primary(&labels).unwrap(), "Suspense");
+        let labels = mapping.map(&[Drama, Comedy]);
+        assert_eq!(mapping.primary(&labels).unwrap(), "Drama");
+        assert_eq!(mapping.primary(&[]), None);
+    }
+
+    #[test]
+    fn validate() {
+        let headers = CsvHeaders::default();
+
+        let mut merged_and_dropped = mapping();
+        merged_and_dropped.drop.push(Crime);
+        assert!(merged_and_dropped.validate(&headers).is_err());
+
+        for label in &["Crime", "News"] {
+            let mut unknown_priority = mapping();
+            unknown_priority.priority.push(label.to_string());
+            assert!(unknown_priority.validate(&headers).is_err(), "{}", label);
+        }
+
+        for column in &["weight", "Weight", "Spectrogram"] {
+            let mut fixed_column = mapping();
+            fixed_column.merge.insert(column.to_string(), vec![Western]);
+            assert!(fixed_column.validate(&headers).is_err(), "{}", column);
+        }
+
+        let renamed = CsvHeaders {
+            labels: "Genres".to_owned(),
+            ..Default::default()
+        };
+        let mut header = mapping();
+        header.merge.insert("Labels".to_owned(), vec![Western]);
+        assert!(header.validate(&headers).is_err());
+        assert!(header.validate(&renamed).is_ok());
+    }
+}
@@ -0,0 +1,148 @@
+//! Paths of the files in a working directory.
+
+use crate::error::*;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Where every file of a dataset lives, relative to its working directory:
+///
+/// ```text
+/// metadata/      movies.json, manifest.json, state.json
+/// audio/         stored trailer audio, sharded by id
+/// spectrograms/  trailer-{id}.jpg, sharded by id
+/// logs/          youtube-dl logs sharded by id, rejected.csv
+/// exports/       movies_selected.csv and other exports
+/// tmp/           downloads in progress
+/// ```
+///
+/// Large directories are split into subdirectories named after the first two characters of the
+/// id, so `spectrograms/dQ/trailer-dQw4w9WgXcQ.jpg`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layout {
+    root: PathBuf,
+}
+
+impl Layout {
+    pub fn new<T: Into<PathBuf>>(root: T) -> Layout {
+        Layout {
+            root: root.into(),
+        }
+    }
+
+    pub fn create_dirs(&self) -> Result<()> {
+        for dir in &["metadata", "audio", "spectrograms", "logs", "exports", "tmp"] {
+            fs::create_dir_all(self.root.join(dir))?;
+        }
+
+        Ok(())
+    }
+
+    pub fn movies_json(&self) -> PathBuf {
+        self.root.join("metadata").join("movies.json")
+    }
+
+    pub fn manifest_json(&self) -> PathBuf {
+        self.root.join("metadata").join("manifest.json")
+    }
+
+    /// Status of every movie in every pipeline stage.
+    pub fn state_json(&self) -> PathBuf {
+        self.root.join("metadata").join("state.json")
+    }
+
+    pub fn audio_dir(&self) -> PathBuf {
+        self.root.join("audio")
+    }
+
+    pub fn exports_dir(&self) -> PathBuf {
+        self.root.join("exports")
+    }
+
+    pub fn movies_selected_csv(&self) -> PathBuf {
+        self.exports_dir().join("movies_selected.csv")
+    }
+
+    pub fn labels_txt(&self) -> PathBuf {
+        self.exports_dir().join("labels.txt")
+    }
+
+    /// Where the `report` command writes the dataset report and its charts.
+    pub fn report_dir(&self) -> PathBuf {
+        self.exports_dir().join("report")
+    }
+
+    /// Where the `evaluate` command writes its reports.
+    pub fn evaluation_dir(&self) -> PathBuf {
+        self.exports_dir().join("evaluation")
+    }
+
+    /// Where the `train-baseline` command writes the evaluation of its model.
+    pub fn baseline_dir(&self) -> PathBuf {
+        self.exports_dir().join("baseline")
+    }
+
+    pub fn rejected_csv(&self) -> PathBuf {
+        self.root.join("logs").join("rejected.csv")
+    }
+
+    /// Spectrogram of the movie, relative to the root, as recorded in the manifest.
+    pub fn spectrogram(&self, movie_id: &str) -> PathBuf {
+        Path::new("spectrograms").join(shard(movie_id)).join(format!("trailer-{}.jpg", movie_id))
+    }
+
+    /// Where the audio of the movie is downloaded to before processing.
+    pub fn download(&self, movie_id: &str) -> PathBuf {
+        self.root.join("tmp").join(format!("trailer-{}.m4a", movie_id))
+    }
+
+    /// Prefix of the log files of the movie; the extension is replaced by the log name.
+    pub fn logs(&self, movie_id: &str) -> PathBuf {
+        self.root.join("logs").join(shard(movie_id)).join(format!("trailer-{}", movie_id))
+    }
+
+    /// Turns a path relative to the root into a usable one.
+    pub fn resolve<T: AsRef<Path>>(&self, path: T) -> PathBuf {
+        self.root.join(path)
+    }
+}
+
+/// Subdirectory name of an id in sharded directories.
+pub fn shard(id: &str) -> String {
+    let shard: String = id.chars().take(2).collect();
+
+    match shard.as_str() {
+        "" => "_".to_owned(),
+        // Avoid special names like `.` and `..`.
+        s if s.starts_with('.') => format!("_{}", s),
+        s => s.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Layout;
+
+    use std::path::Path;
+
+    #[test]
+    fn paths() {
+        let layout = Layout::new("/data/v2");
+
+        assert_eq!(layout.movies_json(), Path::new("/data/v2/metadata/movies.json"));
+        assert_eq!(layout.spectrogram("dQw4w9WgXcQ"), Path::new("spectrograms/dQ/trailer-dQw4w9WgXcQ.jpg"));
+        assert_eq!(
+            layout.resolve(layout.spectrogram("dQw4w9WgXcQ")),
+            Path::new("/data/v2/spectrograms/dQ/trailer-dQw4w9WgXcQ.jpg"),
+        );
+        assert_eq!(layout.logs("-x1").with_extension("out.log"), Path::new("/data/v2/logs/-x/trailer--x1.out.log"));
+    }
+
+    #[test]
+    fn shard() {
+        assert_eq!(super::shard("abc"), "ab");
+        assert_eq!(super::shard("a"), "a");
+        assert_eq!(super::shard(""), "_");
+        assert_eq!(super::shard(".."), "_..");
+    }
+}
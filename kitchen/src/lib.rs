@@ -0,0 +1,50 @@
+//! Builds a dataset of movie trailer spectrograms labeled with the genres of the movie.
+//!
+//! The `kitchen` binary runs the whole pipeline, but every step is usable on its own:
+//!
+//! - Loaders: [`movielens`] reads the movies and their trailer ids, [`imdb`] fills in genres,
+//!   critics and metascores, matching titles with [`title`].
+//! - Providers: [`external`] searches and downloads trailers with youtube-dl, [`validation`] and
+//!   [`fingerprint`] decide whether a video is the right trailer.
+//! - Processors: [`trailer`] turns trailers into spectrograms with ffmpeg, or with `decode`
+//!   under the `native-decode` feature, and records them in the [`manifest`].
+//! - Exporters: [`dataset`] selects and labels movies as set by the [`config`], [`export`]
+//!   packages them, and [`report`] summarizes them. [`evaluation`] scores a model trained on
+//!   them, and `baseline` trains a simple one under the `baseline` feature.
+//!
+//! Files are placed in a working directory as described by [`layout::Layout`], and [`pipeline`]
+//! records which movies each stage has processed. Under the `predict` feature, `predict` runs a
+//! trained model on new trailers, and `serve` exposes it over HTTP with the `serve` feature.
+
+pub mod error;
+pub mod external;
+pub mod movie;
+pub mod movielens;
+pub mod imdb;
+pub mod fingerprint;
+pub mod validation;
+pub mod trailer;
+pub mod throttle;
+pub mod store;
+pub mod manifest;
+pub mod layout;
+pub mod config;
+pub mod labels;
+pub mod balance;
+pub mod dataset;
+pub mod export;
+pub mod tfrecord;
+pub mod title;
+pub mod report;
+pub mod pipeline;
+pub mod evaluation;
+#[cfg(feature = "baseline")]
+pub mod baseline;
+#[cfg(feature = "native-decode")]
+pub mod decode;
+#[cfg(feature = "predict")]
+pub mod predict;
+#[cfg(feature = "serve")]
+pub mod serve;
+
+pub use error::{Error, Result};
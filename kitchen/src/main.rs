@@ -1,247 +1,674 @@
-mod error;
-mod external;
-mod movie;
-mod movielens;
-mod imdb;
+use kitchen::config::Config;
+use kitchen::dataset::{self, Dataset};
+use kitchen::error::*;
+use kitchen::evaluation::{self, Evaluation, Truth};
+#[cfg(feature = "baseline")]
+use kitchen::baseline::{self, Baseline, TrainOptions};
+#[cfg(feature = "baseline")]
+use kitchen::dataset::Split;
+use kitchen::export::{self, ExportFormat};
+use kitchen::external::{self, PreprocessOptions};
+use kitchen::layout::Layout;
+use kitchen::manifest::{Manifest, SpectrogramParams};
+use kitchen::movie::Movie;
+use kitchen::pipeline::{Action, Plan, Stage, State, Status};
+#[cfg(feature = "predict")]
+use kitchen::predict::{self, Aggregation, Model};
+#[cfg(feature = "serve")]
+use kitchen::serve::{Limits, Service};
+use kitchen::report::{self, Report};
+use kitchen::store::{AudioFormat, AudioStore};
+use kitchen::{imdb, movielens, throttle, trailer};
 
-use error::*;
-use movie::Movie;
-
-use std::collections::HashMap;
 use std::env;
-use std::fs::{self, File};
-use std::path::{Path, PathBuf};
+use std::fs::File;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
 
-use csv;
 use indicatif::ProgressBar;
-use lazy_static::lazy_static;
 use rayon::prelude::*;
 
-lazy_static! {
-    static ref MOVIES_JSON_PATH: &'static Path = Path::new("movies.json");
-}
-
 fn main() -> Result<()> {
-    let mut args = env::args();
-    let mut stage = 0;
+    let mut args = env::args().skip(1);
+    let mut command = None;
+    let mut workdir = PathBuf::from(".");
+    let mut config = Config::default();
+    let mut export_name = "movie-trailers".to_owned();
+    let mut export_version = "1".to_owned();
+    let mut export_format = ExportFormat::Archive;
+    let mut shard_size = 1000;
+    let mut stage = Stage::Enrich;
+    let mut argument: Option<String> = None;
     let mut save = true;
+    let mut preprocess = PreprocessOptions::default();
+    let mut jobs = trailer::Jobs::default();
+    let mut host_limit = throttle::Limit::default();
+    let mut audio_format = AudioFormat::Original;
+    let mut audio_max_size = None;
+    let mut keep_audio = true;
+    let mut dry_run = false;
+    let mut list = false;
+    let mut cascade = false;
+    let mut dataset_dir = None;
+    #[cfg(feature = "predict")]
+    let (mut model_path, mut labels_path, mut aggregation) = (None, None, Aggregation::Mean);
+    #[cfg(feature = "serve")]
+    let (mut listen, mut limits) = ("127.0.0.1:8000".to_owned(), Limits::default());
 
     while let Some(arg) = args.next() {
         match &*arg {
-            "--stage" => stage = args.next().unwrap().parse().unwrap(),
+            "--workdir" => workdir = PathBuf::from(args.next().unwrap()),
+            "--config" => config = Config::load(args.next().unwrap())?,
+            "--name" => export_name = args.next().unwrap(),
+            "--dataset-version" => export_version = args.next().unwrap(),
+            "--format" => export_format = ExportFormat::from_text(&args.next().unwrap())?,
+            "--shard-size" => shard_size = args.next().unwrap().parse().unwrap(),
+            "--stage" => stage = Stage::from_text(&args.next().unwrap())?,
             "--nosave" => save = false,
+            "--loudness" => preprocess.loudness = Some(args.next().unwrap().parse().unwrap()),
+            "--trim-silence" => preprocess.silence_threshold = Some(args.next().unwrap().parse().unwrap()),
+            "--cut-start" => preprocess.cut_start = args.next().unwrap().parse().unwrap(),
+            "--download-jobs" => jobs.downloads = args.next().unwrap().parse().unwrap(),
+            "--process-jobs" => jobs.processing = args.next().unwrap().parse().unwrap(),
+            "--queue" => jobs.queue = args.next().unwrap().parse().unwrap(),
+            "--host-concurrency" => host_limit.concurrent = args.next().unwrap().parse().unwrap(),
+            "--host-interval" => host_limit.interval = Duration::from_millis(args.next().unwrap().parse().unwrap()),
+            "--audio-format" => audio_format = AudioFormat::from_text(&args.next().unwrap())?,
+            "--audio-max-size" => audio_max_size = Some(args.next().unwrap().parse::<u64>().unwrap() * 1024 * 1024),
+            "--discard-audio" => keep_audio = false,
+            "--dry-run" => dry_run = true,
+            "--list" => list = true,
+            "--cascade" => cascade = true,
+            "--dataset" => dataset_dir = Some(PathBuf::from(args.next().unwrap())),
+            #[cfg(feature = "predict")]
+            "--model" => model_path = Some(PathBuf::from(args.next().unwrap())),
+            #[cfg(feature = "predict")]
+            "--labels" => labels_path = Some(PathBuf::from(args.next().unwrap())),
+            #[cfg(feature = "predict")]
+            "--aggregate" => aggregation = Aggregation::from_text(&args.next().unwrap())?,
+            #[cfg(feature = "serve")]
+            "--listen" => listen = args.next().unwrap(),
+            #[cfg(feature = "serve")]
+            "--request-max-size" => limits.max_body = args.next().unwrap().parse::<usize>().unwrap() * 1024 * 1024,
+            #[cfg(feature = "serve")]
+            "--serve-jobs" => limits.concurrency = args.next().unwrap().parse().unwrap(),
+            cmd if command.is_none() && !cmd.starts_with('-') => command = Some(cmd.to_owned()),
+            arg if argument.is_none() && !arg.starts_with('-') => argument = Some(arg.to_owned()),
             _ => {},
         }
     }
 
+    throttle::set_limit(host_limit);
+
+    let layout = Layout::new(workdir);
+
+    #[cfg(feature = "predict")]
+    if command.as_deref() == Some("predict") || (cfg!(feature = "serve") && command.as_deref() == Some("serve")) {
+        let labels_path = labels_path.unwrap_or_else(|| layout.movies_selected_csv());
+        let labels = predict::read_labels(labels_path, &config.csv.headers)?;
+        let model_path = model_path.ok_or("a model is needed, pass it with --model")?;
+        let model = Model::load(&model_path, labels)?;
+        // Spectrograms are made like those of the dataset, unless preprocessing flags are given.
+        let params = if preprocess == PreprocessOptions::default() {
+            predict::dataset_params(dataset_dir.as_deref(), &layout.manifest_json())?
+        } else {
+            None
+        };
+        let params = params.unwrap_or_else(|| SpectrogramParams::new(preprocess));
+
+        #[cfg(feature = "serve")]
+        if command.as_deref() == Some("serve") {
+            let server = tiny_http::Server::http(&listen).map_err(|e| e.to_string())?;
+            println!("Listening on http://{}", listen);
+            Service { model, model_path, params, aggregation, limits }.run(&server);
+
+            return Ok(());
+        }
+
+        let input = argument.ok_or("predict needs an audio file or a YouTube id")?;
+        external::check_ffmpeg(&params.preprocess, false)?;
+        let probabilities = model.predict(&input, &params, aggregation)?;
+        for (label, probability) in model.labels.iter().zip(probabilities) {
+            println!("{:.3}\t{}", probability, label);
+        }
+
+        return Ok(());
+    }
+
+    if command.as_deref() == Some("evaluate") {
+        let predictions = argument.ok_or("evaluate needs a predictions file")?;
+        let truth = match dataset_dir {
+            Some(dir) => Truth::from_export(dir)?,
+            None => {
+                let movies: Vec<Movie> = serde_json::from_reader(File::open(layout.movies_json())?)?;
+                let manifest = Manifest::load(layout.manifest_json())?;
+                Truth::from_dataset(&Dataset::select(&movies, &manifest, &config.selection, &config.labels)?)
+            },
+        };
+
+        let predictions = evaluation::read_predictions(predictions, &truth.labels)?;
+        let evaluation = Evaluation::new(&truth, &predictions);
+        for (split, metrics) in &evaluation.splits {
+            println!("{}: {} movies, micro F1 {:.3}, macro F1 {:.3}", split, metrics.samples, metrics.micro.f1, metrics.macro_.f1);
+        }
+        if let Some(tuned) = &evaluation.tuned {
+            println!("validation with tuned thresholds: micro F1 {:.3}, macro F1 {:.3}", tuned.micro.f1, tuned.macro_.f1);
+        }
+
+        let path = evaluation.write(layout.evaluation_dir())?;
+        println!("Evaluation written to {}", path.display());
+
+        return Ok(());
+    }
+
+    // Every other command takes a stage, if anything.
+    let target = argument.as_deref().map(Stage::from_text).transpose()?;
+
+    // Plans are worked out from what is on disk, without changing it.
+    let planning = dry_run || command.as_deref() == Some("plan");
+    if planning {
+        let movies = if layout.movies_json().exists() {
+            serde_json::from_reader(File::open(layout.movies_json())?)?
+        } else {
+            println!("The MovieLens dataset would be downloaded first.");
+            vec![]
+        };
+        let manifest = load_manifest(&layout, &movies)?;
+        let state = load_state(&layout, &movies, &manifest)?;
+
+        let stages: Vec<_> = match (command.as_deref(), target) {
+            (Some("plan"), Some(target)) | (Some("run"), Some(target)) => vec![target],
+            (Some("plan"), None) | (Some("run"), None) => Stage::ALL.to_vec(),
+            (Some("run-until"), Some(target)) => Stage::ALL.iter().copied().filter(|s| *s <= target).collect(),
+            (None, _) => Stage::ALL.iter().copied().filter(|s| *s >= stage).collect(),
+            _ => Err("--dry-run only applies to running stages")?,
+        };
+
+        let plans = Plan::chain(&stages, &movies, &manifest, &state, &config.selection);
+
+        // With --list, stdout is left to the CSV.
+        let print = |text: &str| if list { eprintln!("{}", text) } else { println!("{}", text) };
+        if plans.len() > 1 {
+            print("Stages are planned assuming the ones before them succeed, so later plans are upper bounds.");
+        }
+        for plan in &plans {
+            print(&describe_plan(plan, &movies, &manifest, &config)?);
+        }
+
+        if list {
+            let mut writer = csv::Writer::from_writer(std::io::stdout());
+            writer.write_record(["Stage", "Id", "Name", "Year", "Action"])?;
+            for plan in &plans {
+                for (i, action) in &plan.tasks {
+                    let movie = &movies[*i];
+                    let year = movie.year.map(|y| y.to_string()).unwrap_or_default();
+                    writer.write_record([plan.stage.name(), &movie.youtube_id, &movie.name, &year, action.name()])?;
+                }
+            }
+            writer.flush()?;
+        }
+
+        return Ok(());
+    }
+
+    layout.create_dirs()?;
+
+    let store = AudioStore::new(layout.audio_dir(), audio_format, audio_max_size);
+
     println!("Loading movies...");
 
-    let mut movies = init_movies()?;
+    let movies = init_movies(&layout)?;
+    let mut manifest = load_manifest(&layout, &movies)?;
+    if save && !layout.manifest_json().exists() && !manifest.artifacts.is_empty() {
+        manifest.save(layout.manifest_json())?;
+    }
+    let params = SpectrogramParams::new(preprocess);
+
+    match command.as_deref() {
+        None => {},
+        Some("regenerate-features") => {
+            external::check_ffmpeg(&params.preprocess, store.format() == AudioFormat::Opus)?;
+            println!("Regenerating spectrograms from stored audio...");
+            regenerate_features(&layout, &mut manifest, &store, &params, jobs.processing);
+
+            if save {
+                println!("Saving manifest into disk...");
+                manifest.save(layout.manifest_json())?;
+            }
+
+            return Ok(());
+        },
+        Some("export") => {
+            let dataset = Dataset::select(&movies, &manifest, &config.selection, &config.labels)?;
+
+            println!("Exporting {} movies...", dataset.samples.len());
+            let path = match export_format {
+                ExportFormat::Archive => export::export(&layout, &dataset, &config, &export_name, &export_version)?,
+                format => export::export_shards(&layout, &dataset, format, &export_name, &export_version, shard_size)?,
+            };
+            println!("Exported to {}", path.display());
+
+            return Ok(());
+        },
+        Some("report") => {
+            let dataset = Dataset::select(&movies, &manifest, &config.selection, &config.labels)?;
+            let rejected = report::read_rejected(layout.rejected_csv())?;
+
+            println!("Writing report...");
+            let report = Report::new(&movies, &manifest, &dataset, &config.selection, &rejected);
+            let path = report.write(layout.report_dir())?;
+            println!("Report written to {}", path.display());
+
+            return Ok(());
+        },
+        #[cfg(feature = "baseline")]
+        Some("train-baseline") => {
+            let dataset = Dataset::select(&movies, &manifest, &config.selection, &config.labels)?;
+            let stop_hz = Manifest::common_params(dataset.samples.iter().map(|s| s.artifact))?
+                .map(|p| p.stop_hz)
+                .unwrap_or(params.stop_hz);
+
+            println!("Pooling the spectrograms of {} movies...", dataset.samples.len());
+            let features = dataset.samples.par_iter()
+                .map(|s| {
+                    let file = s.artifact.files.first().ok_or_else(|| format!("artifact of {} has no files", s.movie.youtube_id))?;
+                    baseline::pool_file(layout.resolve(file), stop_hz)
+                })
+                .collect::<Result<Vec<_>>>()?;
+
+            let truth = Truth::from_dataset(&dataset);
+            let (train_x, train_y): (Vec<_>, Vec<_>) = truth.samples.iter().zip(&features)
+                .filter(|((_, _, split), _)| *split == Split::Train)
+                .map(|((_, targets, _), x)| (x.clone(), targets.clone()))
+                .unzip();
+            if train_x.is_empty() {
+                Err("no movies in the train split")?;
+            }
+
+            println!("Training on {} movies...", train_x.len());
+            let model = Baseline::fit(&train_x, &train_y, &dataset.pos_weights(), &TrainOptions::default());
+            let predictions = truth.samples.iter().zip(&features)
+                .map(|((key, _, _), x)| (key.clone(), model.predict(x)))
+                .collect();
+
+            let evaluation = Evaluation::new(&truth, &predictions);
+            match evaluation.splits.get("validation") {
+                Some(metrics) => {
+                    for class in &metrics.classes {
+                        println!("{:>12}  F1 {:.3}  ROC-AUC {}", class.label, class.f1,
+                            class.roc_auc.map(|v| format!("{:.3}", v)).unwrap_or_else(|| "-".to_owned()));
+                    }
+                    println!("Validation: micro F1 {:.3}, macro F1 {:.3}, subset accuracy {:.3}",
+                        metrics.micro.f1, metrics.macro_.f1, metrics.subset_accuracy);
+                },
+                None => println!("No movies in the validation split to report on."),
+            }
+
+            let path = evaluation.write(layout.baseline_dir())?;
+            println!("Evaluation written to {}", path.display());
+
+            return Ok(());
+        },
+        Some(_) => {},
+    }
+
+    let state = load_state(&layout, &movies, &manifest)?;
+    let stages: Vec<_> = match command.as_deref() {
+        None => Stage::ALL.iter().copied().filter(|s| *s >= stage).collect(),
+        Some("run") => match target {
+            Some(target) => vec![target],
+            None => Stage::ALL.to_vec(),
+        },
+        Some("run-until") => {
+            let target = target.ok_or("run-until needs a stage")?;
+            Stage::ALL.iter().copied().filter(|s| *s <= target).collect()
+        },
+        Some("invalidate") => {
+            let target = target.ok_or("invalidate needs a stage")?;
+            let mut state = state;
+            for stage in state.invalidate(target, cascade) {
+                println!("Invalidated stage {}", stage);
+            }
+            if !cascade && !target.dependents().is_empty() {
+                println!("Stages after it keep their outputs; pass --cascade to invalidate them too");
+            }
+            state.save(layout.state_json())?;
+
+            return Ok(());
+        },
+        Some("status") => {
+            print_status(&state);
+            return Ok(());
+        },
+        Some(cmd) => Err(format!("unknown command '{}'", cmd))?,
+    };
+
+    let mut run = Run {
+        layout,
+        config,
+        movies,
+        manifest,
+        state,
+        store: if keep_audio { Some(store) } else { None },
+        params,
+        jobs,
+        save,
+        produced: vec![],
+    };
+
+    for stage in stages {
+        run.stage(stage)?;
+    }
+
+    Ok(())
+}
+
+/// Everything the stages work on.
+struct Run {
+    layout: Layout,
+    config: Config,
+    movies: Vec<Movie>,
+    manifest: Manifest,
+    state: State,
+    store: Option<AudioStore>,
+    params: SpectrogramParams,
+    jobs: trailer::Jobs,
+    save: bool,
+    /// Outputs of the stages run so far, available to the next ones even when not saved.
+    produced: Vec<&'static str>,
+}
 
-    if stage == 0 {
+impl Run {
+    fn stage(&mut self, stage: Stage) -> Result<()> {
+        for dependency in stage.info().depends_on {
+            if !self.state.has_run(*dependency) {
+                return Err(Error::Generic(format!("stage {} needs stage {} to run first", stage, dependency)));
+            }
+        }
+        if let Some(input) = stage.missing_input(&self.layout, &self.produced) {
+            return Err(Error::Generic(format!("stage {} needs {}, which is missing", stage, input)));
+        }
+
+        match stage {
+            Stage::Enrich => self.enrich()?,
+            Stage::Download => self.download()?,
+            Stage::Select => self.select()?,
+        }
+
+        if self.save {
+            self.state.save(self.layout.state_json())?;
+        }
+        self.produced.extend(stage.info().outputs);
+
+        Ok(())
+    }
+
+    fn enrich(&mut self) -> Result<()> {
         println!("Filling missing information...");
 
-        let movies_2000: Vec<_> = movies.iter_mut()
-            .filter(|m| m.year >= Some(2000))
+        let plan = Plan::enrich(&self.movies, &self.state, &self.config.selection);
+        let mut actions = vec![None; self.movies.len()];
+        for (i, action) in &plan.tasks {
+            actions[*i] = Some(*action);
+        }
+
+        let movies: Vec<_> = self.movies.iter_mut()
+            .zip(actions)
+            .filter_map(|(movie, action)| Some((movie, action?)))
             .collect();
-    
-        let bar = ProgressBar::new(movies_2000.len() as u64);
+
+        let statuses = Mutex::new(vec![]);
+        let bar = ProgressBar::new(movies.len() as u64);
         let pool = rayon::ThreadPoolBuilder::new().num_threads(16).build().unwrap();
         pool.install(|| {
-            movies_2000.into_par_iter().for_each(|movie| {
-                let result = fill_movie_info(movie);
-                if result.is_err() {
-                    eprintln!("Failed to fill '{}' ({:?}): {}\n",
-                        movie.name, movie.year, result.unwrap_err().to_string());
-                }
+            movies.into_par_iter().for_each(|(movie, action)| {
+                let result = match action {
+                    Action::Fetch => imdb::fetch_movie_info(movie),
+                    _ => Ok(()),
+                };
+
+                let status = match result {
+                    Ok(()) => Status::Done,
+                    Err(err) => {
+                        eprintln!("Failed to fill '{}' ({:?}): {}\n", movie.name, movie.year, err);
+                        Status::Failed(err.to_string())
+                    },
+                };
+                statuses.lock().unwrap().push((movie.youtube_id.clone(), status));
                 bar.inc(1);
             });
         });
 
-        if save {
+        for (id, status) in statuses.into_inner().unwrap() {
+            self.state.set(Stage::Enrich, &id, status);
+        }
+
+        if self.save {
             println!("Saving movies into disk...");
-            save_movies(&movies)?;
-        }
-
-        stage += 1;
-    }
-    
-    let movies_selected: Vec<_> = movies.par_iter()
-        .filter(|m| m.year >= Some(2000))
-        .filter(|m| m.critics_number.is_some())
-        .filter(|m| m.critics_number.unwrap() >= 100)
-        .map(|m| {
-            let mut path = PathBuf::new();
-            path.set_file_name(format!("trailer-{}", m.youtube_id));
-            path.set_extension("jpg");
-
-            (m, path)
-        })
-        .collect();
-
-    if stage == 1 {
-        let mut movies_selected: Vec<_> = movies_selected.par_iter()
-            .filter(|(_, p)| !p.exists())
-            .map(|(m, p)| {
-                let mut pn = p.clone();
-                pn.set_extension("m4a");
-                (m, pn)
-            })
-            .collect();
-        
-        movies_selected.sort_by_key(|m| !m.0.critics_number.unwrap());
+            save_movies(&self.layout, &self.movies)?;
+        }
+
+        Ok(())
+    }
+
+    fn download(&mut self) -> Result<()> {
+        let plan = Plan::download(&self.movies, &self.manifest, &self.state, &self.config.selection);
+        for (i, status) in plan.settled {
+            self.state.set(Stage::Download, &self.movies[i].youtube_id, status);
+        }
+
+        let movies = &self.movies;
+        let movies_selected: Vec<_> = plan.tasks.iter().map(|(i, _)| (*i, &movies[*i])).collect();
 
         println!("Downloading {} movie trailers...", movies_selected.len());
+        let opus = self.store.as_ref().map(|s| s.format()) == Some(AudioFormat::Opus);
+        external::check_ffmpeg(&self.params.preprocess, opus)?;
+
+        // Each stored file evicts older ones, but a lowered --audio-max-size applies right away.
+        if let Some(store) = &self.store {
+            let freed = store.enforce_retention()?;
+            if freed > 0 {
+                println!("Evicted {} MiB of stored audio to stay under --audio-max-size", freed / 1024 / 1024);
+            }
+        }
 
+        let fetcher = trailer::Fetcher::new(&self.movies, self.params.clone(), self.store.take(), self.layout.clone())?;
+        let trailers = Mutex::new(vec![]);
+        let statuses = Mutex::new(vec![]);
         let bar = ProgressBar::new(movies_selected.len() as u64);
-        let pool = rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap();
-        pool.install(|| {
-            movies_selected.par_iter().for_each(|(movie, path)| {
-                let result = external::download_youtube_m4a_by_id(&movie.youtube_id, &path)
-                    .or_else(|_| {
-                        let query = if let Some(year) = movie.year {
-                            format!("{} {} movie trailer", movie.name, year)
-                        } else {
-                            format!("{} movie trailer", movie.name)
-                        };
-
-                        external::download_youtube_m4a_by_search(&query, &path)
-                    });
-
-                if result.is_err() {
-                    eprintln!("Failed to download '{}' ({:?}): {}\n",
-                        movie.name, movie.year, result.unwrap_err().to_string());
-                    return;
-                }
+        fetcher.run(&movies_selected, self.jobs, |i, movie, result| {
+            let status = match result {
+                Ok(done) => {
+                    trailers.lock().unwrap().push((i, done));
+                    Status::Done
+                },
+                Err(err) => {
+                    eprintln!("Failed to download '{}' ({:?}): {}\n", movie.name, movie.year, err);
+                    Status::Failed(err.to_string())
+                },
+            };
+            statuses.lock().unwrap().push((movie.youtube_id.clone(), status));
+            bar.inc(1);
+        });
 
-                let audio_len = external::get_audio_length(&path).unwrap();
-                if audio_len > 10 * 60 * 1000 {
-                    eprintln!("Failed to download '{}' ({:?}): length > 10 minutes\n", movie.name, movie.year);
-                } else {
-                    external::make_spectrogram(&path, (audio_len as usize / 100, 224)).unwrap();
-                }
+        for (id, status) in statuses.into_inner().unwrap() {
+            self.state.set(Stage::Download, &id, status);
+        }
 
-                fs::remove_file(path).unwrap();
-                bar.inc(1);
-            });
-        });
+        for (i, (trailer, artifact)) in trailers.into_inner().unwrap() {
+            self.movies[i].trailer = Some(trailer);
+            self.manifest.insert(artifact);
+        }
+
+        if self.save {
+            println!("Saving movies and manifest into disk...");
+            save_movies(&self.layout, &self.movies)?;
+            self.manifest.save(self.layout.manifest_json())?;
+        }
 
-        stage += 1;
+        Ok(())
     }
 
-    if stage == 2 {
-        let exist_movies: Vec<_> = movies_selected.iter()
-            .filter(|(_, p)| p.exists())
-            .map(|(m, _)| *m)
-            .collect();
+    fn select(&mut self) -> Result<()> {
+        let config = &self.config;
+        let plan = Plan::select(&self.movies, &self.manifest, &config.selection);
+        let movies = &self.movies;
+        let all: Vec<_> = plan.tasks.iter().map(|(i, _)| &movies[*i]).collect();
 
-        println!("All:\t{}", exist_movies.len());
+        println!("All:\t{}", all.len());
         println!("Number of movies in each genre:");
-        let sorted = count_and_print_genres(&exist_movies, None);
-
-        println!("Selecting movies that have 1 in top 5 genres...");
-        let top_5_genres: Vec<_> = sorted.iter().rev().take(5).map(|(g, _)| *g).collect();
-        let top_5_movies: Vec<_> = exist_movies.into_iter()
-            .map(|m| (m, m.genres.iter()
-                .filter(|g| top_5_genres.contains(g))
-                .map(|g| *g)
-                .collect::<Vec<_>>()))
-            .filter(|(_, g)| g.len() > 0)
-            .collect();
+        print_counts(&dataset::count_genres(all.iter().copied()), all.len());
 
-        println!("Number of selected movies: {}", top_5_movies.len());
-        println!("Number of movies in each genre:");
-        count_and_print_genres(&top_5_movies.iter().map(|(m, _)| *m).collect::<Vec<_>>(), Some(&top_5_genres));
+        if config.selection.all_genres {
+            println!("Selecting movies that have any label...");
+        } else {
+            println!("Selecting movies that have 1 in top {} labels...", config.selection.top_genres);
+        }
+        let dataset = Dataset::select(&self.movies, &self.manifest, &config.selection, &config.labels)?;
+        let selected = dataset.movies();
+
+        println!("Number of selected movies: {}", selected.len());
+        println!("Number of movies with each label:");
+        print_counts(&dataset.label_counts(), selected.len());
+
+        println!("pos_weight of each label:");
+        for (label, pos_weight) in dataset.labels.iter().zip(dataset.pos_weights()) {
+            println!("{:.3}\t{}", pos_weight, label);
+        }
 
-        if save {
+        if self.save {
             println!("Saving selected movies to disk...");
+            dataset.write_csv(File::create(self.layout.movies_selected_csv())?, &config.csv)?;
+            dataset.write_labels(File::create(self.layout.labels_txt())?)?;
+        }
 
-            let mut wtr = csv::Writer::from_path("movies_selected.csv")?;
-            let mut headers: Vec<_> = top_5_genres.iter()
-                .map(|m| m.to_string())
-                .collect();
-            headers.insert(0, "Id".to_owned());
-            headers.insert(1, "Name".to_owned());
-            wtr.write_record(headers)?;
-    
-            for (movie, genres) in &top_5_movies {
-                wtr.write_field(&movie.youtube_id)?;
-                wtr.write_field(&movie.name)?;
-    
-                for genre in &top_5_genres {
-                    if genres.contains(genre) {
-                        wtr.write_field("1")?;
-                    } else {
-                        wtr.write_field("0")?;
-                    }
-                }
-    
-                wtr.write_record(None::<&[u8]>)?;
-            }
+        for (i, status) in plan.select_statuses(&self.movies, &dataset) {
+            self.state.set(Stage::Select, &self.movies[i].youtube_id, status);
         }
-    }
 
-    Ok(())
+        Ok(())
+    }
 }
 
-fn init_movies() -> Result<Vec<Movie>> {
-    if MOVIES_JSON_PATH.exists() {
-        return Ok(serde_json::from_reader(File::open(*MOVIES_JSON_PATH)?)?);
+fn init_movies(layout: &Layout) -> Result<Vec<Movie>> {
+    let path = layout.movies_json();
+    if path.exists() {
+        // Files saved before titles were parsed have no alternate titles at all.
+        let value: serde_json::Value = serde_json::from_reader(File::open(path)?)?;
+        let outdated = value.as_array()
+            .and_then(|movies| movies.first())
+            .is_some_and(|movie| movie.get("alternate_titles").is_none());
+        let mut movies: Vec<Movie> = serde_json::from_value(value)?;
+
+        if outdated {
+            println!("Parsing titles again from the MovieLens dataset...");
+            let updated = movielens::update_titles(&mut movies, &movielens::download()?);
+            println!("Updated the titles of {} movies", updated);
+            save_movies(layout, &movies)?;
+        }
+
+        return Ok(movies);
     }
 
     println!("Downloading MovieLens dataset...");
 
     let movies = movielens::download()?;
-    save_movies(&movies)?;
+    save_movies(layout, &movies)?;
 
     Ok(movies)
 }
 
-fn save_movies(movies: &Vec<Movie>) -> Result<()> {
-    Ok(serde_json::to_writer_pretty(File::create(*MOVIES_JSON_PATH)?, movies)?)
-}
+/// Loads the manifest. Before it is first saved, the spectrograms already on disk are imported.
+fn load_manifest(layout: &Layout, movies: &[Movie]) -> Result<Manifest> {
+    if layout.manifest_json().exists() {
+        return Manifest::load(layout.manifest_json());
+    }
+
+    let mut manifest = Manifest::default();
+    let imported = manifest.import_spectrograms(layout, movies);
+    if imported > 0 {
+        println!("Imported {} spectrograms made before the manifest", imported);
+    }
 
-fn fill_movie_info(movie: &mut Movie) -> Result<()> {
-    if movie.genres.len() == 0
-        || movie.critics_number.is_none()
-        || movie.metacritic_score.is_none() {
-        let imdb_id = imdb::search(&movie.name, movie.year)?;
-        let page = imdb::get_page(imdb_id)?;
+    Ok(manifest)
+}
 
-        movie.genres = imdb::get_genres(&page)?;
-        movie.critics_number = imdb::get_critics_number(&page).ok(); // ignore error
-        movie.metacritic_score = imdb::get_metascore(&page).ok();
+/// Loads the state, or works it out from the movies and manifest of an older working directory.
+fn load_state(layout: &Layout, movies: &[Movie], manifest: &Manifest) -> Result<State> {
+    if layout.state_json().exists() {
+        return State::load(layout.state_json());
     }
 
-    Ok(())
+    Ok(State::seed(movies, manifest))
 }
 
-fn count_and_print_genres(movies: &[&movie::Movie], selected_genre: Option<&[movie::Genre]>) -> Vec<(movie::Genre, usize)> {
-    let mut map: HashMap<movie::Genre, usize, _> = HashMap::new();
-    let mut n = 0;
+fn save_movies(layout: &Layout, movies: &[Movie]) -> Result<()> {
+    Ok(serde_json::to_writer_pretty(File::create(layout.movies_json())?, movies)?)
+}
 
-    for movie in movies {
-        for genre in &movie.genres {
-            if let Some(includes) = selected_genre {
-                if !includes.contains(genre) {
-                    continue;
-                }
+/// Rebuilds the spectrograms of every trailer from the audio store, without network access.
+fn regenerate_features(layout: &Layout, manifest: &mut Manifest, store: &AudioStore, params: &SpectrogramParams, jobs: usize) {
+    let bar = ProgressBar::new(manifest.artifacts.len() as u64);
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(jobs).build().unwrap();
+    pool.install(|| {
+        manifest.artifacts.par_iter_mut().for_each(|artifact| {
+            let output = match artifact.files.first() {
+                Some(file) => layout.resolve(file),
+                None => layout.resolve(layout.spectrogram(&artifact.movie)),
+            };
+
+            match store.get(&artifact.youtube_id) {
+                Some(audio) => match trailer::process_audio(&audio, output, params) {
+                    Ok(preprocessing) => {
+                        artifact.params = params.clone();
+                        artifact.preprocessing = preprocessing;
+                    },
+                    Err(err) => eprintln!("Failed to process '{}': {}\n", artifact.movie, err),
+                },
+                None => eprintln!("Missing stored audio of '{}'\n", artifact.movie),
             }
+            bar.inc(1);
+        });
+    });
+}
 
-            let mut next = 0;
-            if let Some(old) = map.get(genre) {
-                next = *old;
-            }
-            next += 1;
-            map.insert(*genre, next);
-        }
-        n += 1;
+/// What a stage would do, in a few lines.
+fn describe_plan(plan: &Plan, movies: &[Movie], manifest: &Manifest, config: &Config) -> Result<String> {
+    let mut text = format!("{}:\t{} to process", plan.stage, plan.tasks.len());
+    if !plan.tasks.is_empty() {
+        let counts: Vec<_> = plan.counts().iter().map(|(a, n)| format!("{} {}", n, a)).collect();
+        text += &format!(" ({})", counts.join(", "));
     }
+    text += &format!(", {} settled without processing, {} left as they are", plan.settled.len(), plan.untouched);
 
-    let mut sorted: Vec<_> = map.into_iter().collect();
-    sorted.sort_by_key(|(_, i)| *i);
+    let (min, max) = plan.requests;
+    if max > 0 {
+        text += &format!("\n\t{} to {} requests", min, max);
+    }
+
+    if plan.stage == Stage::Select {
+        let dataset = Dataset::select(movies, manifest, &config.selection, &config.labels)?;
+        text += &format!("\n\t{} movies would be selected with labels {}", dataset.samples.len(), dataset.labels.join(", "));
+    }
 
-    sorted.iter().rev().for_each(|(g, i)| println!("{}\t{:02.2}\t{:?}", i, *i as f32 * 100.0 / n as f32, g));
+    Ok(text)
+}
 
-    sorted
-}
\ No newline at end of file
+fn print_status(state: &State) {
+    for stage in Stage::ALL {
+        let (statuses, reasons) = state.counts(stage);
+        let statuses: Vec<_> = statuses.iter().map(|(s, n)| format!("{} {}", n, s)).collect();
+        println!("{}:\t{}", stage, if statuses.is_empty() { "not run".to_owned() } else { statuses.join(", ") });
+
+        for (reason, n) in reasons {
+            println!("\t{}\t{}", n, reason);
+        }
+    }
+}
+
+fn print_counts<T: std::fmt::Display>(counts: &[(T, usize)], n: usize) {
+    for (label, i) in counts {
+        println!("{}\t{:02.2}\t{}", i, *i as f32 * 100.0 / n as f32, label);
+    }
+}
@@ -0,0 +1,294 @@
+//! Records how each spectrogram was made, in `manifest.json`.
+
+use crate::error::*;
+use crate::external::{self, PreprocessOptions, Preprocessing};
+use crate::layout::Layout;
+use crate::movie::{Movie, TrailerSource};
+
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Serialize, Deserialize};
+
+/// Everything that changes how a spectrogram looks. Artifacts made with different parameters
+/// must not end up in the same dataset.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpectrogramParams {
+    /// Milliseconds of audio per pixel column.
+    pub ms_per_column: u64,
+    pub height: usize,
+    pub stop_hz: u32,
+    /// `ffmpeg`, or `native` with the `native-decode` feature.
+    pub renderer: String,
+    pub preprocess: PreprocessOptions,
+}
+
+impl SpectrogramParams {
+    pub fn new(preprocess: PreprocessOptions) -> SpectrogramParams {
+        SpectrogramParams {
+            ms_per_column: 100,
+            height: 224,
+            stop_hz: external::SPECTROGRAM_STOP_HZ,
+            renderer: if cfg!(feature = "native-decode") { "native" } else { "ffmpeg" }.to_owned(),
+            preprocess,
+        }
+    }
+
+    /// Checks that this build renders spectrograms with the same renderer and frequency range,
+    /// e.g. before predicting with a model trained on a dataset made by another build.
+    pub fn check_renderer(&self) -> Result<()> {
+        let own = SpectrogramParams::new(self.preprocess.clone());
+        if self.renderer != own.renderer || self.stop_hz != own.stop_hz {
+            Err(format!(
+                "spectrograms were rendered by {} up to {} Hz, but this build renders them by {} up \
+                to {} Hz; build with the matching `native-decode` feature",
+                self.renderer, self.stop_hz, own.renderer, own.stop_hz,
+            ))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// One trailer turned into feature files.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Artifact {
+    /// Key of the movie: its YouTube id in MovieLens.
+    pub movie: String,
+    /// MovieLens movie id.
+    pub movie_id: Option<u32>,
+    /// YouTube id of the video the audio came from.
+    pub youtube_id: String,
+    pub source: TrailerSource,
+    /// MD5 of the downloaded audio.
+    pub audio_md5: String,
+    pub duration_ms: u64,
+    pub params: SpectrogramParams,
+    pub preprocessing: Option<Preprocessing>,
+    pub files: Vec<PathBuf>,
+    /// Seconds since the Unix epoch.
+    pub created_at: u64,
+}
+
+impl Artifact {
+    pub fn new(movie: &Movie, youtube_id: &str, source: TrailerSource, audio_md5: String) -> Artifact {
+        Artifact {
+            movie: movie.youtube_id.clone(),
+            movie_id: movie.movielens_id,
+            youtube_id: youtube_id.to_owned(),
+            source,
+            audio_md5,
+            duration_ms: 0,
+            params: SpectrogramParams::new(PreprocessOptions::default()),
+            preprocessing: None,
+            files: vec![],
+            created_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+        }
+    }
+}
+
+/// Records every artifact made so far, one per movie.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub artifacts: Vec<Artifact>,
+}
+
+impl Manifest {
+    /// Loads the manifest, or an empty one when the file doesn't exist yet.
+    pub fn load<T: AsRef<Path>>(path: T) -> Result<Manifest> {
+        if !path.as_ref().exists() {
+            return Ok(Manifest::default());
+        }
+
+        Ok(serde_json::from_reader(File::open(path)?)?)
+    }
+
+    pub fn save<T: AsRef<Path>>(&self, path: T) -> Result<()> {
+        Ok(serde_json::to_writer_pretty(File::create(path)?, self)?)
+    }
+
+    /// Adds the artifact, replacing the one of the same movie.
+    pub fn insert(&mut self, artifact: Artifact) {
+        self.artifacts.retain(|a| a.movie != artifact.movie);
+        self.artifacts.push(artifact);
+    }
+
+    pub fn get(&self, movie: &Movie) -> Option<&Artifact> {
+        self.artifacts.iter().find(|a| a.movie == movie.youtube_id)
+    }
+
+    /// Records the spectrograms made before there was a manifest, so they aren't downloaded again.
+    /// They were rendered by ffmpeg without preprocessing, either in the root of the working
+    /// directory or where the `Layout` puts them. Their audio hash and duration are unknown.
+    pub fn import_spectrograms(&mut self, layout: &Layout, movies: &[Movie]) -> usize {
+        let mut imported = 0;
+
+        for movie in movies {
+            if self.get(movie).is_some() {
+                continue;
+            }
+
+            let legacy = PathBuf::from(format!("trailer-{}.jpg", movie.youtube_id));
+            let candidates = [layout.spectrogram(&movie.youtube_id), legacy];
+            let file = match candidates.iter().find(|p| layout.resolve(p).exists()) {
+                Some(file) => file,
+                None => continue,
+            };
+
+            self.artifacts.push(Artifact {
+                params: SpectrogramParams {
+                    renderer: "ffmpeg".to_owned(),
+                    ..SpectrogramParams::new(PreprocessOptions::default())
+                },
+                files: vec![file.clone()],
+                ..Artifact::new(movie, &movie.youtube_id, TrailerSource::Id, String::new())
+            });
+            imported += 1;
+        }
+
+        imported
+    }
+
+    /// The parameters shared by all `artifacts`, or an error if they were made differently.
+    pub fn common_params<'a, I>(artifacts: I) -> Result<Option<&'a SpectrogramParams>>
+    where
+        I: IntoIterator<Item = &'a Artifact>,
+    {
+        let mut params: Option<&SpectrogramParams> = None;
+
+        for artifact in artifacts {
+            match params {
+                Some(p) if *p != artifact.params => return Err(format!(
+                    "artifacts made with different parameters: {:?} and {:?} (movie {}); \
+                    run regenerate-features to rebuild them",
+                    p, artifact.params, artifact.movie,
+                ))?,
+                Some(_) => {},
+                None => params = Some(&artifact.params),
+            }
+        }
+
+        Ok(params)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::{Artifact, Manifest, SpectrogramParams};
+    use crate::external::PreprocessOptions;
+    use crate::layout::Layout;
+    use crate::movie::{Genre, Movie, TrailerSource};
+
+    use std::fs;
+    use std::path::Path;
+
+    /// A Drama movie from 2010 with enough critics, named and keyed by `id`.
+    pub fn movie(id: &str) -> Movie {
+        Movie {
+            name: id.to_owned(),
+            youtube_id: id.to_owned(),
+            year: Some(2010),
+            genres: vec![Genre::Drama],
+            critics_number: Some(500),
+            ..Default::default()
+        }
+    }
+
+    /// A manifest with an artifact of the MovieLens video of each movie.
+    pub fn manifest<'a, I: IntoIterator<Item = &'a Movie>>(movies: I) -> Manifest {
+        let mut manifest = Manifest::default();
+        for movie in movies {
+            manifest.insert(Artifact::new(movie, &movie.youtube_id, TrailerSource::Id, String::new()));
+        }
+        manifest
+    }
+
+    fn artifact(key: &str, params: SpectrogramParams) -> Artifact {
+        let movie = Movie {
+            youtube_id: key.to_owned(),
+            ..Default::default()
+        };
+
+        Artifact {
+            params,
+            ..Artifact::new(&movie, key, TrailerSource::Id, String::new())
+        }
+    }
+
+    #[test]
+    fn insert_and_save() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("manifest.json");
+
+        let mut manifest = Manifest::load(&path).unwrap();
+        assert!(manifest.artifacts.is_empty());
+
+        let params = SpectrogramParams::new(PreprocessOptions::default());
+        manifest.insert(artifact("a", params.clone()));
+        manifest.insert(artifact("b", params.clone()));
+        manifest.insert(Artifact {
+            duration_ms: 1000,
+            ..artifact("a", params)
+        });
+        manifest.save(&path).unwrap();
+
+        let loaded = Manifest::load(&path).unwrap();
+        assert_eq!(loaded, manifest);
+        assert_eq!(loaded.artifacts.len(), 2);
+        assert_eq!(loaded.artifacts.iter().find(|a| a.movie == "a").unwrap().duration_ms, 1000);
+
+        tempdir.close().unwrap();
+    }
+
+    #[test]
+    fn import_spectrograms() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let layout = Layout::new(tempdir.path());
+        layout.create_dirs().unwrap();
+
+        let movies: Vec<_> = ["flat", "sharded", "missing", "known"].iter().map(|id| movie(id)).collect();
+        fs::write(tempdir.path().join("trailer-flat.jpg"), b"").unwrap();
+        fs::create_dir_all(tempdir.path().join("spectrograms/sh")).unwrap();
+        fs::write(layout.resolve(layout.spectrogram("sharded")), b"").unwrap();
+        fs::write(tempdir.path().join("trailer-known.jpg"), b"").unwrap();
+
+        let mut manifest = Manifest::default();
+        manifest.insert(artifact("known", SpectrogramParams::new(PreprocessOptions::default())));
+
+        assert_eq!(manifest.import_spectrograms(&layout, &movies), 2);
+        assert_eq!(manifest.artifacts.len(), 3);
+        assert_eq!(manifest.get(&movies[0]).unwrap().files, [Path::new("trailer-flat.jpg")]);
+        assert_eq!(manifest.get(&movies[1]).unwrap().files, [layout.spectrogram("sharded")]);
+        assert_eq!(manifest.get(&movies[1]).unwrap().params.renderer, "ffmpeg");
+        assert!(manifest.get(&movies[2]).is_none());
+
+        tempdir.close().unwrap();
+    }
+
+    #[test]
+    fn check_renderer() {
+        let params = SpectrogramParams::new(PreprocessOptions::default());
+        assert!(params.check_renderer().is_ok());
+
+        let other = if params.renderer == "native" { "ffmpeg" } else { "native" };
+        assert!(SpectrogramParams { renderer: other.to_owned(), ..params.clone() }.check_renderer().is_err());
+        assert!(SpectrogramParams { stop_hz: 8000, ..params }.check_renderer().is_err());
+    }
+
+    #[test]
+    fn common_params() {
+        let params = SpectrogramParams::new(PreprocessOptions::default());
+        let other = SpectrogramParams::new(PreprocessOptions {
+            loudness: Some(-23.0),
+            ..Default::default()
+        });
+
+        let same = [artifact("a", params.clone()), artifact("b", params.clone())];
+        assert_eq!(Manifest::common_params(&same).unwrap(), Some(&params));
+        assert_eq!(Manifest::common_params(&[]).unwrap(), None);
+
+        let mixed = [artifact("a", params), artifact("b", other)];
+        assert!(Manifest::common_params(&mixed).is_err());
+    }
+}
@@ -1,18 +1,63 @@
+//! Movies, their genres and trailers, as saved in `movies.json`.
+
 use crate::error::*;
+use crate::external::{MediaInfo, VideoInfo};
+use crate::store::StoredAudio;
+use crate::validation::{Candidate, Validation};
 
 use serde::{Serialize, Deserialize};
 
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct Movie {
+    pub movielens_id: Option<u32>,
+    /// Numeric part of the IMDb id (`tt0133093`).
+    pub imdb_id: Option<u32>,
     pub name: String,
+    /// Original or "a.k.a." titles from MovieLens, searched when the name is not found.
+    #[serde(default)]
+    pub alternate_titles: Vec<String>,
     pub youtube_id: String,
     pub year: Option<u32>,
     pub genres: Vec<Genre>,
     pub critics_number: Option<u32>,
     pub metacritic_score: Option<u8>,
+    pub trailer: Option<Trailer>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Trailer {
+    pub source: TrailerSource,
+    pub video: Option<VideoInfo>,
+    pub validation: Option<Validation>,
+    /// Ranked search results when the trailer was found by searching, otherwise the MovieLens video.
+    #[serde(default)]
+    pub candidates: Vec<Candidate>,
+    /// Hex-encoded audio fingerprint, used to detect the same audio across movies.
+    pub fingerprint: Option<String>,
+    pub media: Option<MediaInfo>,
+    pub audio: Option<StoredAudio>,
+}
+
+/// Where the trailer was found.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub enum TrailerSource {
+    /// The YouTube id from the MovieLens dataset.
+    #[default]
+    Id,
+    /// The search result ranked `rank`-th (starting from 1) by `validation::rank`.
+    Search { query: String, rank: usize },
 }
 
-#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Serialize, Deserialize)]
+impl std::fmt::Display for TrailerSource {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            TrailerSource::Id => write!(fmt, "id"),
+            TrailerSource::Search { query, rank } => write!(fmt, "search #{} '{}'", rank, query),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Serialize, Deserialize)]
 pub enum Genre {
     Fantasy,
     Comedy,
@@ -45,6 +90,38 @@ pub enum Genre {
 }
 
 impl Genre {
+    /// Every genre, in declaration order.
+    pub const ALL: [Genre; 28] = [
+        Genre::Fantasy,
+        Genre::Comedy,
+        Genre::Adventure,
+        Genre::Family,
+        Genre::Animation,
+        Genre::Drama,
+        Genre::Action,
+        Genre::SciFi,
+        Genre::Romance,
+        Genre::Short,
+        Genre::Thriller,
+        Genre::Mystery,
+        Genre::Horror,
+        Genre::Music,
+        Genre::Crime,
+        Genre::Musical,
+        Genre::GameShow,
+        Genre::History,
+        Genre::War,
+        Genre::Sport,
+        Genre::TalkShow,
+        Genre::Documentary,
+        Genre::RealityTV,
+        Genre::Biography,
+        Genre::Western,
+        Genre::News,
+        Genre::Adult,
+        Genre::FilmNoir,
+    ];
+
     pub fn from_text(text: &str) -> Result<Genre> {
         use Genre::*;
 
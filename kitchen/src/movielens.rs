@@ -1,6 +1,9 @@
+//! Loads movies and their YouTube trailer ids from the MovieLens 20M YouTube dataset.
+
 use crate::error::{Error, Result};
 use crate::movie::Movie;
 
+use std::collections::HashMap;
 use std::io::{Cursor, Read};
 
 use lazy_static::lazy_static;
@@ -18,6 +21,7 @@ lazy_static! {
         r"^(?:([^,]*)|(?:(.*),\s*(.*)))$").unwrap();
 }
 
+/// Downloads the dataset and reads its movies, checking the archive against its published MD5.
 pub fn download() -> Result<Vec<Movie>> {
     let original_md5 = http_get(ZIP_CHECKSUM_URL)?.text()?.replace("MD5 (ml-youtube.zip) = ", "");
     let original_md5 = original_md5.trim();
@@ -36,7 +40,8 @@ pub fn download() -> Result<Vec<Movie>> {
     from_reader(csv_file)
 }
 
-fn from_reader<T: Read>(reader: T) -> Result<Vec<Movie>> {
+/// Reads movies from `ml-youtube.csv`.
+pub fn from_reader<T: Read>(reader: T) -> Result<Vec<Movie>> {
     let mut csv_reader = csv::Reader::from_reader(reader);
 
     let mut movies = vec![];
@@ -45,11 +50,13 @@ fn from_reader<T: Read>(reader: T) -> Result<Vec<Movie>> {
         let record = result?;
         let raw_name = record.get(2).unwrap().trim();
 
-        let (name, year) = extract_name_and_year(raw_name);
+        let title = parse_title(raw_name);
 
         movies.push(Movie {
-            year: year,
-            name: name,
+            movielens_id: record.get(1).and_then(|id| id.parse().ok()),
+            year: title.year,
+            name: title.primary,
+            alternate_titles: title.alternates,
             youtube_id: record.get(0).unwrap().to_owned(),
             ..Default::default()
         });
@@ -58,79 +65,283 @@ fn from_reader<T: Read>(reader: T) -> Result<Vec<Movie>> {
     Ok(movies)
 }
 
-fn extract_name_and_year(raw_name: &str) -> (String, Option<u32>) {
+/// Replaces the names and alternate titles of `movies` with those of the same YouTube ids in
+/// `parsed`, for movies saved before titles were parsed this way. Returns how many were found.
+pub fn update_titles(movies: &mut [Movie], parsed: &[Movie]) -> usize {
+    let parsed: HashMap<_, _> = parsed.iter().map(|m| (&m.youtube_id, m)).collect();
+    let mut updated = 0;
+
+    for movie in movies {
+        if let Some(new) = parsed.get(&movie.youtube_id) {
+            movie.name = new.name.clone();
+            movie.alternate_titles = new.alternate_titles.clone();
+            movie.movielens_id = movie.movielens_id.or(new.movielens_id);
+            movie.year = movie.year.or(new.year);
+            updated += 1;
+        }
+    }
+
+    updated
+}
+
+/// A MovieLens title, such as `"City of Lost Children, The (Cité des enfants perdus, La) (1995)"`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ParsedTitle {
+    /// The title with its article moved back to the front: `"The City of Lost Children"`.
+    pub primary: String,
+    /// Original or "a.k.a." titles given in parentheses: `"La Cité des enfants perdus"`.
+    pub alternates: Vec<String>,
+    pub year: Option<u32>,
+    /// Whether the primary title was written as `"Title, Article"`.
+    pub moved_article: bool,
+}
+
+pub fn parse_title(raw_name: &str) -> ParsedTitle {
     let mut name = String::new();
-    let mut year_str = String::new();
-    let mut year = None;
-    let mut is_year = false;
-    let mut prev = '\x00';
+    let mut groups = vec![];
+    let mut group = String::new();
+    let mut depth = 0;
 
     for c in raw_name.chars() {
-        if c.is_whitespace() && prev.is_whitespace() {
-            continue;
+        match c {
+            '(' => {
+                if depth > 0 {
+                    group.push(c);
+                }
+                depth += 1;
+            },
+            ')' if depth > 0 => {
+                depth -= 1;
+                if depth == 0 {
+                    groups.push(std::mem::take(&mut group));
+                } else {
+                    group.push(c);
+                }
+            },
+            _ if depth > 0 => group.push(c),
+            _ => name.push(c),
         }
+    }
 
-        if is_year {
-            if c == ')' {
-                if let Ok(num) = year_str.parse::<u32>() {
-                    year = Some(num);
-                    break;
-                }
-                is_year = false;
-                year_str = String::new();
-            } else {
-                year_str.push(c);
+    // An unclosed parenthesis is part of the name.
+    if depth > 0 {
+        name.push('(');
+        name.push_str(&group);
+    }
+
+    let (primary, moved_article) = move_article(&collapse_whitespace(&name));
+    let mut parsed = ParsedTitle {
+        primary,
+        moved_article,
+        ..Default::default()
+    };
+
+    for group in groups {
+        let group = collapse_whitespace(&group);
+
+        if group.len() == 4 {
+            if let Ok(year) = group.parse() {
+                parsed.year = Some(year);
+                continue;
             }
-        } else if c == '(' {
-            is_year = true;
-        } else {
-            name.push(c);
         }
 
-        prev = c;
+        let (alternate, _) = move_article(strip_aka(&group));
+        if !alternate.is_empty() && alternate != parsed.primary && !parsed.alternates.contains(&alternate) {
+            parsed.alternates.push(alternate);
+        }
     }
 
-    let mut processed_name = name.trim().to_owned();
+    parsed
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
 
-    if let Some(pos) = name.rfind(',') {
-        let (mut name_part, mut article) = name.split_at(pos);
-        name_part = name_part.trim();
-        article = &article[1..]; // Remove ','
-        article = article.trim();
-        
-        match &*article.to_lowercase() {
-            "a" | "an" | "the" | "le" | "les" => processed_name = format!("{} {}", article, name_part),
+/// Removes the `a.k.a.` in front of an alternate title.
+fn strip_aka(title: &str) -> &str {
+    for prefix in &["a.k.a.", "aka "] {
+        if title.get(..prefix.len()).is_some_and(|p| p.eq_ignore_ascii_case(prefix)) {
+            return title[prefix.len()..].trim();
+        }
+    }
+    title
+}
+
+/// Articles that MovieLens moves to the end of titles, as in `"Vita è bella, La"`. Those ending
+/// with an apostrophe are elided and joined to the next word.
+const ARTICLES: &[&str] = &[
+    // English
+    "a", "an", "the",
+    // French
+    "le", "la", "les", "l'", "un", "une", "des",
+    // Spanish
+    "el", "los", "las", "una", "unos", "unas",
+    // Italian
+    "il", "lo", "i", "gli", "uno", "un'",
+    // German
+    "der", "die", "das", "ein", "eine",
+    // Portuguese
+    "o", "os", "as", "um", "uma",
+    // Dutch
+    "de", "het", "een", "'t",
+    // Danish, Norwegian and Swedish
+    "den", "det", "en", "ett", "ei",
+];
+
+/// Turns `"Title, The"` into `"The Title"` and `"Auberge espagnole, L'"` into `"L'Auberge espagnole"`.
+pub(crate) fn move_article(title: &str) -> (String, bool) {
+    if let Some(pos) = title.rfind(',') {
+        let (name_part, article) = title.split_at(pos);
+        let name_part = name_part.trim();
+        let article = article[1..].trim().replace('’', "'"); // Remove ','
+
+        if !name_part.is_empty() && ARTICLES.contains(&&*article.to_lowercase()) {
+            let separator = if article.ends_with('\'') { "" } else { " " };
+            return (format!("{}{}{}", article, separator, name_part), true);
+        }
+    }
+
+    (title.trim().to_owned(), false)
+}
+
+/// The title without a leading article among `articles`, or any article MovieLens moves with
+/// `None`: `"L'Auberge espagnole"` becomes `"Auberge espagnole"`.
+pub(crate) fn strip_article<'a>(title: &'a str, articles: Option<&[&str]>) -> &'a str {
+    for article in articles.unwrap_or(ARTICLES) {
+        let (word, elided) = match article.strip_suffix('\'') {
+            Some(word) => (word, true),
+            None => (*article, false),
+        };
+
+        let rest = match title.get(..word.len()) {
+            Some(prefix) if prefix.eq_ignore_ascii_case(word) => &title[word.len()..],
+            _ => continue,
+        };
+
+        match rest.chars().next() {
+            Some(c @ ('\'' | '’')) if elided => return rest[c.len_utf8()..].trim_start(),
+            Some(c) if !elided && c.is_whitespace() && !rest.trim().is_empty() => return rest.trim_start(),
             _ => {},
         }
     }
 
-    (processed_name, year)
+    title
 }
 
 #[cfg(test)]
 mod test {
+    use crate::movie::Movie;
+
+    /// Checks that a parsed title parses to itself again, so no article is left at the end.
+    fn assert_stable(primary: &str, alternates: &[String]) {
+        let again = super::parse_title(primary);
+        assert_eq!(again.primary, primary);
+        assert!(!again.moved_article, "{}", primary);
+        for alternate in alternates {
+            assert_eq!(&super::move_article(alternate).0, alternate);
+        }
+    }
+
     #[test]
     fn download() {
         let movies = super::download().unwrap();
+        for movie in &movies {
+            assert_stable(&movie.name, &movie.alternate_titles);
+        }
+
         let lotr = movies.into_iter()
             .find(|m| m.name == "The Lord of the Rings: The Fellowship of the Ring")
             .unwrap();
         assert_eq!(lotr.year, Some(2001));
     }
 
+    /// A sample of titles from `ml-youtube.csv` and how they parse. `download` checks that every
+    /// title is stable.
+    #[test]
+    fn fixture_titles() {
+        let fixture = include_str!("../fixtures/movielens-titles.csv");
+
+        for record in csv::Reader::from_reader(fixture.as_bytes()).records() {
+            let record = record.unwrap();
+            let parsed = super::parse_title(&record[0]);
+
+            assert_eq!(parsed.primary, &record[1], "{}", &record[0]);
+            let alternates: Vec<_> = record[2].split('|').filter(|a| !a.is_empty()).collect();
+            assert_eq!(parsed.alternates, alternates, "{}", &record[0]);
+            assert_eq!(parsed.year, record[3].parse().ok(), "{}", &record[0]);
+
+            assert_stable(&parsed.primary, &parsed.alternates);
+        }
+    }
+
+    #[test]
+    fn strip_article() {
+        let cases = [
+            ("The Hunt", "Hunt"),
+            ("L'Auberge espagnole", "Auberge espagnole"),
+            ("L’Eclisse", "Eclisse"),
+            ("Das Boot", "Boot"),
+            ("Theory of Everything", "Theory of Everything"),
+            ("The", "The"),
+            ("Amelie", "Amelie"),
+        ];
+
+        for (title, stripped) in cases {
+            assert_eq!(super::strip_article(title, None), stripped, "{}", title);
+        }
+
+        assert_eq!(super::strip_article("Das Boot", Some(&["the"])), "Das Boot");
+        assert_eq!(super::strip_article("The Hunt", Some(&["the"])), "Hunt");
+    }
+
     #[test]
-    fn extract_name_and_year() {
-        assert_eq!(
-            super::extract_name_and_year("Kenji Mizoguchi: The Life of a Film Director (Aru eiga-kantoku no shogai) (1975)"),
-            ("Kenji Mizoguchi: The Life of a Film Director".to_owned(), Some(1975))
-        );
-        assert_eq!(
-            super::extract_name_and_year("Misérables, Les (1995)"),
-            ("Les Misérables".to_owned(), Some(1995))
-        );
-        assert_eq!(
-            super::extract_name_and_year("Honey, I Shrunk the Kids (1989)"),
-            ("Honey, I Shrunk the Kids".to_owned(), Some(1989))
-        );
+    fn update_titles() {
+        let csv = "youtubeId,movieId,title\nabc,1,\"Vita è bella, La (1997)\"\n";
+        let parsed = super::from_reader(csv.as_bytes()).unwrap();
+        let mut movies = vec![
+            Movie { youtube_id: "abc".to_owned(), name: "Vita è bella, La".to_owned(), ..Default::default() },
+            Movie { youtube_id: "xyz".to_owned(), name: "Unknown".to_owned(), ..Default::default() },
+        ];
+
+        assert_eq!(super::update_titles(&mut movies, &parsed), 1);
+        assert_eq!(movies[0].name, "La Vita è bella");
+        assert_eq!((movies[0].movielens_id, movies[0].year), (Some(1), Some(1997)));
+        assert_eq!(movies[1].name, "Unknown");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn parse_title() {
+        use super::ParsedTitle;
+
+        let cases = [
+            ("Kenji Mizoguchi: The Life of a Film Director (Aru eiga-kantoku no shogai) (1975)",
+                "Kenji Mizoguchi: The Life of a Film Director", &["Aru eiga-kantoku no shogai"][..], Some(1975), false),
+            ("Misérables, Les (1995)", "Les Misérables", &[], Some(1995), true),
+            ("Honey, I Shrunk the Kids (1989)", "Honey, I Shrunk the Kids", &[], Some(1989), false),
+            ("City of Lost Children, The (Cité des enfants perdus, La)   (1995)",
+                "The City of Lost Children", &["La Cité des enfants perdus"], Some(1995), true),
+            ("Seven (a.k.a. Se7en) (1995)", "Seven", &["Se7en"], Some(1995), false),
+            ("Babies (Bébé(s)) (2010)", "Babies", &["Bébé(s)"], Some(2010), false),
+            ("Big Bang Theory, The", "The Big Bang Theory", &[], None, true),
+            ("Auberge espagnole, L' (2002)", "L'Auberge espagnole", &[], Some(2002), true),
+            ("Eclisse, L’ (1962)", "L'Eclisse", &[], Some(1962), true),
+            ("Life Is Beautiful (Vita è bella, La) (1997)", "Life Is Beautiful", &["La Vita è bella"][..], Some(1997), false),
+            ("Downfall (Untergang, Der) (2004)", "Downfall", &["Der Untergang"], Some(2004), false),
+            ("Prophet, A (Prophète, Un) (2009)", "A Prophet", &["Un Prophète"], Some(2009), true),
+            ("Hunt, The (Jagten) (2012)", "The Hunt", &["Jagten"], Some(2012), true),
+            ("Crouching Tiger, Hidden Dragon (Wo hu cang long) (2000)",
+                "Crouching Tiger, Hidden Dragon", &["Wo hu cang long"], Some(2000), false),
+        ];
+
+        for (raw, primary, alternates, year, moved_article) in cases {
+            assert_eq!(super::parse_title(raw), ParsedTitle {
+                primary: primary.to_owned(),
+                alternates: alternates.iter().map(|a| a.to_string()).collect(),
+                year,
+                moved_article,
+            }, "{}", raw);
+        }
+    }
+}
@@ -0,0 +1,609 @@
+//! The stages of the pipeline, what each reads and writes, and the outcome of every stage for
+//! every movie. Outcomes are saved in `state.json`, so a stage only works on what is left.
+//!
+//! Each stage is planned before it runs, from the movies, the manifest and the state alone, so a
+//! plan can also be shown without touching the network or the disk.
+
+use crate::config::SelectionConfig;
+use crate::dataset::{self, Dataset};
+use crate::error::*;
+use crate::labels::LabelMapping;
+use crate::layout::Layout;
+use crate::manifest::Manifest;
+use crate::movie::Movie;
+use crate::trailer;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::path::Path;
+
+use serde::{Serialize, Deserialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    /// Fills genres, critics and metascores from IMDb.
+    Enrich,
+    /// Downloads trailers and renders their spectrograms.
+    Download,
+    /// Selects and labels the dataset.
+    Select,
+}
+
+/// What a stage needs and makes, by path relative to the root of the `Layout`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StageInfo {
+    /// Checked by `Stage::missing_input` before the stage runs.
+    pub inputs: &'static [&'static str],
+    /// Count as inputs of the later stages of the same run, even if they aren't saved.
+    pub outputs: &'static [&'static str],
+    /// Stages whose outputs are inputs of this one.
+    pub depends_on: &'static [Stage],
+}
+
+impl Stage {
+    /// Every stage, in the order they run.
+    pub const ALL: [Stage; 3] = [Stage::Enrich, Stage::Download, Stage::Select];
+
+    /// Parses a stage name, or its number for compatibility with `--stage`.
+    pub fn from_text(text: &str) -> Result<Stage> {
+        match text {
+            "enrich" | "0" => Ok(Stage::Enrich),
+            "download" | "1" => Ok(Stage::Download),
+            "select" | "2" => Ok(Stage::Select),
+            _ => Err(Error::Generic(format!("unknown stage '{}'", text))),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Stage::Enrich => "enrich",
+            Stage::Download => "download",
+            Stage::Select => "select",
+        }
+    }
+
+    pub fn info(self) -> StageInfo {
+        match self {
+            Stage::Enrich => StageInfo {
+                inputs: &["metadata/movies.json"],
+                outputs: &["metadata/movies.json"],
+                depends_on: &[],
+            },
+            Stage::Download => StageInfo {
+                inputs: &["metadata/movies.json"],
+                outputs: &[
+                    "metadata/movies.json", "metadata/manifest.json", "audio", "spectrograms",
+                    "logs/rejected.csv",
+                ],
+                depends_on: &[Stage::Enrich],
+            },
+            Stage::Select => StageInfo {
+                inputs: &["metadata/movies.json", "metadata/manifest.json"],
+                outputs: &["exports/movies_selected.csv", "exports/labels.txt"],
+                depends_on: &[Stage::Download],
+            },
+        }
+    }
+
+    /// The first input that is neither in the working directory nor among the `produced` outputs
+    /// of the stages run before.
+    pub fn missing_input(self, layout: &Layout, produced: &[&str]) -> Option<&'static str> {
+        self.info().inputs.iter()
+            .find(|input| !produced.contains(input) && !layout.resolve(input).exists())
+            .copied()
+    }
+
+    /// Stages using the outputs of this one, directly or through other stages, in order.
+    pub fn dependents(self) -> Vec<Stage> {
+        let mut dependents = vec![];
+        for stage in Stage::ALL {
+            let info = stage.info();
+            if info.depends_on.iter().any(|s| *s == self || dependents.contains(s)) {
+                dependents.push(stage);
+            }
+        }
+        dependents
+    }
+}
+
+impl std::fmt::Display for Stage {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        write!(fmt, "{}", self.name())
+    }
+}
+
+/// Outcome of a stage for one movie.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "status", content = "reason", rename_all = "snake_case")]
+pub enum Status {
+    /// Invalidated; processed again on the next run even if its outputs exist.
+    Pending,
+    Done,
+    /// Tried again on the next run.
+    Failed(String),
+    /// Left out by the stage, until it is invalidated.
+    Skipped(String),
+}
+
+impl Status {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Status::Pending => "pending",
+            Status::Done => "done",
+            Status::Failed(_) => "failed",
+            Status::Skipped(_) => "skipped",
+        }
+    }
+}
+
+/// The status of every movie in every stage, by YouTube id.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct State {
+    stages: BTreeMap<Stage, BTreeMap<String, Status>>,
+}
+
+impl State {
+    /// Loads the state, or an empty one when no stage has run yet.
+    pub fn load<T: AsRef<Path>>(path: T) -> Result<State> {
+        if !path.as_ref().exists() {
+            return Ok(State::default());
+        }
+
+        Ok(serde_json::from_reader(File::open(path)?)?)
+    }
+
+    pub fn save<T: AsRef<Path>>(&self, path: T) -> Result<()> {
+        Ok(serde_json::to_writer_pretty(File::create(path)?, self)?)
+    }
+
+    /// The state of a working directory from before `state.json`: movies with genres were enriched
+    /// and those in the manifest were downloaded.
+    pub fn seed(movies: &[Movie], manifest: &Manifest) -> State {
+        let mut state = State::default();
+
+        for movie in movies {
+            if !movie.genres.is_empty() {
+                state.set(Stage::Enrich, &movie.youtube_id, Status::Done);
+            }
+            if manifest.get(movie).is_some() {
+                state.set(Stage::Download, &movie.youtube_id, Status::Done);
+            }
+        }
+
+        state
+    }
+
+    pub fn get(&self, stage: Stage, id: &str) -> Option<&Status> {
+        self.stages.get(&stage)?.get(id)
+    }
+
+    pub fn set(&mut self, stage: Stage, id: &str, status: Status) {
+        self.stages.entry(stage).or_default().insert(id.to_owned(), status);
+    }
+
+    /// Whether the stage has recorded anything.
+    pub fn has_run(&self, stage: Stage) -> bool {
+        self.stages.get(&stage).is_some_and(|s| !s.is_empty())
+    }
+
+    /// Whether the stage still has to process the movie: it is neither done nor skipped.
+    pub fn needs(&self, stage: Stage, id: &str) -> bool {
+        !matches!(self.get(stage, id), Some(Status::Done) | Some(Status::Skipped(_)))
+    }
+
+    /// Whether the movie was invalidated and must be processed again.
+    pub fn is_pending(&self, stage: Stage, id: &str) -> bool {
+        self.get(stage, id) == Some(&Status::Pending)
+    }
+
+    /// Marks every movie of the stage as pending, and with `cascade` of its dependents too. Returns
+    /// those stages. Cascading from enrich downloads every trailer again.
+    pub fn invalidate(&mut self, stage: Stage, cascade: bool) -> Vec<Stage> {
+        let mut stages = vec![stage];
+        if cascade {
+            stages.extend(stage.dependents());
+        }
+
+        for stage in &stages {
+            if let Some(statuses) = self.stages.get_mut(stage) {
+                for status in statuses.values_mut() {
+                    *status = Status::Pending;
+                }
+            }
+        }
+
+        stages
+    }
+
+    /// Number of movies in each status, and in each reason of failing or being skipped.
+    pub fn counts(&self, stage: Stage) -> (BTreeMap<&'static str, usize>, BTreeMap<String, usize>) {
+        let mut statuses = BTreeMap::new();
+        let mut reasons = BTreeMap::new();
+
+        for status in self.stages.get(&stage).into_iter().flat_map(|s| s.values()) {
+            *statuses.entry(status.name()).or_default() += 1;
+            if let Status::Failed(reason) | Status::Skipped(reason) = status {
+                *reasons.entry(reason.clone()).or_default() += 1;
+            }
+        }
+
+        (statuses, reasons)
+    }
+}
+
+/// What a stage does with a movie.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// The movie already has its IMDb info and is only marked as done.
+    Check,
+    /// Looks the movie up on IMDb.
+    Fetch,
+    /// Downloads, validates and processes the trailer.
+    Download,
+    /// Considers the movie for the dataset.
+    Select,
+}
+
+impl Action {
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Check => "check",
+            Action::Fetch => "fetch",
+            Action::Download => "download",
+            Action::Select => "select",
+        }
+    }
+}
+
+/// What a stage would do, worked out without running it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Plan {
+    pub stage: Stage,
+    /// Movies to process by their index, in the order they are processed.
+    pub tasks: Vec<(usize, Action)>,
+    /// Statuses known without processing, such as movies whose trailer already exists.
+    pub settled: Vec<(usize, Status)>,
+    /// Movies left as they are because the stage is done with them or skipped them.
+    pub untouched: usize,
+    /// Least and most requests to IMDb or YouTube the tasks should make.
+    pub requests: (usize, usize),
+}
+
+impl Plan {
+    fn new(stage: Stage) -> Plan {
+        Plan {
+            stage,
+            tasks: vec![],
+            settled: vec![],
+            untouched: 0,
+            requests: (0, 0),
+        }
+    }
+
+    pub fn stage(stage: Stage, movies: &[Movie], manifest: &Manifest, state: &State, config: &SelectionConfig) -> Plan {
+        match stage {
+            Stage::Enrich => Plan::enrich(movies, state, config),
+            Stage::Download => Plan::download(movies, manifest, state, config),
+            Stage::Select => Plan::select(movies, manifest, config),
+        }
+    }
+
+    /// Plans `stages` in order, each assuming the stages before it do all of their tasks. IMDb info
+    /// is unknown until fetched, so every movie still to be looked up counts as a download, and
+    /// every download as a selected movie: later plans are upper bounds.
+    pub fn chain(
+        stages: &[Stage],
+        movies: &[Movie],
+        manifest: &Manifest,
+        state: &State,
+        config: &SelectionConfig,
+    ) -> Vec<Plan> {
+        let mut state = state.clone();
+        let mut plans: Vec<Plan> = vec![];
+
+        for stage in stages {
+            let mut plan = Plan::stage(*stage, movies, manifest, &state, config);
+            let before = plans.last().filter(|p| Some(p.stage) == stage.info().depends_on.first().copied());
+
+            match (stage, before) {
+                (Stage::Download, Some(enrich)) => {
+                    let fetched: BTreeSet<_> = enrich.tasks.iter()
+                        .filter(|(_, a)| *a == Action::Fetch)
+                        .map(|(i, _)| *i)
+                        .collect();
+                    let (skipped, settled) = plan.settled.into_iter()
+                        .partition(|(i, s)| fetched.contains(i) && matches!(s, Status::Skipped(_)));
+                    plan.settled = settled;
+
+                    for (i, _) in skipped {
+                        plan.requests.0 += 1;
+                        plan.requests.1 += 2 + trailer::MAX_SEARCH_RESULTS;
+                        plan.tasks.push((i, Action::Download));
+                    }
+                    plan.tasks.sort_by_key(|(i, _)| std::cmp::Reverse(movies[*i].critics_number));
+                },
+                (Stage::Select, Some(download)) => {
+                    for (i, _) in &download.tasks {
+                        if !plan.tasks.iter().any(|(j, _)| j == i) {
+                            plan.tasks.push((*i, Action::Select));
+                        }
+                    }
+                },
+                _ => {},
+            }
+
+            // What the next stage sees once this one has run.
+            for (i, _) in &plan.tasks {
+                state.set(*stage, &movies[*i].youtube_id, Status::Done);
+            }
+            for (i, status) in &plan.settled {
+                state.set(*stage, &movies[*i].youtube_id, status.clone());
+            }
+
+            plans.push(plan);
+        }
+
+        plans
+    }
+
+    /// Movies from `min_year` on that are not done, and those done before their IMDb id was
+    /// recorded. Those with every field filled are only looked up again when invalidated.
+    pub fn enrich(movies: &[Movie], state: &State, config: &SelectionConfig) -> Plan {
+        let mut plan = Plan::new(Stage::Enrich);
+
+        for (i, movie) in movies.iter().enumerate().filter(|(_, m)| m.year >= Some(config.min_year)) {
+            let id = &movie.youtube_id;
+            let backfill = movie.imdb_id.is_none() && state.get(Stage::Enrich, id) == Some(&Status::Done);
+            if !state.needs(Stage::Enrich, id) && !backfill {
+                plan.untouched += 1;
+                continue;
+            }
+
+            let complete = movie.imdb_id.is_some()
+                && !movie.genres.is_empty()
+                && movie.critics_number.is_some()
+                && movie.metacritic_score.is_some();
+            if complete && !state.is_pending(Stage::Enrich, id) {
+                plan.tasks.push((i, Action::Check));
+            } else {
+                // A search for each title until one is found, then the title page.
+                plan.requests.0 += 2;
+                plan.requests.1 += 2 + movie.alternate_titles.len();
+                plan.tasks.push((i, Action::Fetch));
+            }
+        }
+
+        plan
+    }
+
+    /// Enriched candidates without a trailer, or invalidated, by most critics first. Enriched
+    /// movies that are not candidates are skipped.
+    pub fn download(movies: &[Movie], manifest: &Manifest, state: &State, config: &SelectionConfig) -> Plan {
+        let mut plan = Plan::new(Stage::Download);
+        let candidates = dataset::candidates(movies, config);
+
+        for (i, movie) in movies.iter().enumerate() {
+            let id = &movie.youtube_id;
+            if state.get(Stage::Enrich, id) != Some(&Status::Done) {
+                continue;
+            }
+            if !state.needs(Stage::Download, id) {
+                plan.untouched += 1;
+                continue;
+            }
+
+            if manifest.get(movie).is_some() && !state.is_pending(Stage::Download, id) {
+                plan.settled.push((i, Status::Done));
+            } else if movie.critics_number.is_none() {
+                plan.settled.push((i, Status::Skipped("no critics number".to_owned())));
+            } else if !candidates.iter().any(|(c, _)| *c == i) {
+                plan.settled.push((i, Status::Skipped(format!("fewer than {} critics", config.min_critics))));
+            } else {
+                // The MovieLens video, or else a search and each of its results.
+                plan.requests.0 += 1;
+                plan.requests.1 += 2 + trailer::MAX_SEARCH_RESULTS;
+                plan.tasks.push((i, Action::Download));
+            }
+        }
+
+        plan.tasks.sort_by_key(|(i, _)| std::cmp::Reverse(movies[*i].critics_number));
+        plan
+    }
+
+    /// Candidates with a trailer. Selection is cheap, so it always starts over.
+    pub fn select(movies: &[Movie], manifest: &Manifest, config: &SelectionConfig) -> Plan {
+        let mut plan = Plan::new(Stage::Select);
+
+        plan.tasks = dataset::candidates(movies, config).into_iter()
+            .filter(|(_, m)| manifest.get(m).is_some())
+            .map(|(i, _)| (i, Action::Select))
+            .collect();
+
+        plan
+    }
+
+    /// The outcome of the select stage for each of its tasks.
+    pub fn select_statuses(&self, movies: &[Movie], dataset: &Dataset) -> Vec<(usize, Status)> {
+        let selected: Vec<_> = dataset.samples.iter().map(|s| &s.movie.youtube_id).collect();
+
+        self.tasks.iter()
+            .map(|(i, _)| {
+                let movie = &movies[*i];
+                let status = if selected.contains(&&movie.youtube_id) {
+                    Status::Done
+                } else if has_label(&dataset.mapping, &dataset.labels, movie) {
+                    Status::Skipped("dropped by balancing".to_owned())
+                } else {
+                    Status::Skipped("no selected label".to_owned())
+                };
+                (*i, status)
+            })
+            .collect()
+    }
+
+    /// Number of tasks doing each action.
+    pub fn counts(&self) -> BTreeMap<&'static str, usize> {
+        let mut counts = BTreeMap::new();
+        for (_, action) in &self.tasks {
+            *counts.entry(action.name()).or_default() += 1;
+        }
+        counts
+    }
+}
+
+fn has_label(mapping: &LabelMapping, labels: &[String], movie: &Movie) -> bool {
+    mapping.map(&movie.genres).iter().any(|l| labels.contains(l))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Action, Plan, Stage, State, Status};
+    use crate::config::SelectionConfig;
+    use crate::layout::Layout;
+    use crate::manifest::tests::{manifest, movie};
+    use crate::manifest::Manifest;
+    use crate::movie::{Genre, Movie};
+
+    use std::fs;
+
+    #[test]
+    fn dependents() {
+        assert_eq!(Stage::Enrich.dependents(), [Stage::Download, Stage::Select]);
+        assert_eq!(Stage::Download.dependents(), [Stage::Select]);
+        assert!(Stage::Select.dependents().is_empty());
+        assert_eq!(Stage::from_text("1").unwrap(), Stage::Download);
+    }
+
+    #[test]
+    fn state() {
+        let mut state = State::default();
+        state.set(Stage::Enrich, "a", Status::Done);
+        state.set(Stage::Enrich, "b", Status::Failed("no result".to_owned()));
+        state.set(Stage::Download, "a", Status::Skipped("too few critics".to_owned()));
+
+        assert!(!state.needs(Stage::Enrich, "a"));
+        assert!(state.needs(Stage::Enrich, "b"));
+        assert!(state.needs(Stage::Enrich, "c"));
+        assert!(!state.needs(Stage::Download, "a"));
+        assert!(!state.has_run(Stage::Select));
+
+        let json = serde_json::to_string(&state).unwrap();
+        assert!(json.contains(r#""b":{"status":"failed","reason":"no result"}"#));
+        assert_eq!(serde_json::from_str::<State>(&json).unwrap(), state);
+
+        let (statuses, reasons) = state.counts(Stage::Enrich);
+        assert_eq!(statuses["done"], 1);
+        assert_eq!(reasons["no result"], 1);
+
+        assert_eq!(state.invalidate(Stage::Enrich, false), [Stage::Enrich]);
+        assert!(state.is_pending(Stage::Enrich, "a"));
+        assert!(!state.is_pending(Stage::Download, "a"));
+        assert_eq!(state.invalidate(Stage::Download, true), [Stage::Download, Stage::Select]);
+        assert!(state.is_pending(Stage::Download, "a"));
+    }
+
+    #[test]
+    fn missing_input() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let layout = Layout::new(tempdir.path());
+        layout.create_dirs().unwrap();
+        fs::write(layout.movies_json(), "[]").unwrap();
+
+        assert_eq!(Stage::Download.missing_input(&layout, &[]), None);
+        assert_eq!(Stage::Select.missing_input(&layout, &[]), Some("metadata/manifest.json"));
+        assert_eq!(Stage::Select.missing_input(&layout, Stage::Download.info().outputs), None);
+
+        tempdir.close().unwrap();
+    }
+
+    #[test]
+    fn seed() {
+        let movies: Vec<_> = ["a", "b", "c"].iter()
+            .map(|id| Movie {
+                genres: if *id == "c" { vec![] } else { vec![Genre::Drama] },
+                ..movie(id)
+            })
+            .collect();
+        let manifest = manifest(&movies[..1]);
+
+        let state = State::seed(&movies, &manifest);
+        assert_eq!(state.get(Stage::Enrich, "b"), Some(&Status::Done));
+        assert_eq!(state.get(Stage::Enrich, "c"), None);
+        assert_eq!(state.get(Stage::Download, "a"), Some(&Status::Done));
+        assert_eq!(state.get(Stage::Download, "b"), None);
+        assert!(!state.has_run(Stage::Select));
+    }
+
+    #[test]
+    fn plan_enrich() {
+        let movies: Vec<_> = [("a", Some(1)), ("b", None), ("c", Some(3)), ("d", Some(4))].iter()
+            .map(|(id, imdb_id)| Movie {
+                imdb_id: *imdb_id,
+                critics_number: Some(100),
+                metacritic_score: Some(50),
+                ..movie(id)
+            })
+            .collect();
+
+        let mut state = State::default();
+        for id in ["a", "b"] {
+            state.set(Stage::Enrich, id, Status::Done);
+        }
+        state.set(Stage::Enrich, "d", Status::Pending);
+
+        // "b" was enriched before IMDb ids were recorded.
+        let plan = Plan::enrich(&movies, &state, &SelectionConfig::default());
+        assert_eq!(plan.tasks, [(1, Action::Fetch), (2, Action::Check), (3, Action::Fetch)]);
+        assert_eq!(plan.untouched, 1);
+    }
+
+    #[test]
+    fn plan_chain() {
+        let movies: Vec<_> = ["a", "b", "c"].iter()
+            .map(|id| Movie {
+                imdb_id: if *id == "a" { Some(1) } else { None },
+                genres: vec![],
+                critics_number: if *id == "a" { Some(500) } else { None },
+                ..movie(id)
+            })
+            .collect();
+        let mut state = State::default();
+        state.set(Stage::Enrich, "a", Status::Done);
+        let (manifest, config) = (Manifest::default(), SelectionConfig::default());
+
+        // On its own, the download plan only sees the movie enriched so far.
+        let plan = Plan::download(&movies, &manifest, &state, &config);
+        assert_eq!(plan.tasks, [(0, Action::Download)]);
+
+        let plans = Plan::chain(&Stage::ALL, &movies, &manifest, &state, &config);
+        assert_eq!(plans[0].tasks, [(1, Action::Fetch), (2, Action::Fetch)]);
+        assert_eq!(plans[1].tasks, [(0, Action::Download), (1, Action::Download), (2, Action::Download)]);
+        assert!(plans[1].settled.is_empty());
+        assert_eq!(plans[2].tasks.len(), 3);
+    }
+
+    #[test]
+    fn plan_tasks() {
+        let movies: Vec<_> = [("a", 500), ("b", 50), ("c", 300), ("d", 500), ("e", 800)].iter()
+            .map(|(id, critics)| Movie {
+                critics_number: Some(*critics),
+                ..movie(id)
+            })
+            .collect();
+
+        let manifest = manifest(vec![&movies[0], &movies[4]]);
+        let mut state = State::default();
+        for movie in &movies {
+            if movie.youtube_id != "d" {
+                state.set(Stage::Enrich, &movie.youtube_id, Status::Done);
+            }
+        }
+        state.set(Stage::Download, "e", Status::Pending);
+
+        let plan = Plan::download(&movies, &manifest, &state, &SelectionConfig::default());
+        assert_eq!(plan.tasks, [(4, Action::Download), (2, Action::Download)]);
+        assert_eq!(plan.settled, [(0, Status::Done), (1, Status::Skipped("fewer than 100 critics".to_owned()))]);
+        assert_eq!(plan.requests, (2, 24));
+    }
+}
@@ -0,0 +1,451 @@
+//! Predicts the genres of one trailer with an exported ONNX model, like the last cells of the
+//! notebook.
+
+use crate::config::CsvHeaders;
+use crate::error::*;
+use crate::external::{self, PreprocessOptions};
+use crate::manifest::{Manifest, SpectrogramParams};
+use crate::trailer;
+
+use std::fs::{self, File};
+use std::path::Path;
+
+use image::imageops::{self, FilterType};
+use image::RgbImage;
+use tract_onnx::prelude::*;
+use tract_onnx::tract_hir::infer::Factoid;
+
+/// Width and height of the windows the model sees, as in the notebook.
+pub const WINDOW_SIZE: u32 = 224;
+
+/// How the predictions of the windows of a spectrogram are combined.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Aggregation {
+    Mean,
+    Max,
+}
+
+impl Aggregation {
+    pub fn from_text(text: &str) -> Result<Aggregation> {
+        match text {
+            "mean" => Ok(Aggregation::Mean),
+            "max" => Ok(Aggregation::Max),
+            _ => Err(format!("unknown aggregation '{}', expected mean or max", text))?,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aggregation::Mean => "mean",
+            Aggregation::Max => "max",
+        }
+    }
+}
+
+/// Left edges of the windows covering an image `width` pixels wide. Like the notebook, the windows
+/// are spread evenly so that the first starts at 0 and the last ends at the right edge.
+pub fn windows(width: u32, size: u32) -> Vec<u32> {
+    if width <= size {
+        return vec![0];
+    }
+
+    let count = width.div_ceil(size);
+    let step = (width - size) / (count - 1);
+
+    (0..count).map(|i| i * step).collect()
+}
+
+/// Rejects spectrograms wider, relative to their height, than that of the longest trailer the
+/// dataset accepts, so one request can't make the model run on thousands of windows.
+pub fn check_image_size(width: u32, height: u32) -> Result<()> {
+    let params = SpectrogramParams::new(PreprocessOptions::default());
+    let max_width = trailer::MAX_AUDIO_LENGTH / params.ms_per_column;
+
+    if height == 0 || width as u64 * params.height as u64 > max_width * height as u64 {
+        return Err(Error::Model(format!(
+            "a {}x{} spectrogram is wider than that of a {}-minute trailer",
+            width, height, trailer::MAX_AUDIO_LENGTH / 60_000,
+        )));
+    }
+
+    Ok(())
+}
+
+/// Combines one row of probabilities per window into one per label.
+pub fn aggregate(predictions: &[Vec<f32>], aggregation: Aggregation) -> Vec<f32> {
+    let len = predictions.first().map(|p| p.len()).unwrap_or(0);
+
+    (0..len)
+        .map(|i| {
+            let values = predictions.iter().map(|p| p[i]);
+            match aggregation {
+                Aggregation::Mean => values.sum::<f32>() / predictions.len() as f32,
+                Aggregation::Max => values.fold(f32::MIN, f32::max),
+            }
+        })
+        .collect()
+}
+
+/// The label set in class order: the multi-hot columns of `movies_selected.csv`, or the lines of
+/// `labels.txt`.
+pub fn read_labels<T: AsRef<Path>>(path: T, headers: &CsvHeaders) -> Result<Vec<String>> {
+    let path = path.as_ref();
+
+    let labels: Vec<String> = if path.extension().is_some_and(|e| e == "txt") {
+        fs::read_to_string(path)?.lines().filter(|l| !l.is_empty()).map(|l| l.to_owned()).collect()
+    } else {
+        let known = [
+            &headers.id, &headers.name, &headers.year, &headers.critics_number, &headers.metacritic_score,
+            &headers.imdb_id, &headers.duration_ms, &headers.labels, &headers.primary, &headers.weight,
+        ];
+
+        csv::Reader::from_path(path)?
+            .headers()?
+            .iter()
+            .filter(|h| !known.iter().any(|k| k == h))
+            .map(|h| h.to_owned())
+            .collect()
+    };
+
+    if labels.is_empty() {
+        Err(format!(
+            "no labels in {}; export with multi-hot encoding or pass labels.txt with --labels",
+            path.display(),
+        ))?;
+    }
+
+    Ok(labels)
+}
+
+/// The spectrogram parameters a dataset was made with, so predictions see spectrograms made the
+/// same way: those of an export directory (an unpacked archive or sharded export) when given,
+/// otherwise those shared by the artifacts of the manifest.
+pub fn dataset_params(export: Option<&Path>, manifest: &Path) -> Result<Option<SpectrogramParams>> {
+    if let Some(dir) = export {
+        if dir.join("params.json").exists() {
+            return Ok(serde_json::from_reader(File::open(dir.join("params.json"))?)?);
+        }
+        if dir.join("dataset.json").exists() {
+            let info: serde_json::Value = serde_json::from_reader(File::open(dir.join("dataset.json"))?)?;
+            return Ok(serde_json::from_value(info["params"].clone())?);
+        }
+        Err(format!("no params.json or dataset.json in {}", dir.display()))?;
+    }
+
+    let manifest = Manifest::load(manifest)?;
+    Ok(Manifest::common_params(&manifest.artifacts)?.cloned())
+}
+
+/// An ONNX model taking 224×224 RGB windows with values from 0 to 255, channels last or first,
+/// and giving one probability per label.
+pub struct Model {
+    plan: TypedRunnableModel<TypedModel>,
+    channels_first: bool,
+    pub labels: Vec<String>,
+}
+
+impl Model {
+    pub fn load<T: AsRef<Path>>(path: T, labels: Vec<String>) -> Result<Model> {
+        let mut model = tract_onnx::onnx().model_for_path(path)?;
+
+        let channels_first = model.input_fact(0)?.shape.dim(1).and_then(|d| d.concretize()) == Some(3.into());
+        let size = WINDOW_SIZE as usize;
+        let shape = if channels_first { [1, 3, size, size] } else { [1, size, size, 3] };
+        model.set_input_fact(0, f32::fact(shape).into())?;
+
+        let plan = model.into_optimized()?.into_runnable()?;
+
+        let outputs = plan.model().output_fact(0)?.shape.as_concrete().map(|s| s.iter().product::<usize>());
+        if outputs != Some(labels.len()) {
+            return Err(Error::Model(format!(
+                "the model gives {:?} outputs but there are {} labels",
+                outputs, labels.len(),
+            )));
+        }
+
+        Ok(Model { plan, channels_first, labels })
+    }
+
+    /// Probabilities of each label for a spectrogram image, aggregated over its windows.
+    pub fn predict_image(&self, image: &RgbImage, aggregation: Aggregation) -> Result<Vec<f32>> {
+        check_image_size(image.width(), image.height())?;
+
+        let image = if image.height() != WINDOW_SIZE {
+            let width = (image.width() as u64 * WINDOW_SIZE as u64 / image.height() as u64).max(1) as u32;
+            imageops::resize(image, width, WINDOW_SIZE, FilterType::Triangle)
+        } else {
+            image.clone()
+        };
+
+        let mut predictions = vec![];
+        for left in windows(image.width(), WINDOW_SIZE) {
+            predictions.push(self.predict_window(&image, left)?);
+        }
+
+        Ok(aggregate(&predictions, aggregation))
+    }
+
+    /// Runs the model on the window starting at `left`, padding past the right edge with black.
+    fn predict_window(&self, image: &RgbImage, left: u32) -> Result<Vec<f32>> {
+        let size = WINDOW_SIZE as usize;
+        let pixel = |y: usize, x: usize, c: usize| {
+            let x = left + x as u32;
+            if x < image.width() { image.get_pixel(x, y as u32)[c] as f32 } else { 0.0 }
+        };
+
+        let input: Tensor = if self.channels_first {
+            tract_ndarray::Array4::from_shape_fn((1, 3, size, size), |(_, c, y, x)| pixel(y, x, c)).into()
+        } else {
+            tract_ndarray::Array4::from_shape_fn((1, size, size, 3), |(_, y, x, c)| pixel(y, x, c)).into()
+        };
+
+        let outputs = self.plan.run(tvec!(input.into()))?;
+        Ok(outputs[0].to_array_view::<f32>()?.iter().copied().collect())
+    }
+
+    /// Makes the spectrogram of an audio file the same way as the dataset, then predicts it. Audio
+    /// longer than the dataset accepts, or spectrograms this build can't render like the dataset,
+    /// are rejected before rendering.
+    pub fn predict_audio(&self, path: &Path, params: &SpectrogramParams, aggregation: Aggregation) -> Result<Vec<f32>> {
+        params.check_renderer()?;
+        let media = external::probe(path)?;
+        if media.duration_ms > trailer::MAX_AUDIO_LENGTH {
+            return Err(Error::Model(format!(
+                "the audio is longer than {} minutes",
+                trailer::MAX_AUDIO_LENGTH / 60_000,
+            )));
+        }
+
+        let tempdir = tempfile::tempdir()?;
+        let output = tempdir.path().join("spectrogram.jpg");
+        trailer::process_audio(path, &output, params)?;
+
+        let image = image::open(&output).map_err(|e| Error::Model(e.to_string()))?.to_rgb8();
+        self.predict_image(&image, aggregation)
+    }
+
+    /// Predicts `input`: a local audio file if it exists, otherwise a YouTube id to download.
+    pub fn predict(&self, input: &str, params: &SpectrogramParams, aggregation: Aggregation) -> Result<Vec<f32>> {
+        if Path::new(input).exists() {
+            self.predict_audio(Path::new(input), params, aggregation)
+        } else {
+            self.predict_youtube(input, params, aggregation)
+        }
+    }
+
+    /// Downloads the audio of a YouTube video, then predicts it.
+    pub fn predict_youtube(&self, id: &str, params: &SpectrogramParams, aggregation: Aggregation) -> Result<Vec<f32>> {
+        params.check_renderer()?;
+        let tempdir = tempfile::tempdir()?;
+        let path = tempdir.path().join(format!("trailer-{}.m4a", id));
+        external::download_youtube_m4a_by_id(id, &path, tempdir.path().join("trailer"))?;
+
+        self.predict_audio(&path, params, aggregation)
+    }
+}
+
+/// Writes an ONNX model taking channels-last windows and giving the sigmoid of the mean of each
+/// channel: black windows give 0.5 for the three labels, a red one about 1 for the first.
+#[cfg(test)]
+pub(crate) fn write_fixture_model<T: AsRef<Path>>(path: T) -> Result<()> {
+    use crate::tfrecord::{write_bytes, write_varint};
+
+    fn int(buf: &mut Vec<u8>, field: u64, value: u64) {
+        write_varint(buf, field << 3);
+        write_varint(buf, value);
+    }
+
+    fn value_info(name: &str, dims: &[u64]) -> Vec<u8> {
+        let mut shape = vec![];
+        for dim in dims {
+            let mut d = vec![];
+            int(&mut d, 1, *dim);
+            write_bytes(&mut shape, 1, &d);
+        }
+
+        let mut tensor = vec![];
+        int(&mut tensor, 1, 1);
+        write_bytes(&mut tensor, 2, &shape);
+
+        let mut ty = vec![];
+        write_bytes(&mut ty, 1, &tensor);
+
+        let mut info = vec![];
+        write_bytes(&mut info, 1, name.as_bytes());
+        write_bytes(&mut info, 2, &ty);
+        info
+    }
+
+    let mut axes = vec![];
+    write_bytes(&mut axes, 1, b"axes");
+    int(&mut axes, 8, 1);
+    int(&mut axes, 8, 2);
+    int(&mut axes, 20, 7);
+
+    let mut keepdims = vec![];
+    write_bytes(&mut keepdims, 1, b"keepdims");
+    int(&mut keepdims, 3, 0);
+    int(&mut keepdims, 20, 2);
+
+    let mut mean = vec![];
+    write_bytes(&mut mean, 1, b"input");
+    write_bytes(&mut mean, 2, b"mean");
+    write_bytes(&mut mean, 4, b"ReduceMean");
+    write_bytes(&mut mean, 5, &axes);
+    write_bytes(&mut mean, 5, &keepdims);
+
+    let mut sigmoid = vec![];
+    write_bytes(&mut sigmoid, 1, b"mean");
+    write_bytes(&mut sigmoid, 2, b"output");
+    write_bytes(&mut sigmoid, 4, b"Sigmoid");
+
+    let mut graph = vec![];
+    write_bytes(&mut graph, 1, &mean);
+    write_bytes(&mut graph, 1, &sigmoid);
+    write_bytes(&mut graph, 2, b"fixture");
+    write_bytes(&mut graph, 11, &value_info("input", &[1, 224, 224, 3]));
+    write_bytes(&mut graph, 12, &value_info("output", &[1, 3]));
+
+    let mut opset = vec![];
+    int(&mut opset, 2, 13);
+
+    let mut model = vec![];
+    int(&mut model, 1, 7);
+    write_bytes(&mut model, 7, &graph);
+    write_bytes(&mut model, 8, &opset);
+
+    Ok(fs::write(path, model)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{aggregate, check_image_size, dataset_params, windows, write_fixture_model, Aggregation, Model};
+    use crate::external::PreprocessOptions;
+    use crate::manifest::{Artifact, Manifest, SpectrogramParams};
+    use crate::movie::{Movie, TrailerSource};
+
+    use std::fs;
+
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn windows_cover_width() {
+        assert_eq!(windows(100, 224), [0]);
+        assert_eq!(windows(224, 224), [0]);
+        assert_eq!(windows(448, 224), [0, 224]);
+        assert_eq!(windows(500, 224), [0, 138, 276]);
+
+        for width in [225, 300, 1000, 1234] {
+            let w = windows(width, 224);
+            assert!(w.last().unwrap() + 224 <= width);
+            assert!(w.last().unwrap() + 224 + w.len() as u32 > width);
+        }
+    }
+
+    #[test]
+    fn aggregate_windows() {
+        let predictions = [vec![0.2, 0.9], vec![0.4, 0.1]];
+        let mean = aggregate(&predictions, Aggregation::Mean);
+        assert!((mean[0] - 0.3).abs() < 1e-6 && (mean[1] - 0.5).abs() < 1e-6);
+        assert_eq!(aggregate(&predictions, Aggregation::Max), [0.4, 0.9]);
+        assert!(aggregate(&[], Aggregation::Mean).is_empty());
+    }
+
+    #[test]
+    fn model() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("model.onnx");
+        write_fixture_model(&path).unwrap();
+
+        let labels = vec!["Action".to_owned(), "Comedy".to_owned(), "Drama".to_owned()];
+        assert!(Model::load(&path, labels[..2].to_vec()).is_err());
+        let model = Model::load(&path, labels).unwrap();
+
+        // Resized to 224 high, then split into two windows: the right one is red.
+        let mut image = RgbImage::new(448, 112);
+        for x in 224..448 {
+            for y in 0..112 {
+                image.put_pixel(x, y, Rgb([255, 0, 0]));
+            }
+        }
+
+        let mean = model.predict_image(&image, Aggregation::Mean).unwrap();
+        // Resizing blurs a little red into the left window.
+        assert!(mean[0] > 0.75 && mean[0] < 0.8, "{:?}", mean);
+        assert!((mean[1] - 0.5).abs() < 1e-6 && (mean[2] - 0.5).abs() < 1e-6);
+
+        let max = model.predict_image(&image, Aggregation::Max).unwrap();
+        assert!(max[0] > 0.99, "{:?}", max);
+
+        tempdir.close().unwrap();
+    }
+
+    #[test]
+    fn image_size() {
+        // 10 minutes at 100 ms per column make 6000 columns for 224 rows.
+        assert!(check_image_size(6000, 224).is_ok());
+        assert!(check_image_size(3000, 112).is_ok());
+        assert!(check_image_size(6001, 224).is_err());
+        assert!(check_image_size(1, 0).is_err());
+
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("model.onnx");
+        write_fixture_model(&path).unwrap();
+        let model = Model::load(&path, vec!["A".to_owned(), "B".to_owned(), "C".to_owned()]).unwrap();
+
+        // Scaled to 224 high, this would be 224 million pixels wide.
+        assert!(model.predict_image(&RgbImage::new(1_000_000, 1), Aggregation::Mean).is_err());
+
+        tempdir.close().unwrap();
+    }
+
+    #[test]
+    fn params() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let manifest_path = tempdir.path().join("manifest.json");
+        assert_eq!(dataset_params(None, &manifest_path).unwrap(), None);
+
+        let params = SpectrogramParams::new(PreprocessOptions {
+            loudness: Some(-23.0),
+            ..Default::default()
+        });
+        let mut manifest = Manifest::default();
+        manifest.insert(Artifact {
+            params: params.clone(),
+            ..Artifact::new(&Movie::default(), "a", TrailerSource::Id, String::new())
+        });
+        manifest.save(&manifest_path).unwrap();
+        assert_eq!(dataset_params(None, &manifest_path).unwrap(), Some(params.clone()));
+
+        let export = tempdir.path().join("export");
+        fs::create_dir(&export).unwrap();
+        assert!(dataset_params(Some(&export), &manifest_path).is_err());
+        let info = serde_json::json!({ "params": SpectrogramParams::new(PreprocessOptions::default()) });
+        fs::write(export.join("dataset.json"), info.to_string()).unwrap();
+        let loaded = dataset_params(Some(&export), &manifest_path).unwrap().unwrap();
+        assert_eq!(loaded.preprocess, PreprocessOptions::default());
+        fs::write(export.join("params.json"), serde_json::to_vec(&params).unwrap()).unwrap();
+        assert_eq!(dataset_params(Some(&export), &manifest_path).unwrap(), Some(params));
+
+        tempdir.close().unwrap();
+    }
+
+    #[cfg(feature = "native-decode")]
+    #[test]
+    fn long_audio() {
+        use crate::decode::tests::write_sine_wav;
+
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("model.onnx");
+        write_fixture_model(&path).unwrap();
+        let model = Model::load(&path, vec!["A".to_owned(), "B".to_owned(), "C".to_owned()]).unwrap();
+
+        let audio = tempdir.path().join("long.wav");
+        write_sine_wav(&audio, 1000, 100.0, 11 * 60 * 1000);
+        let params = SpectrogramParams::new(PreprocessOptions::default());
+        let err = model.predict_audio(&audio, &params, Aggregation::Mean).unwrap_err();
+        assert!(err.to_string().contains("longer than 10 minutes"), "{}", err);
+
+        tempdir.close().unwrap();
+    }
+}
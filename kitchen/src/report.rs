@@ -0,0 +1,536 @@
+//! Statistics of a working directory as a Markdown and an HTML report, with charts drawn as SVG
+//! so a dataset can be reviewed without a notebook.
+
+use crate::config::SelectionConfig;
+use crate::dataset::Dataset;
+use crate::error::*;
+use crate::manifest::Manifest;
+use crate::movie::Movie;
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+const LABEL_WIDTH: usize = 160;
+const BAR_WIDTH: usize = 400;
+const ROW_HEIGHT: usize = 20;
+const CELL_SIZE: usize = 28;
+const TITLE_HEIGHT: usize = 30;
+
+/// Bin edges of the histograms. The last bin is open-ended.
+const CRITICS_EDGES: [f64; 10] = [0.0, 100.0, 200.0, 300.0, 400.0, 500.0, 750.0, 1000.0, 1500.0, 2000.0];
+const METASCORE_EDGES: [f64; 10] = [0.0, 10.0, 20.0, 30.0, 40.0, 50.0, 60.0, 70.0, 80.0, 90.0];
+const DURATION_EDGES: [f64; 10] = [0.0, 30.0, 60.0, 90.0, 120.0, 150.0, 180.0, 240.0, 300.0, 600.0];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Chart {
+    Bars(Vec<(String, f64)>),
+    /// Square matrix with the same labels on both axes.
+    Heatmap(Vec<String>, Vec<Vec<usize>>),
+}
+
+#[derive(Debug, Clone, Default)]
+struct Section {
+    title: String,
+    text: String,
+    headers: Vec<String>,
+    rows: Vec<Vec<String>>,
+    chart: Option<Chart>,
+}
+
+impl Section {
+    fn new(title: &str, text: String) -> Section {
+        Section {
+            title: title.to_owned(),
+            text,
+            ..Default::default()
+        }
+    }
+
+    fn table(mut self, headers: &[&str], rows: Vec<Vec<String>>) -> Section {
+        self.headers = headers.iter().map(|h| h.to_string()).collect();
+        self.rows = rows;
+        self
+    }
+
+    fn chart(mut self, chart: Chart) -> Section {
+        self.chart = Some(chart);
+        self
+    }
+
+    /// File name of the chart, made from the title.
+    fn chart_file(&self) -> String {
+        let slug: String = self.title.to_lowercase()
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+            .collect();
+        format!("{}.svg", slug)
+    }
+
+    fn svg(&self) -> Option<String> {
+        match self.chart.as_ref()? {
+            Chart::Bars(bars) => Some(bar_chart(&self.title, bars)),
+            Chart::Heatmap(labels, values) => Some(heatmap(&self.title, labels, values)),
+        }
+    }
+}
+
+/// The report of a working directory.
+#[derive(Debug, Clone)]
+pub struct Report {
+    sections: Vec<Section>,
+}
+
+impl Report {
+    /// Collects the statistics. `rejected` are the reasons from `rejected.csv`.
+    pub fn new(movies: &[Movie], manifest: &Manifest, dataset: &Dataset, config: &SelectionConfig, rejected: &[String]) -> Report {
+        let mut sections = vec![];
+
+        sections.extend(pipeline(movies, manifest, dataset, config, rejected));
+        sections.push(coverage(movies, manifest, config));
+        sections.extend(labels(dataset));
+        sections.extend(metadata(dataset));
+
+        Report {
+            sections,
+        }
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let mut md = String::new();
+        writeln!(md, "# Dataset report\n").unwrap();
+
+        for section in &self.sections {
+            writeln!(md, "## {}\n", section.title).unwrap();
+            if !section.text.is_empty() {
+                writeln!(md, "{}\n", section.text).unwrap();
+            }
+            if section.chart.is_some() {
+                writeln!(md, "![{}]({})\n", section.title, section.chart_file()).unwrap();
+            }
+            if !section.headers.is_empty() {
+                writeln!(md, "| {} |", section.headers.join(" | ")).unwrap();
+                writeln!(md, "|---{}|", "|---:".repeat(section.headers.len() - 1)).unwrap();
+                for row in &section.rows {
+                    writeln!(md, "| {} |", row.join(" | ")).unwrap();
+                }
+                writeln!(md).unwrap();
+            }
+        }
+
+        md
+    }
+
+    /// A single HTML page with the charts inlined.
+    pub fn to_html(&self) -> String {
+        let mut html = String::new();
+        writeln!(html, "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Dataset report</title>").unwrap();
+        writeln!(html, "<style>body {{ font-family: sans-serif; max-width: 960px; margin: auto; }} \
+            table {{ border-collapse: collapse; margin: 1em 0; }} \
+            td, th {{ border: 1px solid #ccc; padding: 2px 8px; }} td {{ text-align: right; }} \
+            td:first-child {{ text-align: left; }}</style>").unwrap();
+        writeln!(html, "</head>\n<body>\n<h1>Dataset report</h1>").unwrap();
+
+        for section in &self.sections {
+            writeln!(html, "<h2>{}</h2>", escape(&section.title)).unwrap();
+            if !section.text.is_empty() {
+                writeln!(html, "<p>{}</p>", escape(&section.text)).unwrap();
+            }
+            if let Some(svg) = section.svg() {
+                writeln!(html, "{}", svg).unwrap();
+            }
+            if !section.headers.is_empty() {
+                writeln!(html, "<table>").unwrap();
+                let headers: Vec<_> = section.headers.iter().map(|h| format!("<th>{}</th>", escape(h))).collect();
+                writeln!(html, "<tr>{}</tr>", headers.concat()).unwrap();
+                for row in &section.rows {
+                    let cells: Vec<_> = row.iter().map(|c| format!("<td>{}</td>", escape(c))).collect();
+                    writeln!(html, "<tr>{}</tr>", cells.concat()).unwrap();
+                }
+                writeln!(html, "</table>").unwrap();
+            }
+        }
+
+        writeln!(html, "</body>\n</html>").unwrap();
+        html
+    }
+
+    /// Writes `report.md`, `report.html` and the charts into `dir`. Returns the Markdown path.
+    pub fn write<T: AsRef<Path>>(&self, dir: T) -> Result<PathBuf> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+
+        for section in &self.sections {
+            if let Some(svg) = section.svg() {
+                fs::write(dir.join(section.chart_file()), svg)?;
+            }
+        }
+
+        fs::write(dir.join("report.html"), self.to_html())?;
+        let path = dir.join("report.md");
+        fs::write(&path, self.to_markdown())?;
+
+        Ok(path)
+    }
+}
+
+/// Reads the reasons of `rejected.csv`, or nothing if no trailer was rejected yet.
+pub fn read_rejected<T: AsRef<Path>>(path: T) -> Result<Vec<String>> {
+    let path = path.as_ref();
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+
+    let mut reader = csv::Reader::from_reader(File::open(path)?);
+    let mut reasons = vec![];
+    for record in reader.records() {
+        reasons.push(record?.get(5).unwrap_or_default().to_owned());
+    }
+
+    Ok(reasons)
+}
+
+/// Groups rejection reasons, which carry the details of each video.
+fn rejection_kind(reason: &str) -> &str {
+    if reason.starts_with("score ") {
+        "low validation score"
+    } else if reason.starts_with("same audio as video") {
+        "same audio as another movie"
+    } else if reason.starts_with("length >") {
+        "too long"
+    } else {
+        reason
+    }
+}
+
+/// How many movies make it through each stage, and why the others do not.
+fn pipeline(movies: &[Movie], manifest: &Manifest, dataset: &Dataset, config: &SelectionConfig, rejected: &[String]) -> Vec<Section> {
+    let recent: Vec<_> = movies.iter().filter(|m| m.year >= Some(config.min_year)).collect();
+    let with_info: Vec<_> = recent.iter().filter(|m| !m.genres.is_empty()).collect();
+    let candidates: Vec<_> = with_info.iter()
+        .filter(|m| m.critics_number.is_some_and(|n| n >= config.min_critics))
+        .collect();
+    let with_trailer: Vec<_> = candidates.iter().filter(|m| manifest.get(m).is_some()).collect();
+    let selected = dataset.samples.len();
+
+    let mut labeled = 0;
+    for movie in &with_trailer {
+        let labels = dataset.mapping.map(&movie.genres);
+        if labels.iter().any(|l| dataset.labels.contains(l)) {
+            labeled += 1;
+        }
+    }
+
+    let count = |n: usize, total: usize| vec![n.to_string(), format!("{:.1}", percent(n, total))];
+    let stages = [
+        ("MovieLens movies".to_owned(), movies.len()),
+        (format!("From {} on", config.min_year), recent.len()),
+        ("With IMDb genres".to_owned(), with_info.len()),
+        (format!("With at least {} critics", config.min_critics), candidates.len()),
+        ("With a trailer".to_owned(), with_trailer.len()),
+        ("Selected".to_owned(), selected),
+    ];
+    let rows = stages.iter()
+        .map(|(stage, n)| [vec![stage.clone()], count(*n, movies.len())].concat())
+        .collect();
+    let funnel = Section::new("Pipeline", "Movies left after each stage.".to_owned())
+        .table(&["Stage", "Movies", "%"], rows)
+        .chart(Chart::Bars(stages.iter().map(|(s, n)| (s.clone(), *n as f64)).collect()));
+
+    let failures = [
+        ("IMDb", "no IMDb match or genres", recent.len() - with_info.len()),
+        ("IMDb", "no critics count", with_info.iter().filter(|m| m.critics_number.is_none()).count()),
+        ("IMDb", "too few critics", with_info.len() - candidates.len()
+            - with_info.iter().filter(|m| m.critics_number.is_none()).count()),
+        ("Trailer", "no accepted trailer", candidates.len() - with_trailer.len()),
+        ("Selection", "no selected label", with_trailer.len() - labeled),
+        ("Selection", "dropped by balancing", labeled - selected),
+    ];
+    let failures = Section::new("Failures by stage", "Movies lost at each stage, by reason.".to_owned())
+        .table(&["Stage", "Reason", "Movies"], failures.iter()
+            .map(|(stage, reason, n)| vec![stage.to_string(), reason.to_string(), n.to_string()])
+            .collect())
+        .chart(Chart::Bars(failures.iter()
+            .map(|(stage, reason, n)| (format!("{}: {}", stage, reason), *n as f64))
+            .collect()));
+
+    let mut kinds: BTreeMap<&str, usize> = BTreeMap::new();
+    for reason in rejected {
+        *kinds.entry(rejection_kind(reason)).or_default() += 1;
+    }
+    let mut kinds: Vec<_> = kinds.into_iter().collect();
+    kinds.sort_by_key(|(_, n)| std::cmp::Reverse(*n));
+
+    let text = format!("{} trailer candidates were rejected, from `rejected.csv`. A movie may have several.", rejected.len());
+    let rejections = Section::new("Rejected trailers", text)
+        .table(&["Reason", "Candidates", "%"], kinds.iter()
+            .map(|(kind, n)| [vec![kind.to_string()], count(*n, rejected.len())].concat())
+            .collect())
+        .chart(Chart::Bars(kinds.iter().map(|(k, n)| (k.to_string(), *n as f64)).collect()));
+
+    vec![funnel, failures, rejections]
+}
+
+/// How many of the movies from `min_year` on have each field filled.
+fn coverage(movies: &[Movie], manifest: &Manifest, config: &SelectionConfig) -> Section {
+    let recent: Vec<_> = movies.iter().filter(|m| m.year >= Some(config.min_year)).collect();
+    let trailer = |f: fn(&crate::movie::Trailer) -> bool| {
+        recent.iter().filter(|m| m.trailer.as_ref().is_some_and(f)).count()
+    };
+
+    let fields = vec![
+        ("imdb_id", recent.iter().filter(|m| m.imdb_id.is_some()).count()),
+        ("genres", recent.iter().filter(|m| !m.genres.is_empty()).count()),
+        ("critics_number", recent.iter().filter(|m| m.critics_number.is_some()).count()),
+        ("metacritic_score", recent.iter().filter(|m| m.metacritic_score.is_some()).count()),
+        ("trailer", trailer(|_| true)),
+        ("trailer.video", trailer(|t| t.video.is_some())),
+        ("trailer.fingerprint", trailer(|t| t.fingerprint.is_some())),
+        ("trailer.media", trailer(|t| t.media.is_some())),
+        ("trailer.audio", trailer(|t| t.audio.is_some())),
+        ("spectrogram", recent.iter().filter(|m| manifest.get(m).is_some()).count()),
+    ];
+
+    let text = format!("Fields filled in the {} movies from {} on.", recent.len(), config.min_year);
+    Section::new("Enrichment coverage", text)
+        .table(&["Field", "Movies", "%"], fields.iter()
+            .map(|(field, n)| vec![field.to_string(), n.to_string(), format!("{:.1}", percent(*n, recent.len()))])
+            .collect())
+        .chart(Chart::Bars(fields.iter().map(|(f, n)| (f.to_string(), percent(*n, recent.len()))).collect()))
+}
+
+fn labels(dataset: &Dataset) -> Vec<Section> {
+    let total = dataset.samples.len();
+    let counts = dataset.label_counts();
+
+    let frequencies = Section::new("Label frequencies", format!("Labels of the {} selected movies.", total))
+        .table(&["Label", "Movies", "%"], counts.iter()
+            .map(|(label, n)| vec![label.clone(), n.to_string(), format!("{:.1}", percent(*n, total))])
+            .collect())
+        .chart(Chart::Bars(counts.iter().map(|(l, n)| (l.clone(), *n as f64)).collect()));
+
+    let labels = &dataset.labels;
+    let mut matrix = vec![vec![0; labels.len()]; labels.len()];
+    for sample in &dataset.samples {
+        let multi_hot = dataset.multi_hot(sample);
+        for (i, row) in matrix.iter_mut().enumerate() {
+            for (j, cell) in row.iter_mut().enumerate() {
+                if multi_hot[i] == 1 && multi_hot[j] == 1 {
+                    *cell += 1;
+                }
+            }
+        }
+    }
+
+    let headers: Vec<&str> = std::iter::once("").chain(labels.iter().map(|l| &**l)).collect();
+    let co_occurrence = Section::new("Label co-occurrence", "Movies having both labels. The diagonal is the label count.".to_owned())
+        .table(&headers, labels.iter().zip(&matrix)
+            .map(|(label, row)| std::iter::once(label.clone()).chain(row.iter().map(|n| n.to_string())).collect())
+            .collect())
+        .chart(Chart::Heatmap(labels.clone(), matrix));
+
+    let mut cardinality: BTreeMap<usize, usize> = BTreeMap::new();
+    for sample in &dataset.samples {
+        let n = dataset.multi_hot(sample).iter().filter(|v| **v == 1).count();
+        *cardinality.entry(n).or_default() += 1;
+    }
+    let mean = cardinality.iter().map(|(k, n)| k * n).sum::<usize>() as f64 / total.max(1) as f64;
+
+    let cardinality = Section::new("Label cardinality", format!("Number of labels per movie, {:.2} on average.", mean))
+        .table(&["Labels", "Movies", "%"], cardinality.iter()
+            .map(|(k, n)| vec![k.to_string(), n.to_string(), format!("{:.1}", percent(*n, total))])
+            .collect())
+        .chart(Chart::Bars(cardinality.iter().map(|(k, n)| (k.to_string(), *n as f64)).collect()));
+
+    vec![frequencies, co_occurrence, cardinality]
+}
+
+/// Distributions of the metadata of the selected movies.
+fn metadata(dataset: &Dataset) -> Vec<Section> {
+    let movies = dataset.movies();
+
+    let mut years: BTreeMap<u32, usize> = BTreeMap::new();
+    let mut decades: BTreeMap<u32, usize> = BTreeMap::new();
+    for year in movies.iter().filter_map(|m| m.year) {
+        *years.entry(year).or_default() += 1;
+        *decades.entry(year / 10 * 10).or_default() += 1;
+    }
+
+    let distribution = |title: &str, text: &str, unit: &str, bars: Vec<(String, f64)>| {
+        Section::new(title, text.to_owned())
+            .table(&[unit, "Movies"], bars.iter().map(|(k, n)| vec![k.clone(), n.to_string()]).collect())
+            .chart(Chart::Bars(bars))
+    };
+
+    let critics: Vec<_> = movies.iter().filter_map(|m| m.critics_number).map(f64::from).collect();
+    let metascores: Vec<_> = movies.iter().filter_map(|m| m.metacritic_score).map(f64::from).collect();
+    let durations: Vec<_> = dataset.samples.iter().map(|s| s.artifact.duration_ms as f64 / 1000.0).collect();
+
+    vec![
+        distribution("Years", "Release years of the selected movies.", "Year",
+            years.iter().map(|(y, n)| (y.to_string(), *n as f64)).collect()),
+        distribution("Decades", "Release decades of the selected movies.", "Decade",
+            decades.iter().map(|(d, n)| (format!("{}s", d), *n as f64)).collect()),
+        distribution("Critics", "Number of critic reviews on IMDb.", "Critics",
+            histogram(&critics, &CRITICS_EDGES)),
+        distribution("Metascores", &format!("Metascores of the {} movies that have one.", metascores.len()), "Metascore",
+            histogram(&metascores, &METASCORE_EDGES)),
+        distribution("Trailer durations", "Length of the trailer audio in seconds.", "Seconds",
+            histogram(&durations, &DURATION_EDGES)),
+    ]
+}
+
+/// Counts values into bins between `edges`; the last bin takes everything above the last edge.
+fn histogram(values: &[f64], edges: &[f64]) -> Vec<(String, f64)> {
+    let mut bins: Vec<(String, f64)> = edges.iter().enumerate()
+        .map(|(i, edge)| match edges.get(i + 1) {
+            Some(next) => (format!("{}-{}", edge, next), 0.0),
+            None => (format!("{}+", edge), 0.0),
+        })
+        .collect();
+
+    for value in values {
+        if let Some(i) = edges.iter().rposition(|e| value >= e) {
+            bins[i].1 += 1.0;
+        }
+    }
+
+    bins
+}
+
+/// A horizontal bar chart with the labels on the left and the values after the bars.
+fn bar_chart(title: &str, bars: &[(String, f64)]) -> String {
+    let max = bars.iter().map(|(_, v)| *v).fold(0.0, f64::max);
+    let width = LABEL_WIDTH + BAR_WIDTH + 60;
+    let height = TITLE_HEIGHT + bars.len() * ROW_HEIGHT + 10;
+
+    let mut svg = String::new();
+    writeln!(svg, r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" font-family="sans-serif" font-size="12">"#, width, height).unwrap();
+    writeln!(svg, r#"<text x="0" y="16" font-size="14" font-weight="bold">{}</text>"#, escape(title)).unwrap();
+
+    for (i, (label, value)) in bars.iter().enumerate() {
+        let y = TITLE_HEIGHT + i * ROW_HEIGHT;
+        let length = if max > 0.0 { (value / max * BAR_WIDTH as f64).round() as usize } else { 0 };
+        writeln!(svg, r#"<text x="{}" y="{}" text-anchor="end">{}</text>"#, LABEL_WIDTH - 6, y + 14, escape(label)).unwrap();
+        writeln!(svg, r##"<rect x="{}" y="{}" width="{}" height="{}" fill="#4682b4"/>"##, LABEL_WIDTH, y + 2, length, ROW_HEIGHT - 4).unwrap();
+        writeln!(svg, r#"<text x="{}" y="{}">{}</text>"#, LABEL_WIDTH + length + 4, y + 14, format_value(*value)).unwrap();
+    }
+
+    writeln!(svg, "</svg>").unwrap();
+    svg
+}
+
+/// A matrix of cells shaded by their value, with the labels on the left and above.
+fn heatmap(title: &str, labels: &[String], values: &[Vec<usize>]) -> String {
+    let max = values.iter().flatten().copied().max().unwrap_or(0).max(1);
+    let top = TITLE_HEIGHT + LABEL_WIDTH;
+    let width = LABEL_WIDTH + labels.len() * CELL_SIZE + 10;
+    let height = top + labels.len() * CELL_SIZE + 10;
+
+    let mut svg = String::new();
+    writeln!(svg, r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" font-family="sans-serif" font-size="12">"#, width, height).unwrap();
+    writeln!(svg, r#"<text x="0" y="16" font-size="14" font-weight="bold">{}</text>"#, escape(title)).unwrap();
+
+    for (i, label) in labels.iter().enumerate() {
+        let offset = i * CELL_SIZE + CELL_SIZE / 2 + 4;
+        writeln!(svg, r#"<text x="{}" y="{}" text-anchor="end">{}</text>"#, LABEL_WIDTH - 6, top + offset, escape(label)).unwrap();
+        let x = LABEL_WIDTH + offset;
+        writeln!(svg, r#"<text x="{x}" y="{y}" transform="rotate(-90 {x} {y})">{}</text>"#, escape(label), x = x, y = top - 6).unwrap();
+    }
+
+    for (i, row) in values.iter().enumerate() {
+        for (j, value) in row.iter().enumerate() {
+            let (x, y) = (LABEL_WIDTH + j * CELL_SIZE, top + i * CELL_SIZE);
+            let opacity = *value as f64 / max as f64;
+            writeln!(svg, r##"<rect x="{}" y="{}" width="{}" height="{}" fill="#4682b4" fill-opacity="{:.3}" stroke="#fff"><title>{}</title></rect>"##,
+                x, y, CELL_SIZE, CELL_SIZE, opacity, value).unwrap();
+        }
+    }
+
+    writeln!(svg, "</svg>").unwrap();
+    svg
+}
+
+fn format_value(value: f64) -> String {
+    if value.fract() == 0.0 {
+        format!("{}", value)
+    } else {
+        format!("{:.1}", value)
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn percent(n: usize, total: usize) -> f64 {
+    n as f64 * 100.0 / total.max(1) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Report;
+    use crate::config::SelectionConfig;
+    use crate::dataset::Dataset;
+    use crate::labels::LabelMapping;
+    use crate::manifest::tests::{manifest, movie};
+    use crate::movie::{Genre, Movie};
+
+    #[test]
+    fn histogram() {
+        let bins = super::histogram(&[5.0, 10.0, 15.0, 40.0, -1.0], &[0.0, 10.0, 20.0]);
+        assert_eq!(bins, [
+            ("0-10".to_owned(), 1.0),
+            ("10-20".to_owned(), 2.0),
+            ("20+".to_owned(), 1.0),
+        ]);
+    }
+
+    #[test]
+    fn charts() {
+        let svg = super::bar_chart("A & B", &[("x".to_owned(), 2.0), ("<y>".to_owned(), 1.0)]);
+        assert_eq!(svg.matches("<rect").count(), 2);
+        assert!(svg.contains("A &amp; B") && svg.contains("&lt;y&gt;"));
+        assert!(svg.contains(r#"width="400""#) && svg.contains(r#"width="200""#));
+
+        let labels = vec!["a".to_owned(), "b".to_owned()];
+        let svg = super::heatmap("", &labels, &[vec![2, 1], vec![1, 1]]);
+        assert_eq!(svg.matches("<rect").count(), 4);
+        assert!(svg.contains(r#"fill-opacity="0.500""#));
+    }
+
+    #[test]
+    fn report() {
+        let genres = [vec![Genre::Drama], vec![Genre::Drama, Genre::Comedy], vec![], vec![Genre::Horror]];
+        let movies: Vec<_> = genres.iter().enumerate()
+            .map(|(i, genres)| Movie {
+                genres: genres.clone(),
+                critics_number: Some(300),
+                ..movie(&i.to_string())
+            })
+            .collect();
+
+        let mut manifest = manifest(&movies[..2]);
+        for artifact in &mut manifest.artifacts {
+            artifact.duration_ms = 95_000;
+        }
+
+        let config = SelectionConfig::default();
+        let dataset = Dataset::select(&movies, &manifest, &config, &LabelMapping::default()).unwrap();
+        let rejected = vec!["score -3: too short".to_owned(), "length > 10 minutes".to_owned(), "score -1: x".to_owned()];
+        let report = Report::new(&movies, &manifest, &dataset, &config, &rejected);
+
+        let md = report.to_markdown();
+        assert!(md.contains("| With a trailer | 2 | 50.0 |"));
+        assert!(md.contains("| IMDb | no IMDb match or genres | 1 |"));
+        assert!(md.contains("| Trailer | no accepted trailer | 1 |"));
+        assert!(md.contains("| low validation score | 2 | 66.7 |"));
+        assert!(md.contains("| Drama | 2 | 1 |"));
+        assert!(md.contains("| 90-120 | 2 |"));
+        assert!(md.contains("![Label co-occurrence](label-co-occurrence.svg)"));
+
+        let html = report.to_html();
+        assert_eq!(html.matches("<svg").count(), md.matches("![").count());
+    }
+}
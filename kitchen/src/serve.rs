@@ -0,0 +1,266 @@
+//! A small HTTP API predicting the genres of trailers with a [`Model`].
+//!
+//! ```text
+//! GET  /labels                   the label set, in class order
+//! GET  /model                    the model file, labels, window size and spectrogram parameters
+//! POST /predict                  the body is an audio file, or a spectrogram with an image/* type
+//! POST /predict?youtube_id=ID    downloads the audio of the video
+//! ```
+//!
+//! Predictions are a list of `{"label": ..., "probability": ...}` in class order. Errors are
+//! `{"error": ...}`.
+
+use crate::manifest::SpectrogramParams;
+use crate::predict::{self, Aggregation, Model, WINDOW_SIZE};
+
+use std::fs;
+use std::io::{Cursor, Read};
+use std::path::PathBuf;
+use std::thread;
+
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+/// Bounds on what the server accepts.
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    /// Largest accepted request body, in bytes.
+    pub max_body: usize,
+    /// Requests handled at the same time. Others wait until one finishes.
+    pub concurrency: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_body: 50 * 1024 * 1024,
+            concurrency: 2,
+        }
+    }
+}
+
+pub struct Service {
+    pub model: Model,
+    /// Reported by `GET /model`.
+    pub model_path: PathBuf,
+    pub params: SpectrogramParams,
+    pub aggregation: Aggregation,
+    pub limits: Limits,
+}
+
+impl Service {
+    /// Answers the requests of `server` with `limits.concurrency` threads, until it is unblocked
+    /// once for each of them.
+    pub fn run(&self, server: &Server) {
+        thread::scope(|s| {
+            for _ in 0..self.limits.concurrency.max(1) {
+                s.spawn(|| {
+                    while let Ok(request) = server.recv() {
+                        self.handle(request);
+                    }
+                });
+            }
+        });
+    }
+
+    fn handle(&self, mut request: Request) {
+        let content_type = request.headers().iter()
+            .find(|h| h.field.equiv("Content-Type"))
+            .map(|h| h.value.as_str().to_owned());
+
+        let result = if request.body_length().is_some_and(|len| len > self.limits.max_body) {
+            Err((413, "request body too large".to_owned()))
+        } else {
+            let mut body = vec![];
+            let limit = self.limits.max_body as u64 + 1;
+            match request.as_reader().take(limit).read_to_end(&mut body) {
+                Ok(_) if body.len() > self.limits.max_body => Err((413, "request body too large".to_owned())),
+                Ok(_) => self.route(request.method(), request.url(), content_type.as_deref(), &body),
+                Err(err) => Err((400, err.to_string())),
+            }
+        };
+
+        let (status, value) = match result {
+            Ok(value) => (200, value),
+            Err((status, error)) => (status, json!({ "error": error })),
+        };
+
+        let header = Header::from_bytes("Content-Type", "application/json").unwrap();
+        let response = Response::from_string(value.to_string()).with_status_code(status).with_header(header);
+        if let Err(err) = request.respond(response) {
+            eprintln!("Failed to respond: {}", err);
+        }
+    }
+
+    /// Answers one request, or gives the status and message of the error.
+    pub fn route(
+        &self,
+        method: &Method,
+        url: &str,
+        content_type: Option<&str>,
+        body: &[u8],
+    ) -> std::result::Result<Value, (u16, String)> {
+        let (path, query) = url.split_once('?').unwrap_or((url, ""));
+
+        match (method, path) {
+            (Method::Get, "/labels") => Ok(json!(self.model.labels)),
+            (Method::Get, "/model") => Ok(json!({
+                "file": self.model_path.file_name().map(|n| n.to_string_lossy()),
+                "labels": self.model.labels,
+                "window_size": WINDOW_SIZE,
+                "aggregation": self.aggregation.name(),
+                "params": self.params,
+            })),
+            (Method::Post, "/predict") => {
+                let youtube_id = query.split('&')
+                    .filter_map(|pair| pair.split_once('='))
+                    .find(|(key, _)| *key == "youtube_id")
+                    .map(|(_, value)| value);
+
+                let probabilities = match youtube_id {
+                    Some(id) if !is_youtube_id(id) => return Err((400, format!("invalid YouTube id '{}'", id))),
+                    Some(id) => self.model.predict_youtube(id, &self.params, self.aggregation),
+                    None if body.is_empty() => return Err((400, "expected an audio file or a youtube_id".to_owned())),
+                    None if content_type.is_some_and(|t| t.starts_with("image/")) => {
+                        // Checked before decoding, which allocates the whole image.
+                        let (width, height) = image::io::Reader::new(Cursor::new(body))
+                            .with_guessed_format()
+                            .map_err(|e| (400, e.to_string()))?
+                            .into_dimensions()
+                            .map_err(|e| (400, e.to_string()))?;
+                        predict::check_image_size(width, height).map_err(|e| (422, e.to_string()))?;
+
+                        let image = image::load_from_memory(body).map_err(|e| (400, e.to_string()))?.to_rgb8();
+                        self.model.predict_image(&image, self.aggregation)
+                    },
+                    None => self.predict_body(body),
+                };
+                let probabilities = probabilities.map_err(|e| (422, e.to_string()))?;
+
+                let labels: Vec<_> = self.model.labels.iter().zip(probabilities)
+                    .map(|(label, probability)| json!({ "label": label, "probability": probability }))
+                    .collect();
+                Ok(json!(labels))
+            },
+            (_, "/labels") | (_, "/model") | (_, "/predict") => Err((405, "method not allowed".to_owned())),
+            _ => Err((404, "not found".to_owned())),
+        }
+    }
+
+    fn predict_body(&self, body: &[u8]) -> crate::Result<Vec<f32>> {
+        let tempdir = tempfile::tempdir()?;
+        let path = tempdir.path().join("audio");
+        fs::write(&path, body)?;
+
+        self.model.predict_audio(&path, &self.params, self.aggregation)
+    }
+}
+
+/// Only plain ids are passed on to youtube-dl, so they can't be taken for options or URLs.
+fn is_youtube_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 64
+        && !id.starts_with('-')
+        && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_youtube_id, Limits, Service};
+    use crate::external::PreprocessOptions;
+    use crate::manifest::SpectrogramParams;
+    use crate::predict::{write_fixture_model, Aggregation, Model};
+
+    use std::io::{Cursor, Read, Write};
+    use std::net::TcpStream;
+    use std::thread;
+
+    use image::{ImageOutputFormat, RgbImage};
+    use tiny_http::{Method, Server};
+
+    fn service(dir: &std::path::Path, limits: Limits) -> Service {
+        let path = dir.join("model.onnx");
+        write_fixture_model(&path).unwrap();
+        let labels = vec!["Action".to_owned(), "Comedy".to_owned(), "Drama".to_owned()];
+
+        Service {
+            model: Model::load(&path, labels).unwrap(),
+            model_path: path,
+            params: SpectrogramParams::new(PreprocessOptions::default()),
+            aggregation: Aggregation::Mean,
+            limits,
+        }
+    }
+
+    #[test]
+    fn routes() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let service = service(tempdir.path(), Limits::default());
+
+        let labels = service.route(&Method::Get, "/labels", None, b"").unwrap();
+        assert_eq!(labels, serde_json::json!(["Action", "Comedy", "Drama"]));
+        let model = service.route(&Method::Get, "/model", None, b"").unwrap();
+        assert_eq!(model["file"], "model.onnx");
+        assert_eq!(model["window_size"], 224);
+
+        let mut jpeg = vec![];
+        image::DynamicImage::ImageRgb8(RgbImage::new(300, 224))
+            .write_to(&mut Cursor::new(&mut jpeg), ImageOutputFormat::Jpeg(90))
+            .unwrap();
+        let prediction = service.route(&Method::Post, "/predict", Some("image/jpeg"), &jpeg).unwrap();
+        assert_eq!(prediction[1]["label"], "Comedy");
+        assert!((prediction[1]["probability"].as_f64().unwrap() - 0.5).abs() < 0.01);
+
+        let mut wide = vec![];
+        image::DynamicImage::ImageRgb8(RgbImage::new(6000, 8))
+            .write_to(&mut Cursor::new(&mut wide), ImageOutputFormat::Jpeg(90))
+            .unwrap();
+        let error = service.route(&Method::Post, "/predict", Some("image/jpeg"), &wide).unwrap_err();
+        assert_eq!(error.0, 422, "{}", error.1);
+
+        let status = |method, url, body: &[u8]| service.route(method, url, None, body).unwrap_err().0;
+        assert_eq!(status(&Method::Post, "/predict", b""), 400);
+        assert_eq!(status(&Method::Post, "/predict?youtube_id=--exec=rm", b""), 400);
+        assert_eq!(status(&Method::Get, "/predict", b""), 405);
+        assert_eq!(status(&Method::Get, "/nothing", b""), 404);
+
+        assert!(is_youtube_id("dQw4w9WgXcQ"));
+        assert!(!is_youtube_id("https://example.com/") && !is_youtube_id("-x") && !is_youtube_id(""));
+
+        tempdir.close().unwrap();
+    }
+
+    #[test]
+    fn server() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let limits = Limits { max_body: 1024, concurrency: 2 };
+        let service = service(tempdir.path(), limits);
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let addr = server.server_addr().to_ip().unwrap();
+
+        let send = |request: &[u8]| {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream.write_all(request).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+
+        thread::scope(|s| {
+            s.spawn(|| service.run(&server));
+
+            let response = send(b"GET /labels HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+            assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+            assert!(response.ends_with(r#"["Action","Comedy","Drama"]"#), "{}", response);
+
+            let response = send(b"POST /predict HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: 2048\r\n\r\n");
+            assert!(response.starts_with("HTTP/1.1 413"), "{}", response);
+
+            for _ in 0..limits.concurrency {
+                server.unblock();
+            }
+        });
+
+        tempdir.close().unwrap();
+    }
+}
@@ -0,0 +1,265 @@
+//! Keeps downloaded trailer audio, so spectrograms can be rebuilt without the network.
+
+use crate::error::*;
+use crate::layout;
+
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str;
+use std::sync::Mutex;
+
+use serde::{Serialize, Deserialize};
+
+/// How audio is kept in the store.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AudioFormat {
+    /// The downloaded file as is.
+    Original,
+    /// Transcoded to a small Opus file. Good enough for spectrograms.
+    Opus,
+    /// Transcoded to FLAC, keeping the decoded audio bit-exact.
+    Flac,
+}
+
+impl AudioFormat {
+    pub fn from_text(text: &str) -> Result<AudioFormat> {
+        match text {
+            "original" => Ok(AudioFormat::Original),
+            "opus" => Ok(AudioFormat::Opus),
+            "flac" => Ok(AudioFormat::Flac),
+            x => Err(Error::ParseError(format!("audio format: {}", x))),
+        }
+    }
+}
+
+/// A file put into the store.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StoredAudio {
+    pub file_name: String,
+    pub md5: String,
+    pub size: u64,
+}
+
+/// Keeps downloaded audio under its YouTube id, sharded like the rest of the `Layout`, so
+/// features can be rebuilt without downloading again. When `max_size` is set, the least recently
+/// stored files are evicted to stay below it.
+pub struct AudioStore {
+    dir: PathBuf,
+    format: AudioFormat,
+    max_size: Option<u64>,
+    /// Held while files are moved in or evicted, so processing threads don't evict the same files
+    /// or a file that is still being stored.
+    lock: Mutex<()>,
+}
+
+const EXTENSIONS: &[&str] = &["m4a", "opus", "flac"];
+
+impl AudioStore {
+    pub fn new<T: Into<PathBuf>>(dir: T, format: AudioFormat, max_size: Option<u64>) -> AudioStore {
+        AudioStore {
+            dir: dir.into(),
+            format,
+            max_size,
+            lock: Mutex::new(()),
+        }
+    }
+
+    pub fn format(&self) -> AudioFormat {
+        self.format
+    }
+
+    /// Moves (or transcodes) the audio at `path` into the store.
+    pub fn put<T: AsRef<Path>>(&self, id: &str, path: T) -> Result<StoredAudio> {
+        let source = match self.format {
+            AudioFormat::Original => path.as_ref().to_owned(),
+            AudioFormat::Opus => transcode(path.as_ref(), "opus", &["-c:a", "libopus", "-b:a", "48k"])?,
+            AudioFormat::Flac => transcode(path.as_ref(), "flac", &["-c:a", "flac"])?,
+        };
+        let ext = source.extension().and_then(|e| e.to_str()).unwrap_or("m4a");
+        let target = self.path(id, ext);
+
+        let _guard = self.lock.lock().unwrap();
+        fs::create_dir_all(self.dir.join(layout::shard(id)))?;
+
+        for old in self.find_all(id) {
+            remove(&old)?;
+        }
+
+        if fs::rename(&source, &target).is_err() {
+            fs::copy(&source, &target)?;
+            fs::remove_file(&source)?;
+        }
+
+        let bytes = fs::read(&target)?;
+        let stored = StoredAudio {
+            file_name: target.file_name().unwrap().to_string_lossy().into_owned(),
+            md5: format!("{:x}", md5::compute(&bytes)),
+            size: bytes.len() as u64,
+        };
+
+        self.evict(Some(&target))?;
+
+        Ok(stored)
+    }
+
+    /// Path of the stored audio of the video, if any.
+    pub fn get(&self, id: &str) -> Option<PathBuf> {
+        self.find_all(id).into_iter().next()
+    }
+
+    /// Evicts the oldest files until the store fits in `max_size`. Returns the number of bytes
+    /// freed; files removed by someone else meanwhile count as already freed.
+    pub fn enforce_retention(&self) -> Result<u64> {
+        let _guard = self.lock.lock().unwrap();
+        self.evict(None)
+    }
+
+    /// Evicts with the lock held, never evicting `keep`.
+    fn evict(&self, keep: Option<&Path>) -> Result<u64> {
+        let max_size = match self.max_size {
+            Some(m) => m,
+            None => return Ok(0),
+        };
+
+        let mut files = vec![];
+        for shard in fs::read_dir(&self.dir)? {
+            let shard = shard?;
+            if !shard.file_type()?.is_dir() {
+                continue;
+            }
+
+            for entry in fs::read_dir(shard.path())? {
+                let entry = entry?;
+                let metadata = match entry.metadata() {
+                    Ok(metadata) => metadata,
+                    Err(err) if err.kind() == ErrorKind::NotFound => continue,
+                    Err(err) => return Err(err.into()),
+                };
+                if metadata.is_file() {
+                    files.push((metadata.modified()?, metadata.len(), entry.path()));
+                }
+            }
+        }
+
+        files.sort();
+
+        let mut total: u64 = files.iter().map(|(_, size, _)| size).sum();
+        let mut freed = 0;
+
+        for (_, size, path) in files {
+            if total <= max_size {
+                break;
+            }
+            if Some(&*path) == keep {
+                continue;
+            }
+
+            if remove(&path)? {
+                freed += size;
+            }
+            total -= size;
+        }
+
+        Ok(freed)
+    }
+
+    fn find_all(&self, id: &str) -> Vec<PathBuf> {
+        EXTENSIONS.iter()
+            .map(|ext| self.path(id, ext))
+            .filter(|p| p.exists())
+            .collect()
+    }
+
+    fn path(&self, id: &str, ext: &str) -> PathBuf {
+        self.dir.join(layout::shard(id)).join(format!("{}.{}", id, ext))
+    }
+}
+
+/// Transcodes the audio at `path` next to it, removing the original.
+fn transcode(path: &Path, ext: &str, codec: &[&str]) -> Result<PathBuf> {
+    let target = path.with_extension(format!("store.{}", ext));
+    let output = Command::new("ffmpeg")
+        .arg("-i")
+        .arg(path.as_os_str())
+        .args(["-vn", "-y"])
+        .args(codec)
+        .arg(&target)
+        .output()?;
+
+    if !output.status.success() {
+        return Err(Error::FFMPEG(str::from_utf8(&output.stderr).unwrap().to_owned()));
+    }
+
+    fs::remove_file(path)?;
+
+    Ok(target)
+}
+
+/// Removes a file, returning false if it was already gone.
+fn remove(path: &Path) -> Result<bool> {
+    match fs::remove_file(path) {
+        Ok(()) => Ok(true),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(false),
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AudioFormat, AudioStore};
+
+    use std::fs;
+    use std::thread;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn put_and_retention() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let store = AudioStore::new(tempdir.path().join("audio"), AudioFormat::Original, Some(250));
+
+        for (i, id) in ["a1", "b1", "b2"].iter().enumerate() {
+            let path = tempdir.path().join(format!("download-{}.m4a", id));
+            fs::write(&path, vec![i as u8; 100]).unwrap();
+
+            let stored = store.put(id, &path).unwrap();
+            assert_eq!(stored.file_name, format!("{}.m4a", id));
+            assert_eq!(stored.size, 100);
+            assert!(!path.exists());
+
+            // Make the modification times distinct regardless of the filesystem resolution.
+            let file = fs::File::options().write(true).open(store.get(id).unwrap()).unwrap();
+            file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(1000 + i as u64)).unwrap();
+        }
+
+        store.enforce_retention().unwrap();
+        assert!(store.get("a1").is_none());
+        assert!(store.get("b1").is_some());
+        assert_eq!(store.get("b2").unwrap(), tempdir.path().join("audio/b2/b2.m4a"));
+
+        tempdir.close().unwrap();
+    }
+
+    #[test]
+    fn concurrent_retention() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let store = AudioStore::new(tempdir.path().join("audio"), AudioFormat::Original, Some(300));
+
+        thread::scope(|s| {
+            for t in 0..8 {
+                let (store, dir) = (&store, tempdir.path());
+                s.spawn(move || for i in 0..20 {
+                    let id = format!("t{}-{}", t, i);
+                    let path = dir.join(format!("download-{}.m4a", id));
+                    fs::write(&path, [0; 100]).unwrap();
+                    store.put(&id, &path).unwrap();
+                });
+            }
+        });
+
+        assert_eq!(store.enforce_retention().unwrap(), 0);
+
+        tempdir.close().unwrap();
+    }
+}
@@ -0,0 +1,159 @@
+//! Writes TFRecord files of `tf.train.Example` protos, without depending on TensorFlow or a
+//! protobuf library.
+
+use crate::error::*;
+
+use std::io::Write;
+
+use lazy_static::lazy_static;
+
+lazy_static! {
+    static ref CRC32C_TABLE: [u32; 256] = {
+        let mut table = [0; 256];
+        for (i, entry) in table.iter_mut().enumerate() {
+            let mut crc = i as u32;
+            for _ in 0..8 {
+                crc = if crc & 1 == 1 { (crc >> 1) ^ 0x82f6_3b78 } else { crc >> 1 };
+            }
+            *entry = crc;
+        }
+        table
+    };
+}
+
+/// A value of `tf.train.Feature`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Feature {
+    Bytes(Vec<Vec<u8>>),
+    Floats(Vec<f32>),
+    Ints(Vec<i64>),
+}
+
+/// Encodes a `tf.train.Example` with the given features.
+pub fn encode_example(features: &[(&str, Feature)]) -> Vec<u8> {
+    let mut map = vec![];
+
+    for (key, feature) in features {
+        let mut list = vec![];
+        let field = match feature {
+            Feature::Bytes(values) => {
+                for value in values {
+                    write_bytes(&mut list, 1, value);
+                }
+                1
+            },
+            Feature::Floats(values) => {
+                let packed: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+                write_bytes(&mut list, 1, &packed);
+                2
+            },
+            Feature::Ints(values) => {
+                let mut packed = vec![];
+                for value in values {
+                    write_varint(&mut packed, *value as u64);
+                }
+                write_bytes(&mut list, 1, &packed);
+                3
+            },
+        };
+
+        let mut value = vec![];
+        write_bytes(&mut value, field, &list);
+
+        let mut entry = vec![];
+        write_bytes(&mut entry, 1, key.as_bytes());
+        write_bytes(&mut entry, 2, &value);
+
+        write_bytes(&mut map, 1, &entry);
+    }
+
+    let mut example = vec![];
+    write_bytes(&mut example, 1, &map);
+    example
+}
+
+pub(crate) fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+/// Writes a length-delimited field.
+pub(crate) fn write_bytes(buf: &mut Vec<u8>, field: u64, data: &[u8]) {
+    write_varint(buf, field << 3 | 2);
+    write_varint(buf, data.len() as u64);
+    buf.extend(data);
+}
+
+pub fn crc32c(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc, b| (crc >> 8) ^ CRC32C_TABLE[((crc ^ *b as u32) & 0xff) as usize])
+}
+
+fn masked_crc32c(data: &[u8]) -> u32 {
+    let crc = crc32c(data);
+    crc.rotate_right(15).wrapping_add(0xa282_ead8)
+}
+
+/// Frames records as TFRecord: length, checksum of the length, data, checksum of the data.
+pub struct TFRecordWriter<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> TFRecordWriter<W> {
+    pub fn new(inner: W) -> TFRecordWriter<W> {
+        TFRecordWriter {
+            inner,
+        }
+    }
+
+    pub fn write(&mut self, data: &[u8]) -> Result<()> {
+        let len = (data.len() as u64).to_le_bytes();
+
+        self.inner.write_all(&len)?;
+        self.inner.write_all(&masked_crc32c(&len).to_le_bytes())?;
+        self.inner.write_all(data)?;
+        self.inner.write_all(&masked_crc32c(data).to_le_bytes())?;
+
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Feature, TFRecordWriter};
+
+    #[test]
+    fn crc32c() {
+        assert_eq!(super::crc32c(b"123456789"), 0xe306_9283);
+        assert_eq!(super::crc32c(b""), 0);
+    }
+
+    #[test]
+    fn encode_example() {
+        assert_eq!(
+            super::encode_example(&[("a", Feature::Ints(vec![1]))]),
+            [0x0a, 0x0c, 0x0a, 0x0a, 0x0a, 0x01, b'a', 0x12, 0x05, 0x1a, 0x03, 0x0a, 0x01, 0x01],
+        );
+        assert_eq!(
+            super::encode_example(&[("b", Feature::Bytes(vec![b"xy".to_vec()]))]),
+            [0x0a, 0x0d, 0x0a, 0x0b, 0x0a, 0x01, b'b', 0x12, 0x06, 0x0a, 0x04, 0x0a, 0x02, b'x', b'y'],
+        );
+    }
+
+    #[test]
+    fn write() {
+        let mut writer = TFRecordWriter::new(vec![]);
+        writer.write(b"abc").unwrap();
+
+        let data = writer.into_inner();
+        assert_eq!(data.len(), 8 + 4 + 3 + 4);
+        assert_eq!(&data[..8], &3u64.to_le_bytes());
+        assert_eq!(&data[12..15], b"abc");
+    }
+}
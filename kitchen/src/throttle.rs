@@ -0,0 +1,152 @@
+//! Process-wide politeness limits for requests to each host.
+
+use std::collections::HashMap;
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use lazy_static::lazy_static;
+
+lazy_static! {
+    static ref THROTTLE: Throttle = Throttle::new(Limit::default());
+}
+
+/// How hard a single host may be hit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limit {
+    /// Requests allowed to be in flight at the same time.
+    pub concurrent: usize,
+    /// Minimum time between the start of two requests.
+    pub interval: Duration,
+}
+
+impl Default for Limit {
+    fn default() -> Self {
+        Limit {
+            concurrent: 8,
+            interval: Duration::from_millis(200),
+        }
+    }
+}
+
+#[derive(Default)]
+struct HostState {
+    active: usize,
+    last_start: Option<Instant>,
+}
+
+/// Per-host politeness: bounds the number of concurrent requests and spaces out their starts.
+pub struct Throttle {
+    default_limit: Mutex<Limit>,
+    hosts: Mutex<HashMap<String, HostState>>,
+    released: Condvar,
+}
+
+/// Held while a request is in flight. Dropping it lets the next request to the host start.
+pub struct Permit<'a> {
+    throttle: &'a Throttle,
+    host: String,
+}
+
+impl Throttle {
+    pub fn new(default_limit: Limit) -> Throttle {
+        Throttle {
+            default_limit: Mutex::new(default_limit),
+            hosts: Mutex::new(HashMap::new()),
+            released: Condvar::new(),
+        }
+    }
+
+    pub fn set_limit(&self, limit: Limit) {
+        *self.default_limit.lock().unwrap() = limit;
+        self.released.notify_all();
+    }
+
+    /// Blocks until a request to `host` is allowed.
+    pub fn acquire(&self, host: &str) -> Permit<'_> {
+        let mut hosts = self.hosts.lock().unwrap();
+
+        loop {
+            let limit = *self.default_limit.lock().unwrap();
+            let state = hosts.entry(host.to_owned()).or_default();
+
+            if state.active < limit.concurrent {
+                let wait = state.last_start
+                    .map(|t| limit.interval.saturating_sub(t.elapsed()))
+                    .unwrap_or_default();
+
+                if wait.is_zero() {
+                    state.active += 1;
+                    state.last_start = Some(Instant::now());
+
+                    return Permit {
+                        throttle: self,
+                        host: host.to_owned(),
+                    };
+                }
+
+                hosts = self.released.wait_timeout(hosts, wait).unwrap().0;
+            } else {
+                hosts = self.released.wait(hosts).unwrap();
+            }
+        }
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        let mut hosts = self.throttle.hosts.lock().unwrap();
+        if let Some(state) = hosts.get_mut(&self.host) {
+            state.active -= 1;
+        }
+        self.throttle.released.notify_all();
+    }
+}
+
+/// Sets the limit used by `acquire` for every host.
+pub fn set_limit(limit: Limit) {
+    THROTTLE.set_limit(limit)
+}
+
+/// Blocks until a request to `host` is allowed by the process-wide throttle.
+pub fn acquire(host: &str) -> Permit<'static> {
+    THROTTLE.acquire(host)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Limit, Throttle};
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn acquire() {
+        let throttle = Throttle::new(Limit {
+            concurrent: 2,
+            interval: Duration::from_millis(20),
+        });
+        let active = AtomicUsize::new(0);
+        let max_active = AtomicUsize::new(0);
+        let start = Instant::now();
+
+        std::thread::scope(|s| {
+            for _ in 0..6 {
+                s.spawn(|| {
+                    let _permit = throttle.acquire("example.com");
+                    let n = active.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_active.fetch_max(n, Ordering::SeqCst);
+                    std::thread::sleep(Duration::from_millis(10));
+                    active.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+
+            // Other hosts are not slowed down.
+            let other = Instant::now();
+            drop(throttle.acquire("example.org"));
+            assert!(other.elapsed() < Duration::from_millis(20));
+        });
+
+        assert!(max_active.load(Ordering::SeqCst) <= 2);
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,262 @@
+//! Fuzzy matching of movie titles, for finding a MovieLens movie among search results. Titles are
+//! normalized, then compared as sets of words, with the distance between the years scored
+//! separately.
+
+use crate::movielens;
+
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+const TITLE_WEIGHT: f64 = 0.7;
+const YEAR_WEIGHT: f64 = 0.3;
+/// Factor of the similarity of titles that only match once a subtitle is dropped.
+const SUBTITLE_FACTOR: f64 = 0.9;
+/// Year similarity when either year is unknown.
+const UNKNOWN_YEAR: f64 = 0.5;
+/// Lowest score of an accepted match.
+const MIN_SCORE: f64 = 0.75;
+/// Articles dropped from titles before comparing them. Articles of other languages, such as
+/// "die" or "i", are common words in English titles, so they are kept on both sides.
+const ARTICLES: &[&str] = &["a", "an", "the"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Score {
+    /// Similarity of the titles, from 0 to 1.
+    pub title: f64,
+    /// Similarity of the years, 1 for the same year and halved for each year apart.
+    pub year: f64,
+    /// Weighted sum of both.
+    pub total: f64,
+}
+
+impl Score {
+    pub fn is_accepted(&self) -> bool {
+        self.total >= MIN_SCORE
+    }
+}
+
+/// A candidate of `rank`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ranked<T> {
+    pub item: T,
+    pub title: String,
+    pub year: Option<u32>,
+    pub score: Score,
+}
+
+/// Folds accents and case, spells out `&`, drops punctuation and turns roman numerals into
+/// numbers: `"Amélie & Co. Part II"` becomes `"amelie and co part 2"`.
+pub fn normalize(title: &str) -> String {
+    let folded: String = title.nfkd()
+        .filter(|c| !is_combining_mark(*c))
+        .flat_map(char::to_lowercase)
+        .filter(|c| !matches!(c, '\'' | '’' | '.'))
+        .map(|c| if c.is_alphanumeric() || c == '&' { c } else { ' ' })
+        .collect();
+
+    let words: Vec<_> = folded.replace('&', " and ")
+        .split_whitespace()
+        .map(|w| roman(w).map(|n| n.to_string()).unwrap_or_else(|| w.to_owned()))
+        .collect();
+
+    words.join(" ")
+}
+
+/// The normalized words of a title, without its leading English article. Articles moved to the
+/// end (`"Godfather, The"`) are dropped the same way.
+fn tokens(title: &str) -> Vec<String> {
+    let (title, _) = movielens::move_article(title);
+    let mut tokens: Vec<_> = normalize(movielens::strip_article(&title, Some(ARTICLES)))
+        .split(' ')
+        .filter(|w| !w.is_empty())
+        .map(|w| w.to_owned())
+        .collect();
+    tokens.sort_unstable();
+    tokens.dedup();
+    tokens
+}
+
+/// The title without its subtitle, if it has one.
+fn main_title(title: &str) -> Option<&str> {
+    let end = title.find(':').or_else(|| title.find(" - "))?;
+    Some(&title[..end])
+}
+
+/// Dice coefficient of two sorted word sets.
+fn dice(a: &[String], b: &[String]) -> f64 {
+    if a.is_empty() && b.is_empty() {
+        return 1.0;
+    }
+
+    let common = a.iter().filter(|w| b.binary_search(w).is_ok()).count();
+    2.0 * common as f64 / (a.len() + b.len()) as f64
+}
+
+/// How alike two titles are, from 0 to 1. A title also matches the other without its subtitle,
+/// slightly lower, so `"Borat: Cultural Learnings of America..."` matches `"Borat"`.
+pub fn similarity(a: &str, b: &str) -> f64 {
+    let mut best = dice(&tokens(a), &tokens(b));
+
+    if let Some(main) = main_title(a) {
+        best = best.max(SUBTITLE_FACTOR * dice(&tokens(main), &tokens(b)));
+    }
+    if let Some(main) = main_title(b) {
+        best = best.max(SUBTITLE_FACTOR * dice(&tokens(a), &tokens(main)));
+    }
+
+    best
+}
+
+pub fn year_similarity(a: Option<u32>, b: Option<u32>) -> f64 {
+    match (a, b) {
+        (Some(a), Some(b)) => 0.5f64.powi((a as i64 - b as i64).abs().min(32) as i32),
+        _ => UNKNOWN_YEAR,
+    }
+}
+
+pub fn score(title: &str, year: Option<u32>, other: &str, other_year: Option<u32>) -> Score {
+    let title = similarity(title, other);
+    let year = year_similarity(year, other_year);
+
+    Score {
+        title,
+        year,
+        total: TITLE_WEIGHT * title + YEAR_WEIGHT * year,
+    }
+}
+
+/// Scores each `(item, title, year)` against the title and year searched for, best first.
+pub fn rank<T, I>(title: &str, year: Option<u32>, candidates: I) -> Vec<Ranked<T>>
+where
+    I: IntoIterator<Item = (T, String, Option<u32>)>,
+{
+    let mut ranked: Vec<_> = candidates.into_iter()
+        .map(|(item, other, other_year)| Ranked {
+            score: score(title, year, &other, other_year),
+            item,
+            title: other,
+            year: other_year,
+        })
+        .collect();
+
+    ranked.sort_by(|a, b| b.score.total.partial_cmp(&a.score.total).unwrap());
+    ranked
+}
+
+/// Value of a roman numeral from II to XXXIX. Single letters are left alone, as they are more
+/// often words or initials.
+fn roman(word: &str) -> Option<u32> {
+    if word.len() < 2 || !word.chars().all(|c| matches!(c, 'i' | 'v' | 'x')) {
+        return None;
+    }
+
+    let value = |c| match c {
+        'i' => 1,
+        'v' => 5,
+        _ => 10,
+    };
+
+    let chars: Vec<_> = word.chars().map(value).collect();
+    let mut n = 0;
+    for (i, v) in chars.iter().enumerate() {
+        if chars.get(i + 1).is_some_and(|next| next > v) {
+            n -= v;
+        } else {
+            n += v;
+        }
+    }
+
+    // Rejects malformed numerals such as "iiv" by writing the value back.
+    if n > 0 && to_roman(n as u32) == word {
+        Some(n as u32)
+    } else {
+        None
+    }
+}
+
+fn to_roman(mut n: u32) -> String {
+    let mut text = String::new();
+    for (value, numeral) in [(10, "x"), (9, "ix"), (5, "v"), (4, "iv"), (1, "i")] {
+        while n >= value {
+            text.push_str(numeral);
+            n -= value;
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn normalize() {
+        let cases = [
+            ("Amélie", "amelie"),
+            ("Léon: The Professional", "leon the professional"),
+            ("Mr. & Mrs. Smith", "mr and mrs smith"),
+            ("Ocean's Eleven", "oceans eleven"),
+            ("Star Wars: Episode II - Attack of the Clones", "star wars episode 2 attack of the clones"),
+            ("Rocky XIV", "rocky 14"),
+            ("Jim Jefferies: I Swear to God", "jim jefferies i swear to god"),
+            ("Malcolm X", "malcolm x"),
+            ("Mix", "mix"),
+        ];
+
+        for (title, normalized) in cases {
+            assert_eq!(super::normalize(title), normalized, "{}", title);
+        }
+    }
+
+    /// MovieLens titles and years, and the IMDb titles they should match.
+    #[test]
+    fn matches() {
+        let cases = [
+            ("Lord of the Rings: The Fellowship of the Ring, The", 2001, "The Lord of the Rings: The Fellowship of the Ring", 2001),
+            ("Godfather: Part II, The", 1974, "The Godfather Part II", 1974),
+            ("Amelie", 2001, "Amélie", 2001),
+            ("Léon: The Professional", 1994, "Leon: The Professional", 1994),
+            ("Mr. & Mrs. Smith", 2005, "Mr. and Mrs. Smith", 2005),
+            ("Star Wars: Episode II - Attack of the Clones", 2002, "Star Wars: Episode 2 - Attack of the Clones", 2002),
+            ("Borat: Cultural Learnings of America for Make Benefit Glorious Nation of Kazakhstan", 2006, "Borat", 2006),
+            ("Waking the Dead", 2000, "Waking the Dead", 1999),
+            ("Atlantis: Milo's Return", 2003, "Atlantis: Milo's Return", 2003),
+            ("Untergang, Der", 2004, "Untergang", 2004),
+            ("Auberge espagnole, L'", 2002, "L'auberge espagnole", 2002),
+        ];
+
+        for (title, year, imdb_title, imdb_year) in cases {
+            let score = super::score(title, Some(year), imdb_title, Some(imdb_year));
+            assert!(score.is_accepted(), "{} / {}: {:?}", title, imdb_title, score);
+        }
+    }
+
+    #[test]
+    fn mismatches() {
+        let cases = [
+            ("Northern Lights", 2009, "Southern Comfort", 2009),
+            ("Atlantis: Milo's Return", 2003, "Atlantis: The Lost Empire", 2001),
+            ("Dream", 2008, "Dreamgirls", 2006),
+            ("Die Hard", 1988, "Hard Target", 1988),
+            ("I Am Legend", 2007, "Legend", 2007),
+            ("Waking the Dead", 2000, "Waking the Dead", 1980),
+        ];
+
+        for (title, year, imdb_title, imdb_year) in cases {
+            let score = super::score(title, Some(year), imdb_title, Some(imdb_year));
+            assert!(!score.is_accepted(), "{} / {}: {:?}", title, imdb_title, score);
+        }
+    }
+
+    #[test]
+    fn rank() {
+        let candidates = vec![
+            (1, "Wake of Death".to_owned(), Some(2000)),
+            (2, "Waking the Dead".to_owned(), Some(1999)),
+            (3, "Waking the Dead".to_owned(), Some(2000)),
+            (4, "Waking the Dead".to_owned(), None),
+        ];
+
+        let ranked = super::rank("Waking the Dead", Some(2000), candidates);
+        let ids: Vec<_> = ranked.iter().map(|r| r.item).collect();
+        assert_eq!(ids, [3, 2, 4, 1]);
+        assert_eq!(ranked[0].score.total, 1.0);
+    }
+}
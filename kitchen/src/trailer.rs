@@ -0,0 +1,468 @@
+//! Finds, downloads, validates and processes the trailer of each movie into a spectrogram.
+
+use crate::error::*;
+use crate::external::{self, Preprocessing, VideoInfo};
+use crate::fingerprint::{self, Fingerprint};
+use crate::layout::Layout;
+use crate::manifest::{Artifact, SpectrogramParams};
+use crate::movie::{Movie, Trailer, TrailerSource};
+use crate::store::AudioStore;
+use crate::validation::{self, Candidate};
+
+use std::fs::{self, File, OpenOptions};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// How many search results are ranked when the MovieLens video is missing or rejected.
+pub const MAX_SEARCH_RESULTS: usize = 10;
+/// Longest audio accepted, in milliseconds.
+pub const MAX_AUDIO_LENGTH: u64 = 10 * 60 * 1000;
+
+/// How many threads work on each side of the download queue.
+#[derive(Debug, Clone, Copy)]
+pub struct Jobs {
+    /// Threads downloading and validating trailers. Network-bound, also limited by `throttle`.
+    pub downloads: usize,
+    /// Threads fingerprinting downloaded audio and making spectrograms. CPU-bound.
+    pub processing: usize,
+    /// Downloaded trailers allowed to wait for processing before downloads pause.
+    pub queue: usize,
+}
+
+impl Default for Jobs {
+    fn default() -> Self {
+        let processing = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+
+        Jobs {
+            downloads: 4,
+            processing,
+            queue: processing * 2,
+        }
+    }
+}
+
+/// Downloads, validates and processes trailers, remembering the audio of already accepted ones to
+/// reject the same video showing up for another movie.
+pub struct Fetcher {
+    layout: Layout,
+    params: SpectrogramParams,
+    store: Option<AudioStore>,
+    known: Mutex<Vec<(usize, String, Fingerprint)>>,
+    rejected: Mutex<csv::Writer<File>>,
+}
+
+impl Fetcher {
+    /// `movies` are used to collect the fingerprints of trailers from previous runs. Files are
+    /// written where `layout` says, with rejected candidates appended to its `rejected.csv`.
+    /// Processed audio is moved into `store`, or deleted when there is none.
+    pub fn new(
+        movies: &[Movie],
+        params: SpectrogramParams,
+        store: Option<AudioStore>,
+        layout: Layout,
+    ) -> Result<Fetcher> {
+        let mut known = vec![];
+        for (i, movie) in movies.iter().enumerate() {
+            let trailer = match &movie.trailer {
+                Some(t) => t,
+                None => continue,
+            };
+
+            if let (Some(video), Some(hex)) = (&trailer.video, &trailer.fingerprint) {
+                known.push((i, video.id.clone(), Fingerprint::from_hex(hex)?));
+            }
+        }
+
+        let rejected_path = layout.rejected_csv();
+        let is_new = !rejected_path.exists();
+        let file = OpenOptions::new().create(true).append(true).open(rejected_path)?;
+        let mut rejected = csv::WriterBuilder::new().has_headers(false).from_writer(file);
+        if is_new {
+            rejected.write_record(["Name", "Year", "Source", "VideoId", "Title", "Reason"])?;
+            rejected.flush()?;
+        }
+
+        Ok(Fetcher {
+            layout,
+            params,
+            store,
+            known: Mutex::new(known),
+            rejected: Mutex::new(rejected),
+        })
+    }
+
+    /// Downloads and processes the trailers of `movies`, given with their index, calling `done` as
+    /// each one finishes. Downloads are handed to the processing threads through a bounded queue.
+    /// Trailers found to duplicate another movie's are handed back to the download threads, which
+    /// try the next candidate.
+    pub fn run<F>(&self, movies: &[(usize, &Movie)], jobs: Jobs, done: F)
+    where
+        F: Fn(usize, &Movie, Result<(Trailer, Artifact)>) + Sync,
+    {
+        let next = AtomicUsize::new(0);
+        let remaining = AtomicUsize::new(movies.len());
+        let finish = |index, movie, result| {
+            done(index, movie, result);
+            remaining.fetch_sub(1, Ordering::SeqCst);
+        };
+
+        let (sender, receiver) = mpsc::sync_channel::<(usize, &Movie, Trailer, Vec<String>)>(jobs.queue);
+        let receiver = Mutex::new(receiver);
+        let (retry_sender, retry_receiver) = mpsc::channel::<(usize, &Movie, Vec<String>)>();
+        let retry_receiver = Mutex::new(retry_receiver);
+
+        thread::scope(|s| {
+            for _ in 0..jobs.downloads.max(1) {
+                let sender = sender.clone();
+                let (next, remaining, finish, retry_receiver) = (&next, &remaining, &finish, &retry_receiver);
+
+                s.spawn(move || loop {
+                    let retry = retry_receiver.lock().unwrap().try_recv().ok();
+                    let (index, movie, excluded) = match retry {
+                        Some(retry) => retry,
+                        None => match movies.get(next.fetch_add(1, Ordering::SeqCst)) {
+                            Some((index, movie)) => (*index, *movie, vec![]),
+                            // Trailers still being processed may come back to be retried. The
+                            // timeout only lets the thread see when the last one has finished.
+                            None if remaining.load(Ordering::SeqCst) > 0 => {
+                                let wait = Duration::from_millis(100);
+                                match retry_receiver.lock().unwrap().recv_timeout(wait) {
+                                    Ok(retry) => retry,
+                                    Err(_) => continue,
+                                }
+                            },
+                            None => break,
+                        },
+                    };
+
+                    match catch_panic(|| self.download(movie, &excluded)) {
+                        Ok(trailer) => if sender.send((index, movie, trailer, excluded)).is_err() {
+                            finish(index, movie, Err("processing stopped".into()));
+                        },
+                        Err(err) => finish(index, movie, Err(err)),
+                    }
+                });
+            }
+            drop(sender);
+
+            for _ in 0..jobs.processing.max(1) {
+                let retry_sender = retry_sender.clone();
+                let (receiver, finish) = (&receiver, &finish);
+
+                s.spawn(move || loop {
+                    let job = receiver.lock().unwrap().recv();
+                    let (index, movie, trailer, mut excluded) = match job {
+                        Ok(job) => job,
+                        Err(_) => break,
+                    };
+
+                    let video = trailer.video.as_ref().map(|v| v.id.clone());
+                    match catch_panic(|| self.process(index, movie, trailer)) {
+                        Err(Error::Rejected(reason)) => {
+                            excluded.extend(video);
+                            if retry_sender.send((index, movie, excluded)).is_err() {
+                                finish(index, movie, Err(Error::Rejected(reason)));
+                            }
+                        },
+                        result => finish(index, movie, result),
+                    }
+                });
+            }
+            drop(retry_sender);
+        });
+    }
+
+    /// Tries the MovieLens video first, then the ranked search results in order, until one is
+    /// accepted, skipping the `excluded` videos. The audio of the accepted trailer is left in the
+    /// download directory for `process`.
+    pub fn download(&self, movie: &Movie, excluded: &[String]) -> Result<Trailer> {
+        let path = self.layout.download(&movie.youtube_id);
+
+        if !excluded.contains(&movie.youtube_id) {
+            match self.try_download(movie, &movie.youtube_id, TrailerSource::Id, &path) {
+                Err(Error::Rejected(_)) => {},
+                result => return result,
+            }
+        }
+
+        let query = if let Some(year) = movie.year {
+            format!("{} {} movie trailer", movie.name, year)
+        } else {
+            format!("{} movie trailer", movie.name)
+        };
+
+        let candidates = validation::rank(movie, external::search_youtube(&query, MAX_SEARCH_RESULTS)?);
+
+        for (rank, candidate) in candidates.iter().enumerate() {
+            if excluded.contains(&candidate.video.id) {
+                continue;
+            }
+
+            let source = TrailerSource::Search {
+                query: query.clone(),
+                rank: rank + 1,
+            };
+
+            if !candidate.validation.is_accepted() {
+                self.reject(movie, &source, Some(&candidate.video), &describe(&candidate.validation))?;
+                continue;
+            }
+
+            match self.try_download(movie, &candidate.video.id, source, &path) {
+                Err(Error::Rejected(_)) => {},
+                Ok(trailer) => return Ok(Trailer {
+                    candidates,
+                    ..trailer
+                }),
+                result => return result,
+            }
+        }
+
+        Err("no acceptable trailer found")?
+    }
+
+    /// Downloads and checks one video. Rejections are logged and returned as `Error::Rejected`.
+    fn try_download(&self, movie: &Movie, id: &str, source: TrailerSource, path: &Path) -> Result<Trailer> {
+        let video = match external::download_youtube_m4a_by_id(id, path, self.layout.logs(id)) {
+            Ok(video) => video,
+            Err(err) => {
+                let reason = format!("download failed: {}", err);
+                self.reject(movie, &source, None, &reason)?;
+                return Err(Error::Rejected(reason));
+            },
+        };
+
+        match self.check(movie, &video, &source, path) {
+            Ok(trailer) => Ok(Trailer {
+                source,
+                ..trailer
+            }),
+            Err(err) => {
+                if let Error::Rejected(reason) = &err {
+                    self.reject(movie, &source, Some(&video), reason)?;
+                }
+
+                fs::remove_file(path)?;
+                Err(err)
+            },
+        }
+    }
+
+    /// Fingerprints a downloaded trailer, rejecting it if another movie already has the same
+    /// audio, then makes its spectrogram and stores or removes the audio. The fingerprint is
+    /// dropped again if this fails, so it can't reject other movies.
+    pub fn process(&self, index: usize, movie: &Movie, trailer: Trailer) -> Result<(Trailer, Artifact)> {
+        let path = self.layout.download(&movie.youtube_id);
+        let video = trailer.video.clone().ok_or("trailer without a video")?;
+
+        let fingerprint = match self.fingerprint(index, &video, &path) {
+            Ok(fingerprint) => fingerprint,
+            Err(err) => {
+                if let Error::Rejected(reason) = &err {
+                    self.reject(movie, &trailer.source, Some(&video), reason)?;
+                }
+                if path.exists() {
+                    fs::remove_file(&path)?;
+                }
+                return Err(err);
+            },
+        };
+
+        let result = self.make_artifact(movie, Trailer {
+            fingerprint: Some(fingerprint.to_hex()),
+            ..trailer
+        });
+
+        if result.is_err() {
+            self.known.lock().unwrap().retain(|(i, v, _)| (*i, v) != (index, &video.id));
+        }
+
+        result
+    }
+
+    /// Registers the fingerprint of the audio at `path`, or rejects it as a duplicate.
+    fn fingerprint(&self, index: usize, video: &VideoInfo, path: &Path) -> Result<Fingerprint> {
+        let fingerprint = Fingerprint::from_samples(&external::decode_pcm(path, fingerprint::SAMPLE_RATE)?);
+
+        let mut known = self.known.lock().unwrap();
+        let duplicate = known.iter()
+            .filter(|(i, _, _)| *i != index)
+            .find(|(_, _, f)| f.similarity(&fingerprint) > fingerprint::DUPLICATE_SIMILARITY);
+
+        if let Some((_, id, _)) = duplicate {
+            return Err(Error::Rejected(format!("same audio as video {}", id)));
+        }
+
+        known.push((index, video.id.clone(), fingerprint.clone()));
+
+        Ok(fingerprint)
+    }
+
+    fn make_artifact(&self, movie: &Movie, trailer: Trailer) -> Result<(Trailer, Artifact)> {
+        let youtube_id = trailer.video.as_ref().map(|v| v.id.clone()).ok_or("trailer without a video")?;
+        let path = &self.layout.download(&movie.youtube_id);
+        let output = self.layout.spectrogram(&movie.youtube_id);
+        let audio_md5 = format!("{:x}", md5::compute(fs::read(path)?));
+        let result = process_audio(path, self.layout.resolve(&output), &self.params);
+
+        // The spectrogram is what matters; failing to keep the audio only costs a later download.
+        let audio = match (&self.store, &trailer.video) {
+            (Some(store), Some(video)) if result.is_ok() => match store.put(&video.id, path) {
+                Ok(audio) => Some(audio),
+                Err(err) => {
+                    eprintln!("Failed to store the audio of '{}': {}", movie.name, err);
+                    if path.exists() {
+                        fs::remove_file(path)?;
+                    }
+                    None
+                },
+            },
+            _ => {
+                fs::remove_file(path)?;
+                None
+            },
+        };
+
+        let artifact = Artifact {
+            duration_ms: trailer.media.as_ref().map(|m| m.duration_ms).unwrap_or_default(),
+            params: self.params.clone(),
+            preprocessing: result?,
+            files: vec![output],
+            ..Artifact::new(movie, &youtube_id, trailer.source.clone(), audio_md5)
+        };
+
+        Ok((Trailer { audio, ..trailer }, artifact))
+    }
+
+    /// Validates a downloaded video, the MovieLens one more leniently. The returned trailer has
+    /// everything but its source and fingerprint filled in, with the video as its only candidate.
+    fn check(&self, movie: &Movie, video: &VideoInfo, source: &TrailerSource, path: &Path) -> Result<Trailer> {
+        let validation = match source {
+            TrailerSource::Id => validation::validate_linked(movie, video),
+            TrailerSource::Search { .. } => validation::validate(movie, video),
+        };
+        if !validation.is_accepted() {
+            return Err(Error::Rejected(describe(&validation)));
+        }
+
+        let media = external::probe(path)?;
+        if media.duration_ms > MAX_AUDIO_LENGTH {
+            return Err(Error::Rejected("length > 10 minutes".to_owned()));
+        }
+
+        Ok(Trailer {
+            video: Some(video.clone()),
+            validation: Some(validation.clone()),
+            candidates: vec![Candidate {
+                video: video.clone(),
+                validation,
+            }],
+            media: Some(media),
+            ..Default::default()
+        })
+    }
+
+    fn reject(&self, movie: &Movie, source: &TrailerSource, video: Option<&VideoInfo>, reason: &str) -> Result<()> {
+        eprintln!("Rejected trailer for '{}' ({:?}) from {}: {}", movie.name, movie.year, source, reason);
+
+        let mut rejected = self.rejected.lock().unwrap();
+        rejected.write_record([
+            &movie.name,
+            &movie.year.map(|y| y.to_string()).unwrap_or_default(),
+            &source.to_string(),
+            video.map(|v| &*v.id).unwrap_or(""),
+            video.map(|v| &*v.title).unwrap_or(""),
+            reason,
+        ])?;
+        rejected.flush()?;
+
+        Ok(())
+    }
+}
+
+/// Runs `f`, turning a panic into an error so the movie still counts as finished in `Fetcher::run`.
+fn catch_panic<T, F: FnOnce() -> Result<T>>(f: F) -> Result<T> {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+        let message = payload.downcast_ref::<&str>().map(|m| m.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+        Err(Error::Generic(format!("panicked: {}", message)))
+    })
+}
+
+fn describe(validation: &validation::Validation) -> String {
+    format!("score {}: {}", validation.score, validation.reasons.join(", "))
+}
+
+/// Runs the optional preprocessing and writes the spectrogram to `output`. Fails if this build
+/// renders spectrograms differently than `params` say.
+pub fn process_audio<T: AsRef<Path>>(path: &Path, output: T, params: &SpectrogramParams) -> Result<Option<Preprocessing>> {
+    params.check_renderer()?;
+
+    if let Some(dir) = output.as_ref().parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let audio_len = external::get_audio_length(path)?;
+    let mut len = audio_len;
+    let mut preprocessing = None;
+
+    if params.preprocess.is_enabled() {
+        let analyzed = external::analyze_audio(path, audio_len, &params.preprocess)?;
+        len = analyzed.length_ms(audio_len);
+        preprocessing = Some(analyzed);
+    }
+
+    let width = (len / params.ms_per_column) as usize;
+    let size = (width, params.height);
+    external::make_spectrogram(path, output, size, audio_len, preprocessing.as_ref())?;
+
+    Ok(preprocessing)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Fetcher;
+    use crate::external::{PreprocessOptions, VideoInfo};
+    use crate::fingerprint::Fingerprint;
+    use crate::layout::Layout;
+    use crate::manifest::SpectrogramParams;
+    use crate::movie::{Movie, Trailer};
+
+    #[test]
+    fn failed_process_keeps_no_fingerprint() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let layout = Layout::new(tempdir.path());
+        layout.create_dirs().unwrap();
+
+        let params = SpectrogramParams::new(PreprocessOptions::default());
+        let fetcher = Fetcher::new(&[], params, None, layout).unwrap();
+        let fingerprint = Fingerprint::from_samples(&[0; 4096]);
+        fetcher.known.lock().unwrap().push((1, "other".to_owned(), fingerprint));
+
+        // Nothing was downloaded, so processing fails.
+        let movie = Movie { youtube_id: "m".to_owned(), ..Default::default() };
+        let trailer = Trailer {
+            video: Some(VideoInfo { id: "v".to_owned(), ..Default::default() }),
+            ..Default::default()
+        };
+        assert!(fetcher.process(0, &movie, trailer).is_err());
+
+        let known = fetcher.known.lock().unwrap();
+        assert_eq!(known.iter().map(|(i, id, _)| (*i, &**id)).collect::<Vec<_>>(), [(1, "other")]);
+
+        tempdir.close().unwrap();
+    }
+
+    #[test]
+    fn catch_panic() {
+        assert_eq!(super::catch_panic(|| Ok(1)).unwrap(), 1);
+
+        let err = super::catch_panic::<(), _>(|| panic!("ffmpeg output {}", 42)).unwrap_err();
+        assert_eq!(err.to_string(), "panicked: ffmpeg output 42");
+    }
+}
@@ -0,0 +1,257 @@
+//! Scores YouTube videos on how likely they are the trailer of a movie.
+
+use crate::external::VideoInfo;
+use crate::movie::Movie;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Serialize, Deserialize};
+
+/// Videos scoring below this are not accepted as the trailer of the movie.
+pub const MIN_SCORE: f64 = 1.5;
+
+const NON_TRAILER_WORDS: &[&str] = &[
+    "review", "reaction", "fan made", "fanmade", "fan-made", "fan edit", "lyrics", "song",
+    "soundtrack", "ost", "full movie", "interview", "behind the scenes", "clip", "parody",
+    "explained", "music video", "cover", "honest trailer", "recap",
+];
+
+/// Channels of studios and trailer aggregators that publish official trailers.
+const OFFICIAL_CHANNELS: &[&str] = &[
+    "20th century studios", "a24", "focus features", "ifc films", "lionsgate movies",
+    "magnolia pictures & magnolia selects", "movieclips trailers", "neon", "netflix",
+    "paramount pictures", "rotten tomatoes trailers", "searchlight pictures",
+    "sony pictures classics", "sony pictures entertainment", "universal pictures",
+    "walt disney studios", "warner bros. pictures", "filmselect trailer",
+];
+
+lazy_static! {
+    static ref YEAR_REGEX: Regex = Regex::new(r"\b(19|20)\d{2}\b").unwrap();
+}
+
+/// A search result together with how it scored.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Candidate {
+    pub video: VideoInfo,
+    pub validation: Validation,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Validation {
+    pub score: f64,
+    pub reasons: Vec<String>,
+}
+
+impl Validation {
+    pub fn is_accepted(&self) -> bool {
+        self.score >= MIN_SCORE
+    }
+
+    fn add(&mut self, score: f64, reason: &str) {
+        self.score += score;
+        self.reasons.push(format!("{:+} {}", score, reason));
+    }
+}
+
+/// Scores how likely the video is to be the trailer of the movie, from its metadata only.
+pub fn validate(movie: &Movie, video: &VideoInfo) -> Validation {
+    score(movie, video, false)
+}
+
+/// Scores the video MovieLens links to the movie. The link already vouches for it, so a title
+/// that doesn't match the movie name costs less, and any duration between 30 seconds and 10
+/// minutes is fine.
+pub fn validate_linked(movie: &Movie, video: &VideoInfo) -> Validation {
+    score(movie, video, true)
+}
+
+fn score(movie: &Movie, video: &VideoInfo, linked: bool) -> Validation {
+    let mut result = Validation::default();
+    let title = video.title.to_lowercase();
+
+    if contains_word(&title, "trailer") {
+        result.add(2.0, "title mentions trailer");
+    } else if contains_word(&title, "teaser") {
+        result.add(1.0, "title mentions teaser");
+    }
+
+    if let Some(word) = NON_TRAILER_WORDS.iter().find(|w| contains_word(&title, w)) {
+        result.add(-3.0, &format!("title mentions '{}'", word));
+    }
+
+    let name_words = words(&movie.name);
+    if !name_words.is_empty() {
+        let title_words = words(&title);
+        let found = name_words.iter().filter(|w| title_words.contains(w)).count();
+        let ratio = found as f64 / name_words.len() as f64;
+
+        if ratio < 0.5 {
+            result.add(if linked { -1.0 } else { -4.0 }, "title does not match the movie name");
+        } else {
+            result.add(ratio, "title matches the movie name");
+        }
+    }
+
+    if let Some(year) = movie.year {
+        let mentioned: Vec<u32> = YEAR_REGEX.find_iter(&title)
+            .filter_map(|m| m.as_str().parse().ok())
+            .collect();
+
+        if mentioned.contains(&year) {
+            result.add(0.5, "title mentions the release year");
+        } else if mentioned.iter().any(|y| (*y as i64 - year as i64).abs() > 1) {
+            result.add(-1.0, "title mentions another year");
+        }
+
+        let upload_year: Option<u32> = video.upload_date.as_ref()
+            .and_then(|d| d.get(..4))
+            .and_then(|y| y.parse().ok());
+
+        if upload_year.map(|y| y + 3 < year) == Some(true) {
+            result.add(-1.0, "uploaded long before the release");
+        }
+    }
+
+    if let Some(channel) = &video.channel {
+        let channel = channel.to_lowercase();
+
+        if OFFICIAL_CHANNELS.contains(&channel.trim()) {
+            result.add(2.0, "official channel");
+        } else if contains_word(&channel, "trailers") || contains_word(&channel, "trailer") {
+            result.add(1.0, "channel publishes trailers");
+        } else if contains_word(&channel, "review") || contains_word(&channel, "reviews") {
+            result.add(-1.0, "channel publishes reviews");
+        }
+    }
+
+    match video.duration {
+        Some(d) if d < 30.0 => result.add(-2.0, "shorter than 30 seconds"),
+        Some(d) if d > 600.0 => result.add(-3.0, "longer than 10 minutes"),
+        _ if linked => result.add(1.0, "linked from MovieLens"),
+        Some(d) if (60.0..=240.0).contains(&d) => result.add(1.0, "trailer-like duration"),
+        _ => {},
+    }
+
+    result
+}
+
+/// Scores every video and sorts them from the most to the least likely trailer.
+pub fn rank(movie: &Movie, videos: Vec<VideoInfo>) -> Vec<Candidate> {
+    let mut candidates: Vec<_> = videos.into_iter()
+        .map(|video| Candidate {
+            validation: validate(movie, &video),
+            video,
+        })
+        .collect();
+
+    candidates.sort_by(|a, b| b.validation.score.partial_cmp(&a.validation.score).unwrap());
+    candidates
+}
+
+fn words(text: &str) -> Vec<String> {
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(|w| w.to_owned())
+        .collect()
+}
+
+fn contains_word(text: &str, phrase: &str) -> bool {
+    let text = format!(" {} ", words(text).join(" "));
+    let phrase = format!(" {} ", words(phrase).join(" "));
+    text.contains(&phrase)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::external::VideoInfo;
+    use crate::movie::Movie;
+
+    fn video(title: &str, channel: &str, duration: f64) -> VideoInfo {
+        VideoInfo {
+            title: title.to_owned(),
+            channel: Some(channel.to_owned()),
+            duration: Some(duration),
+            upload_date: Some("20140801".to_owned()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn validate() {
+        let movie = Movie {
+            name: "Interstellar".to_owned(),
+            year: Some(2014),
+            ..Default::default()
+        };
+
+        let accepted = [
+            video("Interstellar - Official Trailer (2014)", "Warner Bros. Pictures", 150.0),
+            video("INTERSTELLAR Teaser", "Movieclips Trailers", 100.0),
+        ];
+        for v in &accepted {
+            assert!(super::validate(&movie, v).is_accepted(), "{}", v.title);
+        }
+
+        let rejected = [
+            video("Interstellar Movie Review", "Some Critic", 420.0),
+            video("Interstellar Main Theme - Hans Zimmer (Soundtrack)", "Music", 250.0),
+            video("Gravity Official Trailer", "Warner Bros. Pictures", 150.0),
+            video("Interstellar trailer", "Fan", 7200.0),
+        ];
+        for v in &rejected {
+            assert!(!super::validate(&movie, v).is_accepted(), "{}", v.title);
+        }
+    }
+
+    #[test]
+    fn validate_linked() {
+        let movie = Movie {
+            name: "Interstellar".to_owned(),
+            year: Some(2014),
+            ..Default::default()
+        };
+
+        let accepted = [
+            video("Official Trailer", "Warner Bros. Pictures", 150.0),
+            video("Interstellar", "Someone", 300.0),
+        ];
+        for v in &accepted {
+            assert!(!super::validate(&movie, v).is_accepted(), "{}", v.title);
+            assert!(super::validate_linked(&movie, v).is_accepted(), "{}", v.title);
+        }
+
+        let rejected = [
+            video("Interstellar trailer", "Someone", 20.0),
+            video("Interstellar Movie Review", "Some Critic", 420.0),
+            video("Interstellar trailer", "Fan", 7200.0),
+        ];
+        for v in &rejected {
+            assert!(!super::validate_linked(&movie, v).is_accepted(), "{}", v.title);
+        }
+    }
+
+    #[test]
+    fn rank() {
+        let movie = Movie {
+            name: "Interstellar".to_owned(),
+            year: Some(2014),
+            ..Default::default()
+        };
+
+        let ranked = super::rank(&movie, vec![
+            video("Interstellar Movie Review", "Some Critic", 420.0),
+            video("Interstellar trailer", "Someone", 150.0),
+            video("Interstellar - Official Trailer", "Warner Bros. Pictures", 150.0),
+            video("Interstellar - Full Trailer", "Someone", 500.0),
+        ]);
+
+        let titles: Vec<_> = ranked.iter().map(|c| &*c.video.title).collect();
+        assert_eq!(titles, vec![
+            "Interstellar - Official Trailer",
+            "Interstellar trailer",
+            "Interstellar - Full Trailer",
+            "Interstellar Movie Review",
+        ]);
+    }
+}